@@ -2,11 +2,96 @@ use marjapussi::game::Game;
 // use marjapussi::game::gameevent::GameEvent;
 use marjapussi::game::gameevent::GameAction;
 
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::io;
+
+use crate::cheater::BiddingReasoning;
+use crate::log::LogFilter;
+use crate::notation::position::Position;
+
 // pub trait MarjapussiAI {
 //     fn observe_event(&self, event: GameEvent);
 //     fn select_action(&self, legal_actions: Vec<GameAction>) -> GameAction;
 // }
 
+// why an agent could not choose an action
+#[derive(Debug)]
+pub enum AgentError {
+    // the game offers no action to the agent
+    NoLegalActions,
+    // the action the agent wanted to choose is not legal
+    IllegalChoice(String),
+    // the bidding history does not follow the conventions of the agent
+    UnknownBidding(String),
+    // the search can only start in the cardplay
+    NotInCardplay(String),
+    // the agent was asked to choose for another player
+    PlayerMismatch(String),
+    // the agent panicked, with the message of the panic
+    Panic(String),
+    // the agent could not read its input (e.g. a human player at the terminal)
+    Io(io::Error)
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AgentError::NoLegalActions => write!(f, "there are no legal actions"),
+            AgentError::IllegalChoice(action) => write!(f, "the chosen action {} is not legal", action),
+            AgentError::UnknownBidding(history) => write!(f, "don't know how to handle the bidding history {}", history),
+            AgentError::NotInCardplay(phase) => write!(f, "the search can not start in the phase {}", phase),
+            AgentError::PlayerMismatch(reason) => write!(f, "player mismatch: {}", reason),
+            AgentError::Panic(message) => write!(f, "the agent panicked: {}", message),
+            AgentError::Io(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl std::error::Error for AgentError {}
+
+impl From<io::Error> for AgentError {
+    fn from(error: io::Error) -> Self {
+        AgentError::Io(error)
+    }
+}
+
 pub trait MarjapussiCheater {
-    fn select_action(&mut self, gamestate: Game) -> GameAction;
+    fn select_action(&mut self, gamestate: Game) -> Result<GameAction, AgentError>;
+
+    // the game built from a position has no history, agents that need the score so far should override this
+    fn select_action_in_position(&mut self, position: &Position) -> Result<GameAction, AgentError> {
+        self.select_action(position.to_game())
+    }
+
+    // agents that log their decisions only keep the entries that pass the filter
+    fn set_log_filter(&mut self, _filter: LogFilter) {}
+
+    // agents that learn from the game see the actions of decisions they were not asked for, e.g. when a host searches itself
+    fn observe(&mut self, _game: &Game) {}
+
+    // agents that reason about their bids return the reasoning of their last bid, so it can be attached to the game record
+    fn take_bidding_reasoning(&mut self) -> Option<BiddingReasoning> {
+        None
+    }
+
+    // the internal state of the agent as JSON, so it can be inspected or saved in the middle of a game
+    fn state(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    // describes the agent for game records, so the players of a recorded game are known
+    fn config(&self) -> AgentConfig {
+        AgentConfig {
+            agent: String::from("unknown"),
+            search_depth: None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentConfig {
+    pub agent: String,
+    pub search_depth: Option<u32>
 }
\ No newline at end of file
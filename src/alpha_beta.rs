@@ -1,5 +1,10 @@
+pub mod trace;
+
 use std::cmp::max;
 use std::cmp::min;
+use std::time::Instant;
+
+use trace::{MoveLabel, SearchTracer};
 
 
 // this trait specifies the requirements for a node in the search tree
@@ -12,9 +17,117 @@ pub trait State<M> {
 }
 
 
+// how far a search may go, in depth and in time
+#[derive(Clone, Copy)]
+struct Limits {
+    max_depth: Option<u32>,
+    deadline: Option<Instant>
+}
+
+impl Limits {
+    fn passed_deadline(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+
+// a tracer together with the function that renders the moves for it
+struct TraceContext<'a, M> {
+    tracer: &'a mut SearchTracer,
+    label: fn(&M) -> String
+}
+
+impl<M> TraceContext<'_, M> {
+    fn enter_child(&mut self, parent: Option<usize>, depth: u32, next_move: &M, maximizing: bool, alpha: i32, beta: i32) -> Option<usize> {
+        // children of nodes that are not recorded are not recorded either
+        parent.and_then(|parent| {
+            self.tracer.enter(Some(parent), depth, Some((self.label)(next_move)), maximizing, alpha, beta)
+        })
+    }
+}
+
+
 // this function finds the best move to make in the current game state
 // a recursive implementation of alpha-beta tree search is used within this function
 pub fn alpha_beta_search<M, S>(start_state: S, max_depth: Option<u32>) -> (M, i32)
+where M: Clone, S: State<M> {
+    search_root(start_state, Limits { max_depth, deadline: None }, &mut None).expect("A search without a deadline always finishes")
+}
+
+
+// same as alpha_beta_search, but the search is given up when the deadline passes
+// returns None if the search did not finish before the deadline
+pub fn alpha_beta_search_until<M, S>(start_state: S, max_depth: Option<u32>, deadline: Instant) -> Option<(M, i32)>
+where M: Clone, S: State<M> {
+    search_root(start_state, Limits { max_depth, deadline: Some(deadline) }, &mut None)
+}
+
+
+// same as alpha_beta_search, but all visited nodes up to the depth of the tracer are recorded
+pub fn alpha_beta_search_traced<M, S>(start_state: S, max_depth: Option<u32>, tracer: &mut SearchTracer) -> (M, i32)
+where M: Clone + MoveLabel, S: State<M> {
+    search_root(start_state, Limits { max_depth, deadline: None }, &mut Some(TraceContext { tracer, label: M::label }))
+        .expect("A search without a deadline always finishes")
+}
+
+
+// the exact value of one move in the start state
+// alpha_beta_search only finds the exact value of the best move, the values of the other moves are cut off
+pub fn evaluate_move<M, S>(start_state: &S, next_move: &M, max_depth: Option<u32>) -> i32
+where S: State<M> {
+    assert!(start_state.is_maximizing(),
+            "Moves can only be evaluated at a state where the maximizing player is at play");
+    let next_state = start_state.apply_move(next_move);
+    recursive_minimax(&next_state, i32::MIN, i32::MAX, 0, Limits { max_depth, deadline: None }, &mut None, None)
+}
+
+
+// the moves the search expects after a move in the start state, as far as it looks ahead
+// at every following state, the best move of the side at turn is chosen
+pub fn principal_variation<M, S>(start_state: &S, next_move: &M, max_depth: Option<u32>) -> Vec<M>
+where M: Clone, S: State<M> {
+    variation_until(start_state, next_move, Limits { max_depth, deadline: None }).expect("A search without a deadline always finishes")
+}
+
+
+// same as principal_variation, but the search is given up when the deadline passes
+// returns None if the variation was not found before the deadline
+pub fn principal_variation_until<M, S>(start_state: &S, next_move: &M, max_depth: Option<u32>, deadline: Instant) -> Option<Vec<M>>
+where M: Clone, S: State<M> {
+    variation_until(start_state, next_move, Limits { max_depth, deadline: Some(deadline) })
+}
+
+
+fn variation_until<M, S>(start_state: &S, next_move: &M, limits: Limits) -> Option<Vec<M>>
+where M: Clone, S: State<M> {
+    let mut variation = vec![next_move.clone()];
+    let mut state = start_state.apply_move(next_move);
+    let mut depth = 0;
+    while Some(depth) != limits.max_depth && !state.is_leaf() {
+        // like at the root, only the best child needs an exact value, so the window is only narrowed on one side
+        let maximizing = state.is_maximizing();
+        let mut alpha = i32::MIN;
+        let mut beta = i32::MAX;
+        let mut best: Option<(M, S)> = None;
+        for child_move in state.legal_moves() {
+            let child = state.apply_move(&child_move);
+            let eval = recursive_minimax(&child, alpha, beta, depth + 1, limits, &mut None, None);
+            let better = if maximizing { eval > alpha } else { eval < beta };
+            if better || best.is_none() {
+                if maximizing { alpha = max(alpha, eval) } else { beta = min(beta, eval) }
+                best = Some((child_move, child));
+            }
+        }
+        let (best_move, best_child) = best.expect("Only leaves have no legal moves");
+        variation.push(best_move);
+        state = best_child;
+        depth += 1;
+    }
+    (!limits.passed_deadline()).then_some(variation)
+}
+
+
+fn search_root<M, S>(start_state: S, limits: Limits, trace: &mut Option<TraceContext<M>>) -> Option<(M, i32)>
 where M: Clone, S: State<M> {
 
     // the search has to start at a state where the maximizing player is at turn
@@ -33,52 +146,92 @@ where M: Clone, S: State<M> {
     let mut best_move = None;
     let mut max_eval: i32 = i32::MIN;
 
+    let root_id = trace.as_mut().and_then(|context| context.tracer.enter(None, 0, None, true, alpha, beta));
+
     // beta is (theoretically) infinite, so no beta cutoffs will happen here
     // we will simply choose the move with the highest evaluation
     for next_move in start_state.legal_moves().into_iter() {
         let next_state = start_state.apply_move(&next_move);
-        let eval = recursive_minimax(&next_state, alpha, beta, 0, max_depth);
+        let child_id = trace.as_mut().and_then(|context| {
+            context.enter_child(root_id, 1, &next_move, next_state.is_maximizing(), alpha, beta)
+        });
+        let eval = recursive_minimax(&next_state, alpha, beta, 0, limits, trace, child_id);
         if eval > max_eval {
             max_eval = eval;
             best_move = Some(next_move);
         }
         alpha = max(alpha, eval);
     }
-    (best_move.expect("There are no legal moves for the player"), alpha)
+
+    if let Some(context) = trace.as_mut() {
+        context.tracer.exit(root_id, alpha, false, 0);
+    }
+    if limits.passed_deadline() {
+        return None;
+    }
+    Some((best_move.expect("There are no legal moves for the player"), alpha))
 }
 
 
+
 // recursive implementation of alpha-beta search (called by alpha_beta_search)
-fn recursive_minimax<M, S>(start_node: &S, alpha: i32, beta: i32, depth: u32, max_depth: Option<u32>) -> i32
+// trace_id is the ID of this node in the tracer, if it is recorded
+// once the deadline has passed, every node returns at once, since the caller throws the result away
+fn recursive_minimax<M, S>(start_node: &S, alpha: i32, beta: i32, depth: u32, limits: Limits,
+                           trace: &mut Option<TraceContext<M>>, trace_id: Option<usize>) -> i32
 where S: State<M> {
-    if Some(depth) == max_depth || start_node.is_leaf() {
-        return start_node.evaluate();
+    if limits.passed_deadline() {
+        0
+    } else if Some(depth) == limits.max_depth || start_node.is_leaf() {
+        let eval = start_node.evaluate();
+        if let Some(context) = trace.as_mut() {
+            context.tracer.exit(trace_id, eval, true, 0);
+        }
+        eval
     } else if start_node.is_maximizing() {
         let mut alpha = alpha;
         let mut max_eval: i32 = i32::MIN;
-        for next_move in start_node.legal_moves() {
-            let next_state = start_node.apply_move(&next_move);
-            let eval = recursive_minimax(&next_state, alpha, beta, depth + 1, max_depth);
+        let legal_moves = start_node.legal_moves();
+        let mut pruned_children = 0;
+        for (index, next_move) in legal_moves.iter().enumerate() {
+            let next_state = start_node.apply_move(next_move);
+            let child_id = trace.as_mut().and_then(|context| {
+                context.enter_child(trace_id, depth + 2, next_move, next_state.is_maximizing(), alpha, beta)
+            });
+            let eval = recursive_minimax(&next_state, alpha, beta, depth + 1, limits, trace, child_id);
             max_eval = max(max_eval, eval);
             alpha = max(alpha, eval);
             if beta <= alpha {
+                pruned_children = legal_moves.len() - index - 1;
                 break;
             }
         }
-        return max_eval;
+        if let Some(context) = trace.as_mut() {
+            context.tracer.exit(trace_id, max_eval, false, pruned_children);
+        }
+        max_eval
     } else {
+        let mut beta = beta;
         let mut min_eval: i32 = i32::MAX;
-        for next_move in start_node.legal_moves() {
-            let mut beta = beta;
-            let next_state = start_node.apply_move(&next_move);
-            let eval = recursive_minimax(&next_state, alpha, beta, depth + 1, max_depth);
+        let legal_moves = start_node.legal_moves();
+        let mut pruned_children = 0;
+        for (index, next_move) in legal_moves.iter().enumerate() {
+            let next_state = start_node.apply_move(next_move);
+            let child_id = trace.as_mut().and_then(|context| {
+                context.enter_child(trace_id, depth + 2, next_move, next_state.is_maximizing(), alpha, beta)
+            });
+            let eval = recursive_minimax(&next_state, alpha, beta, depth + 1, limits, trace, child_id);
             min_eval = min(min_eval, eval);
             beta = min(beta, eval);
             if beta <= alpha {
+                pruned_children = legal_moves.len() - index - 1;
                 break;
             }
         }
-        return min_eval;
+        if let Some(context) = trace.as_mut() {
+            context.tracer.exit(trace_id, min_eval, false, pruned_children);
+        }
+        min_eval
     }
 }
 
@@ -89,6 +242,7 @@ mod tests {
      */
 
     use super::*;
+    use trace::MoveLabel;
 
     // every test state should have a left and a right child (except the leaf states, which have none)
     // therefore, the only allowed moves are "Left" and "Right"
@@ -117,12 +271,11 @@ mod tests {
             
             // find the depth and ID for the child node
             let new_depth: i32 = self.depth + 1;
-            let new_id: i32;
-            if *next_move == Move::Left {
-                new_id = self.id * 2;
+            let new_id: i32 = if *next_move == Move::Left {
+                self.id * 2
             } else {
-                new_id = self.id * 2 + 1;
-            }
+                self.id * 2 + 1
+            };
             
             println!("({}, {}) -> ({}, {})", self.depth, self.id, new_depth, new_id);
 
@@ -178,4 +331,266 @@ mod tests {
         let result = alpha_beta_search(start_state, None);
         assert!(result == (Move::Left, 3));
     }
+
+    impl MoveLabel for Move {
+        fn label(&self) -> String {
+            match self {
+                Move::Left => String::from("Left"),
+                Move::Right => String::from("Right")
+            }
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut tracer = SearchTracer::new(2);
+        let result = alpha_beta_search_traced(TestState { depth: 0, id: 0 }, None, &mut tracer);
+        assert!(result == (Move::Left, 3));
+
+        // the root and its children and grandchildren are recorded, but the right grandchild is pruned
+        let nodes = tracer.nodes();
+        let labels: Vec<(Option<usize>, Option<String>)> = nodes
+            .iter()
+            .map(|node| (node.parent, node.move_label.clone()))
+            .collect();
+        assert_eq!(labels, vec![
+            (None, None),
+            (Some(0), Some(String::from("Left"))),
+            (Some(1), Some(String::from("Left"))),
+            (Some(1), Some(String::from("Right"))),
+            (Some(0), Some(String::from("Right"))),
+            (Some(4), Some(String::from("Left")))
+        ]);
+        assert_eq!(nodes[0].value, Some(3));
+        assert_eq!(nodes[4].pruned_children, 1);
+        assert_eq!((nodes[4].alpha, nodes[4].beta), (3, i32::MAX));
+        assert!(nodes.iter().all(|node| node.value.is_some()));
+
+        let dot = tracer.to_dot();
+        assert!(dot.starts_with("digraph search_tree {"));
+        assert!(dot.contains("n0 -> n4 [label=\"Right\"];"));
+        assert!(dot.contains("cutoff: 1 pruned"));
+        let json: serde_json::Value = serde_json::from_str(&tracer.to_json()).unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 6);
+    }
+
+
+    /*
+    Property tests on randomly generated trees.
+    The values are compared to a plain minimax search and the number of evaluated leaves is compared to
+    the known numbers for perfectly and worst ordered trees.
+     */
+
+    use std::cell::Cell;
+    use std::time::Duration;
+    use crate::deal::SeededRng;
+
+    enum Tree {
+        Leaf(i32),
+        Node(Vec<Tree>)
+    }
+
+    // a node in a generated tree, counting the evaluated leaves
+    struct TreeState<'a> {
+        tree: &'a Tree,
+        depth: u32,
+        evaluated_leaves: &'a Cell<u64>
+    }
+
+    impl<'a> State<usize> for TreeState<'a> {
+        fn legal_moves(&self) -> Vec<usize> {
+            match self.tree {
+                Tree::Leaf(_) => panic!("Tried to generate legal moves for a leaf node"),
+                Tree::Node(children) => (0..children.len()).collect()
+            }
+        }
+
+        fn apply_move(&self, next_move: &usize) -> Self {
+            match self.tree {
+                Tree::Leaf(_) => panic!("Tried to generate child for a leaf node"),
+                Tree::Node(children) => TreeState {
+                    tree: &children[*next_move],
+                    depth: self.depth + 1,
+                    evaluated_leaves: self.evaluated_leaves
+                }
+            }
+        }
+
+        fn is_leaf(&self) -> bool {
+            matches!(self.tree, Tree::Leaf(_))
+        }
+
+        fn is_maximizing(&self) -> bool {
+            self.depth.is_multiple_of(2)
+        }
+
+        fn evaluate(&self) -> i32 {
+            match self.tree {
+                Tree::Leaf(value) => {
+                    self.evaluated_leaves.set(self.evaluated_leaves.get() + 1);
+                    *value
+                },
+                Tree::Node(_) => panic!("Evaluation called on a non-leaf node")
+            }
+        }
+    }
+
+    fn random_tree(rng: &mut SeededRng, depth: u32, max_depth: u32) -> Tree {
+        // the root is never a leaf, every other node below max_depth becomes a leaf with a small chance
+        if depth == max_depth || (depth > 0 && rng.below(6) == 0) {
+            Tree::Leaf(rng.below(21) as i32 - 10)
+        } else {
+            let branching = 1 + rng.below(4);
+            Tree::Node((0..branching).map(|_| random_tree(rng, depth + 1, max_depth)).collect())
+        }
+    }
+
+    fn uniform_tree(rng: &mut SeededRng, branching: u32, depth: u32, next_value: &mut i32) -> Tree {
+        // all leaf values are distinct, so the orderings are unambiguous
+        if depth == 0 {
+            *next_value += 1 + rng.below(3) as i32;
+            Tree::Leaf(*next_value)
+        } else {
+            Tree::Node((0..branching).map(|_| uniform_tree(rng, branching, depth - 1, next_value)).collect())
+        }
+    }
+
+    fn minimax(tree: &Tree, maximizing: bool) -> i32 {
+        match tree {
+            Tree::Leaf(value) => *value,
+            Tree::Node(children) => {
+                let values = children.iter().map(|child| minimax(child, !maximizing));
+                if maximizing {
+                    values.max().unwrap()
+                } else {
+                    values.min().unwrap()
+                }
+            }
+        }
+    }
+
+    fn order(tree: &mut Tree, maximizing: bool, best_first: bool) {
+        // sorts the children of every node by their minimax value
+        if let Tree::Node(children) = tree {
+            for child in children.iter_mut() {
+                order(child, !maximizing, best_first);
+            }
+            children.sort_by_key(|child| minimax(child, !maximizing));
+            if maximizing == best_first {
+                children.reverse();
+            }
+        }
+    }
+
+    fn search(tree: &Tree) -> (i32, u64) {
+        let evaluated_leaves = Cell::new(0);
+        let start_state = TreeState { tree, depth: 0, evaluated_leaves: &evaluated_leaves };
+        let (_, value) = alpha_beta_search(start_state, None);
+        (value, evaluated_leaves.get())
+    }
+
+    #[test]
+    fn test_random_trees_match_minimax() {
+        for seed in 0..500 {
+            let mut rng = SeededRng::new(seed);
+            let max_depth = 1 + rng.below(6) as u32;
+            let tree = random_tree(&mut rng, 0, max_depth);
+            assert_eq!(search(&tree).0, minimax(&tree, true), "wrong value for the tree with seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_chosen_move_has_best_value() {
+        for seed in 0..200 {
+            let mut rng = SeededRng::new(seed);
+            let tree = random_tree(&mut rng, 0, 4);
+            let evaluated_leaves = Cell::new(0);
+            let start_state = TreeState { tree: &tree, depth: 0, evaluated_leaves: &evaluated_leaves };
+            let (best_move, value) = alpha_beta_search(start_state, None);
+            if let Tree::Node(children) = &tree {
+                assert_eq!(minimax(&children[best_move], false), value, "wrong move for the tree with seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_move() {
+        for seed in 0..200 {
+            let mut rng = SeededRng::new(seed);
+            let tree = random_tree(&mut rng, 0, 4);
+            let evaluated_leaves = Cell::new(0);
+            let start_state = TreeState { tree: &tree, depth: 0, evaluated_leaves: &evaluated_leaves };
+            if let Tree::Node(children) = &tree {
+                for (next_move, child) in children.iter().enumerate() {
+                    assert_eq!(evaluate_move(&start_state, &next_move, None), minimax(child, false),
+                               "wrong value of move {} for the tree with seed {}", next_move, seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_principal_variation() {
+        // without a depth limit, the variation ends in a leaf with the minimax value of the tree
+        for seed in 0..200 {
+            let mut rng = SeededRng::new(seed);
+            let tree = random_tree(&mut rng, 0, 5);
+            let evaluated_leaves = Cell::new(0);
+            let start_state = TreeState { tree: &tree, depth: 0, evaluated_leaves: &evaluated_leaves };
+            let (best_move, value) = alpha_beta_search(TreeState { tree: &tree, depth: 0, evaluated_leaves: &evaluated_leaves }, None);
+            let variation = principal_variation(&start_state, &best_move, None);
+            let end = variation.iter().fold(&tree, |node, next_move| match node {
+                Tree::Node(children) => &children[*next_move],
+                Tree::Leaf(_) => panic!("the variation of the tree with seed {} continues after a leaf", seed)
+            });
+            assert!(matches!(end, Tree::Leaf(leaf) if *leaf == value), "wrong variation for the tree with seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let tree = random_tree(&mut SeededRng::new(3), 0, 6);
+        let evaluated_leaves = Cell::new(0);
+        let start_state = || TreeState { tree: &tree, depth: 0, evaluated_leaves: &evaluated_leaves };
+
+        // a search that finishes in time is the same as one without a deadline
+        let later = Instant::now() + Duration::from_secs(60);
+        let (best_move, value) = alpha_beta_search(start_state(), None);
+        assert_eq!(alpha_beta_search_until(start_state(), None, later), Some((best_move, value)));
+        assert_eq!(principal_variation_until(&start_state(), &best_move, None, later), Some(principal_variation(&start_state(), &best_move, None)));
+
+        // after the deadline, the search gives up without evaluating any leaves
+        evaluated_leaves.set(0);
+        assert_eq!(alpha_beta_search_until(start_state(), None, Instant::now()), None);
+        assert_eq!(principal_variation_until(&start_state(), &best_move, None, Instant::now()), None);
+        assert_eq!(evaluated_leaves.get(), 0);
+    }
+
+    #[test]
+    fn test_leaves_perfect_ordering() {
+        // Knuth and Moore: with the best move first, alpha-beta evaluates b^ceil(d/2) + b^floor(d/2) - 1 leaves
+        for branching in 2..=4 {
+            for depth in 1..=6 {
+                let mut tree = uniform_tree(&mut SeededRng::new(u64::from(branching * 10 + depth)), branching, depth, &mut 0);
+                order(&mut tree, true, true);
+                let expected_leaves = u64::from(branching.pow(depth.div_ceil(2)) + branching.pow(depth / 2) - 1);
+                assert_eq!(search(&tree), (minimax(&tree, true), expected_leaves),
+                           "branching {}, depth {}", branching, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn test_leaves_worst_ordering() {
+        // with the worst move first, no cutoff is possible and all b^d leaves are evaluated
+        for branching in 2..=4 {
+            for depth in 1..=6 {
+                let mut tree = uniform_tree(&mut SeededRng::new(u64::from(branching * 10 + depth)), branching, depth, &mut 0);
+                order(&mut tree, true, false);
+                let expected_leaves = u64::from(branching.pow(depth));
+                assert_eq!(search(&tree), (minimax(&tree, true), expected_leaves),
+                           "branching {}, depth {}", branching, depth);
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,150 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+
+// this trait specifies how a move is shown in an exported search tree
+pub trait MoveLabel {
+    fn label(&self) -> String;
+}
+
+
+// a node that was visited during a traced search
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceNode {
+    pub id: usize,
+    pub parent: Option<usize>,
+    pub depth: u32,
+    // the move that leads from the parent to this node (None for the root)
+    pub move_label: Option<String>,
+    pub maximizing: bool,
+    // the alpha-beta window the node was searched with
+    pub alpha: i32,
+    pub beta: i32,
+    pub value: Option<i32>,
+    // true if the node was evaluated instead of being expanded (leaf or depth limit)
+    pub evaluated: bool,
+    // the number of children that were skipped because of a cutoff
+    pub pruned_children: usize
+}
+
+
+// records the nodes of an alpha-beta search up to a given depth
+// deeper nodes are searched as usual, but not recorded
+#[derive(Debug, Serialize)]
+pub struct SearchTracer {
+    max_depth: u32,
+    nodes: Vec<TraceNode>
+}
+
+impl SearchTracer {
+    pub fn new(max_depth: u32) -> Self {
+        SearchTracer {
+            max_depth,
+            nodes: vec![]
+        }
+    }
+
+    #[cfg(test)]
+    pub fn nodes(&self) -> &[TraceNode] {
+        &self.nodes
+    }
+
+    pub fn enter(&mut self, parent: Option<usize>, depth: u32, move_label: Option<String>, maximizing: bool, alpha: i32, beta: i32) -> Option<usize> {
+        /*
+            Records a node before it is searched and returns its ID.
+            Returns None if the node is too deep to be recorded.
+         */
+
+        if depth > self.max_depth {
+            return None;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(TraceNode {
+            id,
+            parent,
+            depth,
+            move_label,
+            maximizing,
+            alpha,
+            beta,
+            value: None,
+            evaluated: false,
+            pruned_children: 0
+        });
+        Some(id)
+    }
+
+    pub fn exit(&mut self, id: Option<usize>, value: i32, evaluated: bool, pruned_children: usize) {
+        /*
+            Records the result of a node after it was searched.
+         */
+
+        if let Some(node) = id.and_then(|id| self.nodes.get_mut(id)) {
+            node.value = Some(value);
+            node.evaluated = evaluated;
+            node.pruned_children = pruned_children;
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        /*
+            Renders the recorded tree in the Graphviz DOT format.
+            Maximizing nodes are boxes, minimizing nodes are ellipses and nodes with a cutoff are red.
+         */
+
+        let mut dot = String::from("digraph search_tree {\n    node [fontname=\"monospace\"];\n");
+        for node in &self.nodes {
+            let mut label = format!(
+                "{}\\nα={} β={}\\nvalue={}",
+                if node.maximizing { "max" } else { "min" },
+                bound_to_string(node.alpha),
+                bound_to_string(node.beta),
+                node.value.map_or(String::from("?"), |value| value.to_string())
+            );
+            if node.pruned_children > 0 {
+                label += &format!("\\ncutoff: {} pruned", node.pruned_children);
+            }
+            dot += &format!(
+                "    n{} [label=\"{}\", shape={}{}];\n",
+                node.id,
+                label,
+                if node.maximizing { "box" } else { "ellipse" },
+                if node.pruned_children > 0 { ", color=red" } else { "" }
+            );
+            if let Some(parent) = node.parent {
+                dot += &format!(
+                    "    n{} -> n{} [label=\"{}\"];\n",
+                    parent,
+                    node.id,
+                    node.move_label.clone().unwrap_or_default().replace('"', "\\\"")
+                );
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("The search trace could not be serialized")
+    }
+
+    pub fn write_dot(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_dot())
+    }
+
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+
+fn bound_to_string(bound: i32) -> String {
+    match bound {
+        i32::MIN => String::from("-inf"),
+        i32::MAX => String::from("inf"),
+        _ => bound.to_string()
+    }
+}
@@ -0,0 +1,268 @@
+/*
+    Post-game analysis: replays a recorded game and compares every decision in the cardplay with the best action
+    of the full-information search. Every decision is judged by the points it loses compared to the best action.
+ */
+
+use marjapussi::game::gameevent::{ActionType, GameAction};
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::Game;
+
+use std::fmt;
+
+use crate::cheater::evaluate_decision;
+use crate::notation::game::{format_action, section};
+use crate::record::{GameRecord, RecordError};
+
+
+// decisions that lose at least this many points are blunders, smaller losses are inaccuracies
+const BLUNDER_LOSS: i32 = 40;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Judgement {
+    Best,
+    Inaccuracy,
+    Blunder
+}
+
+impl Judgement {
+    pub fn from_loss(loss: i32) -> Self {
+        if loss <= 0 {
+            Judgement::Best
+        } else if loss < BLUNDER_LOSS {
+            Judgement::Inaccuracy
+        } else {
+            Judgement::Blunder
+        }
+    }
+}
+
+impl fmt::Display for Judgement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // pad, so the judgement can be aligned in tables
+        f.pad(match self {
+            Judgement::Best => "best",
+            Judgement::Inaccuracy => "inaccuracy",
+            Judgement::Blunder => "blunder"
+        })
+    }
+}
+
+
+// an analyzed action of the record, the values are from the view of the acting player's party
+#[derive(Debug, Clone)]
+pub struct Decision {
+    pub index: usize,
+    pub action: GameAction,
+    pub value: i32,
+    pub best_action: GameAction,
+    pub best_value: i32
+}
+
+impl Decision {
+    pub fn loss(&self) -> i32 {
+        self.best_value - self.value
+    }
+
+    pub fn judgement(&self) -> Judgement {
+        Judgement::from_loss(self.loss())
+    }
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerAccuracy {
+    pub decisions: u32,
+    pub best: u32,
+    pub inaccuracies: u32,
+    pub blunders: u32,
+    pub total_loss: i32
+}
+
+impl PlayerAccuracy {
+    pub fn accuracy(&self) -> f64 {
+        // the share of best decisions, a player without decisions made no mistakes
+        if self.decisions == 0 {
+            100.0
+        } else {
+            100.0 * f64::from(self.best) / f64::from(self.decisions)
+        }
+    }
+
+    pub fn avg_loss(&self) -> f64 {
+        if self.decisions == 0 {
+            0.0
+        } else {
+            f64::from(self.total_loss) / f64::from(self.decisions)
+        }
+    }
+}
+
+
+pub struct GameAnalysis {
+    pub record: GameRecord,
+    pub max_depth: Option<u32>,
+    pub decisions: Vec<Decision>
+}
+
+impl GameAnalysis {
+    pub fn players(&self) -> [PlayerAccuracy; 4] {
+        let mut players: [PlayerAccuracy; 4] = Default::default();
+        for decision in &self.decisions {
+            let player = &mut players[decision.action.player.0 as usize];
+            player.decisions += 1;
+            player.total_loss += decision.loss();
+            match decision.judgement() {
+                Judgement::Best => player.best += 1,
+                Judgement::Inaccuracy => player.inaccuracies += 1,
+                Judgement::Blunder => player.blunders += 1
+            }
+        }
+        players
+    }
+
+    pub fn print_summary(&self) {
+        println!("\n ---------- Game analysis ----------");
+        println!("search depth: {}", self.max_depth.map_or(String::from("unlimited"), |depth| depth.to_string()));
+
+        // the annotated game, grouped into the sections of the text notation
+        let mut game = Game::new(self.record.game_name.clone(), self.record.player_names.clone(), Some(self.record.deal.clone()));
+        let mut current_section = None;
+        let mut decisions = self.decisions.iter().peekable();
+        for (index, action) in self.record.actions.iter().enumerate() {
+            let action_section = section(&game.state.phase, &game, &action.action_type);
+            if action_section.is_some() && action_section != current_section {
+                println!("{}:", action_section.clone().unwrap());
+                current_section = action_section;
+            }
+            let action_string = format!("{}:{}", action.player.0, format_action(&action.action_type));
+            match decisions.next_if(|decision| decision.index == index) {
+                Some(decision) if decision.judgement() == Judgement::Best => {
+                    println!("  {:<28} {:<11} {:>+5}", action_string, decision.judgement(), decision.value);
+                },
+                Some(decision) => println!(
+                    "  {:<28} {:<11} {:>+5}  (best {} {:+}, loss {})",
+                    action_string,
+                    decision.judgement(),
+                    decision.value,
+                    format_action(&decision.best_action.action_type),
+                    decision.best_value,
+                    decision.loss()
+                ),
+                None if current_section.is_some() => println!("  {}", action_string),
+                None => ()
+            }
+            game = game.apply_action(action.clone()).expect("Analyzed records contain only legal actions");
+        }
+
+        println!(
+            "\n{:<24} {:>10} {:>6} {:>13} {:>9} {:>9} {:>9}",
+            "player", "decisions", "best", "inaccuracies", "blunders", "avg loss", "accuracy"
+        );
+        for (place, player) in self.players().iter().enumerate() {
+            println!(
+                "{:<24} {:>10} {:>6} {:>13} {:>9} {:>9.1} {:>9}",
+                format!("{} ({})", self.record.player_names[place], place),
+                player.decisions,
+                player.best,
+                player.inaccuracies,
+                player.blunders,
+                player.avg_loss(),
+                format!("{:.1}%", player.accuracy())
+            );
+        }
+    }
+}
+
+
+pub fn analyze_game(record: &GameRecord, max_depth: Option<u32>) -> Result<GameAnalysis, RecordError> {
+    /*
+        Replays the record and searches every decision in the cardplay in which the player had a choice.
+     */
+
+    let mut game = Game::new(record.game_name.clone(), record.player_names.clone(), Some(record.deal.clone()));
+    let mut decisions = vec![];
+    for (index, action) in record.actions.iter().enumerate() {
+        if is_decision(&game, action) {
+            let (best_action, best_value, value) = evaluate_decision(game.clone(), action, max_depth)?;
+
+            // raises are not searched, so a raise can be better than the best action of the search
+            let (best_action, best_value) = if value > best_value {
+                (action.clone(), value)
+            } else {
+                (best_action, best_value)
+            };
+            decisions.push(Decision { index, action: action.clone(), value, best_action, best_value });
+        }
+        game = game
+            .apply_action(action.clone())
+            .map_err(|_| RecordError::IllegalAction { index, action: action.clone() })?;
+    }
+    if !game.ended() {
+        return Err(RecordError::Unfinished);
+    }
+
+    Ok(GameAnalysis {
+        record: record.clone(),
+        max_depth,
+        decisions
+    })
+}
+
+fn is_decision(game: &Game, action: &GameAction) -> bool {
+    // the search covers the cardplay including questions and answers, but not bidding, passing and undoing
+    let searchable = matches!(
+        game.state.phase,
+        GamePhase::Raising | GamePhase::StartTrick | GamePhase::Trick | GamePhase::AnsweringPair | GamePhase::AnsweringHalf(_)
+    );
+    let undo = matches!(action.action_type, ActionType::UndoRequest | ActionType::UndoAccept | ActionType::UndoDecline);
+    let choices = game.legal_actions()
+        .iter()
+        .filter(|action| action.action_type != ActionType::UndoRequest)
+        .count();
+    searchable && !undo && choices > 1
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::MarjapussiCheater;
+    use crate::cheater::CheaterV1;
+    use crate::deal::deal_from_seed;
+    use crate::log::LogFilter;
+    use crate::simulate_game::play_game;
+
+    #[test]
+    fn test_judgement() {
+        assert_eq!(Judgement::from_loss(0), Judgement::Best);
+        assert_eq!(Judgement::from_loss(5), Judgement::Inaccuracy);
+        assert_eq!(Judgement::from_loss(BLUNDER_LOSS - 1), Judgement::Inaccuracy);
+        assert_eq!(Judgement::from_loss(BLUNDER_LOSS), Judgement::Blunder);
+    }
+
+    #[test]
+    fn test_analyze_game() {
+        // players that search as deep as the analysis always find the best action
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4)
+            .map(|place| Box::new(CheaterV1::new(&player_names[place], place as u8, 2)) as Box<dyn MarjapussiCheater>)
+            .collect();
+        let record = play_game(&mut players, player_names, Some(deal_from_seed(8)), LogFilter::off(), &mut []).unwrap().record(Some(8));
+
+        let analysis = analyze_game(&record, Some(2)).unwrap();
+        assert!(!analysis.decisions.is_empty());
+        assert!(analysis.decisions.iter().all(|decision| decision.judgement() == Judgement::Best));
+        let players = analysis.players();
+        assert_eq!(players.iter().map(|player| player.decisions as usize).sum::<usize>(), analysis.decisions.len());
+        assert!(players.iter().all(|player| player.accuracy() == 100.0));
+
+        // a shallower analysis still gives every decision a value no better than the best one
+        let analysis = analyze_game(&record, Some(1)).unwrap();
+        assert!(analysis.decisions.iter().all(|decision| decision.loss() >= 0));
+
+        let mut unfinished = record.clone();
+        unfinished.actions.pop();
+        assert!(matches!(analyze_game(&unfinished, Some(1)), Err(RecordError::Unfinished)));
+    }
+}
@@ -0,0 +1,406 @@
+/*
+    The benchmark: searches on a fixed, versioned set of deals and positions (bench/set.txt), measured per phase of the game.
+    The report can be saved as JSON and compared with the report of an earlier commit to find performance regressions.
+ */
+
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::Game;
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::ai::{AgentError, MarjapussiCheater};
+use crate::cheater::{search_position, CheaterV1, SearchCounters};
+use crate::deal::deal_from_seed;
+use crate::log::LogFilter;
+use crate::notation::position::{parse_position, Position};
+
+
+// increase this whenever the format of the report changes in an incompatible way
+const FORMAT_VERSION: u32 = 1;
+
+const BENCH_SET: &str = include_str!("../bench/set.txt");
+
+// deep enough that the times are not dominated by noise, but the whole benchmark takes only a few seconds
+pub const DEFAULT_DEPTH: u32 = 9;
+
+// the phases of the cardplay, by the number of the trick
+const PHASES: [&str; 3] = ["opening", "middle", "end"];
+
+
+enum BenchEntry {
+    Deal(u64),
+    Position(String, Position)
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Incompatible(String),
+    // an agent failed in one of the searches of the set
+    Agent(AgentError)
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BenchError::Io(error) => write!(f, "could not access the report: {}", error),
+            BenchError::Json(error) => write!(f, "invalid report: {}", error),
+            BenchError::Incompatible(reason) => write!(f, "the reports can not be compared: {}", reason),
+            BenchError::Agent(error) => write!(f, "the benchmark failed: {}", error)
+        }
+    }
+}
+
+impl From<std::io::Error> for BenchError {
+    fn from(error: std::io::Error) -> Self {
+        BenchError::Io(error)
+    }
+}
+
+impl From<AgentError> for BenchError {
+    fn from(error: AgentError) -> Self {
+        BenchError::Agent(error)
+    }
+}
+
+impl From<serde_json::Error> for BenchError {
+    fn from(error: serde_json::Error) -> Self {
+        BenchError::Json(error)
+    }
+}
+
+
+// the searches of one entry of the set in one phase
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub name: String,
+    pub phase: String,
+    pub searches: u64,
+    pub seconds: f64,
+    pub nodes: u64,
+    pub nodes_per_second: f64,
+    // the average number of children of the nodes that were expanded
+    pub branching_factor: f64,
+    // None as long as the search has no transposition table
+    pub tt_hit_rate: Option<f64>
+}
+
+impl BenchResult {
+    fn new(name: &str, phase: &str, counters: &SearchCounters, time: Duration) -> Self {
+        let seconds = time.as_secs_f64();
+        BenchResult {
+            name: String::from(name),
+            phase: String::from(phase),
+            searches: counters.trees,
+            seconds,
+            nodes: counters.nodes,
+            nodes_per_second: if seconds > 0.0 { counters.nodes as f64 / seconds } else { 0.0 },
+            branching_factor: if counters.expanded_nodes > 0 {
+                counters.children as f64 / counters.expanded_nodes as f64
+            } else {
+                0.0
+            },
+            tt_hit_rate: None
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub format_version: u32,
+    pub set_version: u32,
+    pub search_depth: u32,
+    pub results: Vec<BenchResult>
+}
+
+impl BenchReport {
+    pub fn save(&self, path: &Path) -> Result<(), BenchError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, BenchError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn print_summary(&self) {
+        println!("\n ---------- Benchmark results ----------");
+        println!("benchmark set version: {}", self.set_version);
+        println!("max search depth: {}", self.search_depth);
+        println!(
+            "{:<20} {:<8} {:>9} {:>10} {:>12} {:>12} {:>10} {:>8}",
+            "entry", "phase", "searches", "time", "nodes", "nodes/s", "branching", "TT hits"
+        );
+        let totals = PHASES.map(|phase| self.total(phase));
+        for result in self.results.iter().chain(totals.iter()) {
+            println!(
+                "{:<20} {:<8} {:>9} {:>10} {:>12} {:>12.0} {:>10.2} {:>8}",
+                result.name,
+                result.phase,
+                result.searches,
+                format!("{:.3}s", result.seconds),
+                result.nodes,
+                result.nodes_per_second,
+                result.branching_factor,
+                result.tt_hit_rate.map_or(String::from("n/a"), |rate| format!("{:.1}%", 100.0 * rate))
+            );
+        }
+    }
+
+    fn total(&self, phase: &str) -> BenchResult {
+        // the sum of all results of the phase, the branching factor is weighted by the number of nodes
+        let results: Vec<&BenchResult> = self.results.iter().filter(|result| result.phase == phase).collect();
+        let seconds: f64 = results.iter().map(|result| result.seconds).sum();
+        let nodes: u64 = results.iter().map(|result| result.nodes).sum();
+        let weighted_branching: f64 = results.iter().map(|result| result.branching_factor * result.nodes as f64).sum();
+        BenchResult {
+            name: String::from("total"),
+            phase: String::from(phase),
+            searches: results.iter().map(|result| result.searches).sum(),
+            seconds,
+            nodes,
+            nodes_per_second: if seconds > 0.0 { nodes as f64 / seconds } else { 0.0 },
+            branching_factor: if nodes > 0 { weighted_branching / nodes as f64 } else { 0.0 },
+            tt_hit_rate: None
+        }
+    }
+}
+
+
+// the change of one result compared to the baseline, in percent
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub name: String,
+    pub phase: String,
+    pub time_change: f64,
+    pub nodes_change: f64
+}
+
+impl Comparison {
+    pub fn regression(&self, threshold: f64) -> bool {
+        !self.regressed_metrics(threshold).is_empty()
+    }
+
+    // the metrics that grew by more than threshold percent
+    pub fn regressed_metrics(&self, threshold: f64) -> Vec<&'static str> {
+        let changes = [("time", self.time_change), ("nodes", self.nodes_change)];
+        changes
+            .into_iter()
+            .filter(|(_, change)| *change > threshold)
+            .map(|(metric, _)| metric)
+            .collect()
+    }
+}
+
+pub fn compare(report: &BenchReport, baseline: &BenchReport) -> Result<Vec<Comparison>, BenchError> {
+    /*
+        Compares every result of the report with the result of the same entry and phase in the baseline.
+        Both reports must use the same benchmark set and search depth.
+     */
+
+    let differences = [
+        ("report format", report.format_version, baseline.format_version),
+        ("benchmark set version", report.set_version, baseline.set_version),
+        ("search depth", report.search_depth, baseline.search_depth)
+    ];
+    for (name, current, base) in differences {
+        if current != base {
+            return Err(BenchError::Incompatible(format!("the {} is {}, but {} in the baseline", name, current, base)));
+        }
+    }
+
+    let change = |current: f64, base: f64| if base > 0.0 { 100.0 * (current / base - 1.0) } else { 0.0 };
+    report.results
+        .iter()
+        .map(|result| {
+            let base = baseline.results
+                .iter()
+                .find(|base| base.name == result.name && base.phase == result.phase)
+                .ok_or(BenchError::Incompatible(format!("{} ({}) is missing in the baseline", result.name, result.phase)))?;
+            Ok(Comparison {
+                name: result.name.clone(),
+                phase: result.phase.clone(),
+                time_change: change(result.seconds, base.seconds),
+                nodes_change: change(result.nodes as f64, base.nodes as f64)
+            })
+        })
+        .collect()
+}
+
+pub fn print_comparison(comparisons: &[Comparison], threshold: f64) {
+    println!("\n ---------- Comparison with the baseline ----------");
+    println!("{:<20} {:<8} {:>10} {:>10}", "entry", "phase", "time", "nodes");
+    for comparison in comparisons {
+        println!(
+            "{:<20} {:<8} {:>10} {:>10}{}",
+            comparison.name,
+            comparison.phase,
+            format!("{:+.1}%", comparison.time_change),
+            format!("{:+.1}%", comparison.nodes_change),
+            if comparison.regression(threshold) {
+                format!("  REGRESSION in {}", comparison.regressed_metrics(threshold).join(" and "))
+            } else {
+                String::new()
+            }
+        );
+    }
+}
+
+
+pub fn run_bench(search_depth: u32) -> Result<BenchReport, BenchError> {
+    /*
+        Runs all searches of the benchmark set with the given search depth.
+     */
+
+    let (set_version, entries) = bench_set();
+    let mut results = vec![];
+    for entry in entries {
+        match entry {
+            BenchEntry::Deal(seed) => results.extend(bench_deal(seed, search_depth)?),
+            BenchEntry::Position(name, position) => {
+                let counters = SearchCounters::now();
+                let start = Instant::now();
+                search_position(&position, Some(search_depth))?;
+                let time = start.elapsed();
+                let trick = 10 - position.hands[position.player_at_turn as usize].len();
+                results.push(BenchResult::new(&name, phase(trick), &SearchCounters::now().since(&counters), time));
+            }
+        }
+    }
+
+    Ok(BenchReport {
+        format_version: FORMAT_VERSION,
+        set_version,
+        search_depth,
+        results
+    })
+}
+
+fn bench_deal(seed: u64, search_depth: u32) -> Result<Vec<BenchResult>, AgentError> {
+    /*
+        Plays the deal with four cheaters and measures their searches per phase.
+     */
+
+    let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+    let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4)
+        .map(|place| Box::new(CheaterV1::new(&player_names[place], place as u8, search_depth)) as Box<dyn MarjapussiCheater>)
+        .collect();
+    for player in players.iter_mut() {
+        player.set_log_filter(LogFilter::off());
+    }
+
+    let mut counters = [SearchCounters::default(); 3];
+    let mut times = [Duration::ZERO; 3];
+    let mut game = Game::new(String::from("Benchmark"), player_names, Some(deal_from_seed(seed)));
+    while game.state.phase != GamePhase::Ended {
+        let phase_index = (game.state.all_tricks.len() / 3).min(2);
+        let place = usize::from(game.state.player_at_turn.0);
+        let counters_before = SearchCounters::now();
+        let start = Instant::now();
+        let action = players[place].select_action(game.clone())?;
+        let time = start.elapsed();
+
+        // only the decisions in the cardplay are searched
+        if matches!(game.state.phase, GamePhase::Raising | GamePhase::StartTrick | GamePhase::Trick) {
+            let searched = SearchCounters::now().since(&counters_before);
+            counters[phase_index] = counters[phase_index].add(&searched);
+            times[phase_index] += time;
+        }
+        game.apply_action_mut(action);
+    }
+
+    Ok(PHASES
+        .iter()
+        .enumerate()
+        .map(|(index, phase)| BenchResult::new(&format!("deal {}", seed), phase, &counters[index], times[index]))
+        .collect())
+}
+
+fn phase(trick: usize) -> &'static str {
+    PHASES[((trick - 1) / 3).min(2)]
+}
+
+fn bench_set() -> (u32, Vec<BenchEntry>) {
+    /*
+        Parses the benchmark set. The set is part of the program, so an invalid set is a bug.
+     */
+
+    let mut version = None;
+    let mut entries = vec![];
+    for line in BENCH_SET.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let (kind, rest) = line.split_once(' ').expect("Every line of the benchmark set has a value");
+        match kind {
+            "version" => version = Some(rest.parse().expect("The version of the benchmark set is a number")),
+            "deal" => entries.push(BenchEntry::Deal(rest.parse().expect("The seed of a deal in the benchmark set is a number"))),
+            "position" => {
+                let (name, position) = rest.split_once(' ').expect("Every position in the benchmark set has a name");
+                let position = parse_position(position)
+                    .unwrap_or_else(|error| panic!("Invalid position {} in the benchmark set: {}", name, error));
+                entries.push(BenchEntry::Position(String::from(name), position));
+            },
+            _ => panic!("Unknown entry in the benchmark set: {}", line)
+        }
+    }
+    (version.expect("The benchmark set has a version"), entries)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_set() {
+        let (version, entries) = bench_set();
+        assert_eq!(version, 1);
+        assert!(entries.iter().any(|entry| matches!(entry, BenchEntry::Deal(_))));
+        let positions: Vec<&Position> = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                BenchEntry::Position(_, position) => Some(position),
+                _ => None
+            })
+            .collect();
+        // every phase has positions
+        for phase_name in PHASES {
+            assert!(positions.iter().any(|position| phase(10 - position.hands[position.player_at_turn as usize].len()) == phase_name));
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let report = run_bench(1).unwrap();
+        assert!(report.results.iter().all(|result| result.tt_hit_rate.is_none()));
+        assert!(report.results.iter().any(|result| result.nodes > 0 && result.branching_factor > 1.0));
+
+        // a report is not a regression of itself
+        let json = serde_json::to_string(&report).unwrap();
+        let baseline: BenchReport = serde_json::from_str(&json).unwrap();
+        let comparisons = compare(&report, &baseline).unwrap();
+        assert_eq!(comparisons.len(), report.results.len());
+        assert!(comparisons.iter().all(|comparison| !comparison.regression(0.0)));
+
+        let mut faster = baseline.clone();
+        for result in faster.results.iter_mut() {
+            result.seconds /= 2.0;
+            result.nodes /= 2;
+        }
+        assert!(compare(&report, &faster).unwrap().iter().any(|comparison| comparison.regression(10.0)));
+
+        // more nodes in the same time are a regression in the nodes only
+        let comparison = Comparison { name: String::from("entry"), phase: String::from("opening"), time_change: 0.0, nodes_change: 20.0 };
+        assert!(comparison.regression(10.0));
+        assert_eq!(comparison.regressed_metrics(10.0), vec!["nodes"]);
+
+        let mut deeper = baseline.clone();
+        deeper.search_depth = 2;
+        assert!(matches!(compare(&report, &deeper), Err(BenchError::Incompatible(_))));
+    }
+}
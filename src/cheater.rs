@@ -1,65 +1,167 @@
 mod search;
 mod bidding;
+mod knowledge;
 
 use search::AlphaBetaGameState;
-use bidding::BiddingInfos;
-use super::ai::MarjapussiCheater;
-use crate::alpha_beta::alpha_beta_search;
+pub use search::{score, score_so_far};
+pub use bidding::{BiddingInfos, BiddingReasoning};
+pub use knowledge::Knowledge;
+use super::ai::{AgentConfig, AgentError, MarjapussiCheater};
+use crate::alpha_beta::{alpha_beta_search, alpha_beta_search_traced, alpha_beta_search_until, evaluate_move, principal_variation,
+                        principal_variation_until, State};
+use crate::alpha_beta::trace::SearchTracer;
+use crate::log::{Level, LogFilter, Logger};
+use crate::notation::position::Position;
 use marjapussi::game::Game;
 use marjapussi::game::gameevent::{ActionType, GameAction};
 use marjapussi::game::gamestate::GamePhase;
 use marjapussi::game::player::PlaceAtTable;
-use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-static mut COUNT_TREES: u64 = 0;
-static mut COUNT_NODES: u64 = 0;
-static mut COUNT_CHILDREN: u64 = 0;
-static mut COUNT_NODES_PER_CHILDREN: [i32; 20] = [0; 20];
+// the searches on all threads count into the same counters
+static COUNT_TREES: AtomicU64 = AtomicU64::new(0);
+static COUNT_NODES: AtomicU64 = AtomicU64::new(0);
+static COUNT_CHILDREN: AtomicU64 = AtomicU64::new(0);
+static COUNT_NODES_PER_CHILDREN: [AtomicU64; 20] = [const { AtomicU64::new(0) }; 20];
+
+
+// the counters of all searches so far, the difference of two snapshots is the size of the searches in between
+// (on all threads, so searches that run at the same time on other threads are part of it)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SearchCounters {
+    pub trees: u64,
+    pub nodes: u64,
+    pub expanded_nodes: u64,
+    pub children: u64
+}
+
+impl SearchCounters {
+    pub fn now() -> Self {
+        // the counters only grow, so every counter of a later snapshot is at least as large as in an earlier one
+        SearchCounters {
+            trees: COUNT_TREES.load(Ordering::Relaxed),
+            nodes: COUNT_NODES.load(Ordering::Relaxed),
+            expanded_nodes: COUNT_NODES_PER_CHILDREN.iter().map(|count| count.load(Ordering::Relaxed)).sum(),
+            children: COUNT_CHILDREN.load(Ordering::Relaxed)
+        }
+    }
+
+    pub fn since(&self, earlier: &SearchCounters) -> Self {
+        SearchCounters {
+            trees: self.trees - earlier.trees,
+            nodes: self.nodes - earlier.nodes,
+            expanded_nodes: self.expanded_nodes - earlier.expanded_nodes,
+            children: self.children - earlier.children
+        }
+    }
+
+    pub fn add(&self, other: &SearchCounters) -> Self {
+        SearchCounters {
+            trees: self.trees + other.trees,
+            nodes: self.nodes + other.nodes,
+            expanded_nodes: self.expanded_nodes + other.expanded_nodes,
+            children: self.children + other.children
+        }
+    }
+}
+
+
+// where and how deep the search trees of an agent are exported
+#[derive(Debug, Clone)]
+pub struct SearchTraceConfig {
+    pub depth: u32,
+    pub directory: PathBuf
+}
 
 
 pub struct CheaterV1 {
     pub name: String,
     pub position: PlaceAtTable,
-    to_communicate: Vec<BiddingInfos>,
     search_depth: u32,
-    knowledge: HashMap<String, String>
+    knowledge: Knowledge,
+    trace: Option<SearchTraceConfig>,
+    traced_searches: u32,
+    logger: Logger,
+    // the reasoning behind the last bidding decision, until it is taken
+    bidding_reasoning: Option<BiddingReasoning>
 }
 
+const LOG_MODULE: &str = "cheater";
+
 
 impl CheaterV1 {
     pub fn new(name: &str, position: u8, search_depth: u32) -> Self {
         CheaterV1 {
             name: String::from(name),
             position: PlaceAtTable(position),
-            to_communicate: vec![],
             search_depth,
-            knowledge: HashMap::new()
+            knowledge: Knowledge::new(position),
+            trace: None,
+            traced_searches: 0,
+            logger: Logger::new(LogFilter::off()),
+            bidding_reasoning: None
         }
     }
 
-    fn bid(&mut self, game: Game, legal_actions: Vec<GameAction>) -> GameAction {
+    pub fn trace_searches(&mut self, config: SearchTraceConfig) {
+        /*
+            Exports the search tree of every following search as DOT and JSON file.
+         */
+
+        self.trace = Some(config);
+    }
+
+    fn search(&mut self, start_state: AlphaBetaGameState) -> GameAction {
+        COUNT_TREES.fetch_add(1, Ordering::Relaxed);
+        let Some(config) = &self.trace else {
+            return alpha_beta_search(start_state, Some(self.search_depth)).0;
+        };
+
+        // search and write the recorded tree to "<directory>/<player name>_<number of the search>.{dot,json}"
+        let mut tracer = SearchTracer::new(config.depth);
+        let (best_action, _) = alpha_beta_search_traced(start_state, Some(self.search_depth), &mut tracer);
+        self.traced_searches += 1;
+        let file_name = format!("{}_{:03}", self.name.replace(' ', "_"), self.traced_searches);
+        let result = fs::create_dir_all(&config.directory)
+            .and_then(|_| tracer.write_dot(&config.directory.join(format!("{}.dot", file_name))))
+            .and_then(|_| tracer.write_json(&config.directory.join(format!("{}.json", file_name))));
+        if let Err(error) = result {
+            self.logger.log(Level::Error, LOG_MODULE, || format!("could not write the search trace {}: {}", file_name, error));
+        }
+        best_action
+    }
+
+    fn bid(&mut self, game: Game, legal_actions: Vec<GameAction>) -> Result<GameAction, AgentError> {
     
-        let next_bidding_step = bidding::next_bidding_step(
+        let reasoning = bidding::next_bidding_step(
             &game.state.player_at_turn,
             &game.state.player_at_turn().cards,
             &game.state.partner().cards,
             &game.state.bidding_history,
             &mut self.knowledge,
-            &mut self.to_communicate);
-        let desired_action = match next_bidding_step {
+            &self.logger)?;
+        let desired_action = match reasoning.value {
             0 => ActionType::StopBidding,
             step => ActionType::NewBid(step)
         };
+        self.bidding_reasoning = Some(reasoning);
         legal_actions
             .into_iter()
             .find(|action| {
                 action.action_type == desired_action
-            }).expect("Wanted to bid but the desired step was not in the legal_actions")
+            })
+            .ok_or_else(|| AgentError::IllegalChoice(format!("{:?}", desired_action)))
     }
 }
 
 impl MarjapussiCheater for CheaterV1 {
-    fn select_action(&mut self, game: Game) -> GameAction {
+    fn select_action(&mut self, game: Game) -> Result<GameAction, AgentError> {
+
+        // learn from everything that happened since the last decision
+        self.knowledge.update(&game);
 
         // get the legal actions
         // remove all UndoRequests, since they are irrelevant here and create infinite paths in the search tree
@@ -71,49 +173,242 @@ impl MarjapussiCheater for CheaterV1 {
             .collect::<Vec<GameAction>>();
 
         // make sure that there are any legal actions
-        let first_action = legal_actions.get(0).expect("Player was asked to choose an action, but there are no legal actions");
+        let first_action = legal_actions.first().ok_or(AgentError::NoLegalActions)?;
         
         // make sure that we are the correct player to choose
         if self.position != first_action.player {
-            // should not occur
             // currently, there is a bug in the framework so the "start" action always has player number 0
-            println!("\nALAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAARM (player in action and player choosing an action mismatch)");
+            if game.state.phase != GamePhase::WaitingForStart {
+                return Err(AgentError::PlayerMismatch(format!(
+                    "player {} was asked to choose the action {:?} of player {}", self.position.0, first_action.action_type, first_action.player.0
+                )));
+            }
+            self.logger.log(Level::Warn, LOG_MODULE, || String::from("ALARM: player in action and player choosing an action mismatch"));
         }
         if self.position != game.state.player_at_turn {
-            println!("\nALAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAARM (player at turn and player choosing an action mismatch)");
+            return Err(AgentError::PlayerMismatch(format!(
+                "player {} was asked to choose, but player {} is at turn", self.position.0, game.state.player_at_turn.0
+            )));
         }
 
         // choose an action
         // if there is only one option, we need no further evaluation
         if legal_actions.len() == 1 {
-            legal_actions.into_iter().last().unwrap()
+            Ok(legal_actions.into_iter().last().unwrap())
         } else {
             // act according to the current game phase
             match game.state.phase {
                 GamePhase::Bidding => self.bid(game, legal_actions),
                 // in case the game phase is GamePhase::Raising, we will sort out all raising actions within the search and just play a card
                 GamePhase::StartTrick | GamePhase::Trick | GamePhase::Raising => {
-                    // unsafe {
-                    //     if COUNT_TREES == 1 {
-                    //         print_avg_tree_size();
-                    //     }
-                    //     COUNT_TREES += 1;
-                    // }
-                    alpha_beta_search(AlphaBetaGameState::new(self.position.clone(), game), Some(self.search_depth)).0
+                    Ok(self.search(AlphaBetaGameState::new(self.position.clone(), game)?))
                 },
-                _ => legal_actions.into_iter().nth(0).expect("Player was asked to choose an action, but there are no legal actions")
+                _ => legal_actions.into_iter().nth(0).ok_or(AgentError::NoLegalActions)
+            }
+        }
+    }
+
+    fn select_action_in_position(&mut self, position: &Position) -> Result<GameAction, AgentError> {
+        // positions are always in the cardplay, so there is nothing to do but to search
+        let legal_actions = position.to_game()
+            .legal_actions()
+            .into_iter()
+            .filter(|action| action.action_type != ActionType::UndoRequest)
+            .collect::<Vec<GameAction>>();
+        if legal_actions.len() == 1 {
+            return Ok(legal_actions.into_iter().last().unwrap());
+        }
+        Ok(self.search(AlphaBetaGameState::from_position(self.position.clone(), position)?))
+    }
+
+    fn set_log_filter(&mut self, filter: LogFilter) {
+        self.logger.set_filter(filter);
+    }
+
+    fn observe(&mut self, game: &Game) {
+        self.knowledge.update(game);
+    }
+
+    fn take_bidding_reasoning(&mut self) -> Option<BiddingReasoning> {
+        self.bidding_reasoning.take()
+    }
+
+    fn state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::to_value(&self.knowledge).expect("The knowledge can be serialized"))
+    }
+
+    fn config(&self) -> AgentConfig {
+        AgentConfig {
+            agent: String::from("CheaterV1"),
+            search_depth: Some(self.search_depth)
+        }
+    }
+}
+
+
+pub fn search_position(position: &Position, max_depth: Option<u32>) -> Result<(GameAction, i32), AgentError> {
+    /*
+        Searches the best action for the player at turn in the given position.
+        The value is the evaluation of the search from the view of this player's party.
+     */
+
+    COUNT_TREES.fetch_add(1, Ordering::Relaxed);
+    let owning_player = PlaceAtTable(position.player_at_turn);
+    Ok(alpha_beta_search(AlphaBetaGameState::from_position(owning_player, position)?, max_depth))
+}
+
+
+pub fn evaluate_decision(game: Game, action: &GameAction, max_depth: Option<u32>) -> Result<(GameAction, i32, i32), AgentError> {
+    /*
+        Compares an action of the player at turn with the best action of the full-information search.
+        Returns the best action, its value and the value of the given action, from the view of this player's party.
+        Raising is not part of the search, so a raise can be better than the "best" action.
+     */
+
+    let owning_player = game.state.player_at_turn.clone();
+    let start_state = AlphaBetaGameState::new(owning_player, game)?;
+    let value = evaluate_move(&start_state, action, max_depth);
+    let (best_action, best_value) = alpha_beta_search(start_state, max_depth);
+    Ok((best_action, best_value, value))
+}
+
+
+// one finished iteration of an iterative deepening search
+#[derive(Debug, Clone)]
+pub struct SearchIteration {
+    pub depth: u32,
+    pub best_action: GameAction,
+    // the value from the view of the party at turn
+    pub value: i32,
+    // the best action followed by the actions the search expects after it
+    pub continuation: Vec<GameAction>,
+    pub nodes: u64,
+    pub elapsed: Duration
+}
+
+pub fn search_iteratively(game: &Game, position: Option<&Position>, max_depth: u32, time_limit: Option<Duration>,
+                          report: &mut dyn FnMut(&SearchIteration)) -> Result<SearchIteration, AgentError> {
+    /*
+        Searches the cardplay of the game (or of the position, if it is given) with increasing depths up to max_depth.
+        A deeper iteration is only started while less than half of the time limit is used, since every iteration
+        takes several times longer than the one before. An iteration that is still running when the time limit passes
+        is given up, and the last finished iteration is returned. The search stops early when it reaches the end of the game.
+     */
+
+    let owning_player = game.state.player_at_turn.clone();
+    let start_state = || match position {
+        Some(position) => AlphaBetaGameState::from_position(owning_player.clone(), position),
+        None => AlphaBetaGameState::new(owning_player.clone(), game.clone())
+    };
+    let start = Instant::now();
+    let deadline = time_limit.map(|limit| start + limit);
+    let search = |depth: u32, deadline: Option<Instant>| -> Result<Option<SearchIteration>, AgentError> {
+        COUNT_TREES.fetch_add(1, Ordering::Relaxed);
+        let counters = SearchCounters::now();
+        let searched = match deadline {
+            Some(deadline) => match alpha_beta_search_until(start_state()?, Some(depth), deadline) {
+                Some((best_action, value)) => principal_variation_until(&start_state()?, &best_action, Some(depth), deadline)
+                    .map(|continuation| (best_action, value, continuation)),
+                None => None
+            },
+            None => {
+                let (best_action, value) = alpha_beta_search(start_state()?, Some(depth));
+                let continuation = principal_variation(&start_state()?, &best_action, Some(depth));
+                Some((best_action, value, continuation))
             }
+        };
+        Ok(searched.map(|(best_action, value, continuation)| SearchIteration {
+            depth,
+            best_action,
+            value,
+            nodes: SearchCounters::now().since(&counters).nodes,
+            elapsed: start.elapsed(),
+            continuation
+        }))
+    };
+
+    // the first iteration has no deadline, so there is always an action to return
+    let mut iteration = search(1, None)?.expect("A search without a deadline always finishes");
+    loop {
+        report(&iteration);
+
+        // without a depth limit, the continuation would have one action per level of the search
+        let complete = iteration.continuation.len() <= iteration.depth as usize;
+        let out_of_time = time_limit.is_some_and(|limit| iteration.elapsed * 2 > limit);
+        if iteration.depth >= max_depth || complete || out_of_time {
+            return Ok(iteration);
+        }
+        match search(iteration.depth + 1, deadline)? {
+            Some(deeper) => iteration = deeper,
+            None => return Ok(iteration)
         }
     }
 }
 
 
-pub unsafe fn print_avg_tree_size() {
-    let avg_nodes_per_tree = (COUNT_NODES as f64) / (COUNT_TREES as f64);
-    let avg_children_per_node = (COUNT_CHILDREN as f64) / (COUNT_NODES as f64);
+// the hint for a decision in the cardplay: all actions ranked by their value, and the continuation the search expects after the best one
+pub struct CardplayHint {
+    pub ranked_actions: Vec<(GameAction, i32)>,
+    pub continuation: Vec<GameAction>
+}
+
+pub fn cardplay_hint(game: Game, max_depth: Option<u32>) -> Result<CardplayHint, AgentError> {
+    /*
+        Evaluates every action the search considers for the player at turn, from the view of this player's party.
+        Raising is not part of the search, so raises are not ranked.
+     */
+
+    let start_state = AlphaBetaGameState::new(game.state.player_at_turn.clone(), game)?;
+    let mut ranked_actions: Vec<(GameAction, i32)> = start_state
+        .legal_moves()
+        .into_iter()
+        .map(|action| {
+            let value = evaluate_move(&start_state, &action, max_depth);
+            (action, value)
+        })
+        .collect();
+    ranked_actions.sort_by_key(|(_, value)| -value);
+    let continuation = ranked_actions
+        .first()
+        .map(|(best_action, _)| principal_variation(&start_state, best_action, max_depth))
+        .unwrap_or_default();
+    Ok(CardplayHint { ranked_actions, continuation })
+}
+
+pub fn bidding_hint(game: &Game) -> Result<(ActionType, Option<BiddingInfos>), AgentError> {
+    /*
+        The next bidding step of a cheater in the place of the player at turn, and the information it communicates.
+        The earlier bidding steps of the player are replayed, as if they had followed the same conventions.
+     */
+
+    let state = &game.state;
+    let place = state.player_at_turn.clone();
+    let cards = &state.player_at_turn().cards;
+    let partner_cards = &state.partner().cards;
+    let mut knowledge = Knowledge::new(place.0);
+    let logger = Logger::silent();
+    for (index, (_, player)) in state.bidding_history.iter().enumerate() {
+        if *player == place {
+            let history = state.bidding_history[..index].to_vec();
+            bidding::next_bidding_step(&place, cards, partner_cards, &history, &mut knowledge, &logger)?;
+        }
+    }
+    let reasoning = bidding::next_bidding_step(&place, cards, partner_cards, &state.bidding_history, &mut knowledge, &logger)?;
+    let action_type = match reasoning.value {
+        0 => ActionType::StopBidding,
+        step => ActionType::NewBid(step)
+    };
+    Ok((action_type, reasoning.info))
+}
+
+
+pub fn print_avg_tree_size() {
+    let counters = SearchCounters::now();
+    let avg_nodes_per_tree = (counters.nodes as f64) / (counters.trees as f64);
+    let avg_children_per_node = (counters.children as f64) / (counters.nodes as f64);
     let mut avg_nodes_per_children = [0.0; 20];
     for (index, num) in COUNT_NODES_PER_CHILDREN.iter().enumerate() {
-        avg_nodes_per_children[index] += (*num as f64) / (COUNT_NODES as f64)
+        avg_nodes_per_children[index] += (num.load(Ordering::Relaxed) as f64) / (counters.nodes as f64)
     }
     println!("avg nodes per tree: {}", avg_nodes_per_tree);
     println!("avg children per node: {}", avg_children_per_node);
@@ -121,4 +416,50 @@ pub unsafe fn print_avg_tree_size() {
     for (index, num) in avg_nodes_per_children.iter().enumerate() {
         println!("  {} -> {}", index, num);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deal::deal_from_seed;
+
+    fn started_game(seed: u64) -> Game {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut game = Game::new(String::from("Test"), player_names, Some(deal_from_seed(seed)));
+        while game.state.phase == GamePhase::WaitingForStart {
+            game.apply_action_mut(game.legal_actions()[0].clone());
+        }
+        game
+    }
+
+    #[test]
+    fn test_bidding_hint() {
+        // the hint is the step of a cheater that bid the same way from the start
+        for seed in 0..10 {
+            let mut game = started_game(seed);
+            let mut players: Vec<CheaterV1> = (0..4).map(|place| CheaterV1::new("Player", place, 1)).collect();
+            while game.state.phase == GamePhase::Bidding {
+                let (hint, info) = bidding_hint(&game).unwrap();
+                let action = players[game.state.player_at_turn.0 as usize].select_action(game.clone()).unwrap();
+                assert_eq!(hint, action.action_type, "wrong hint for the deal with seed {}", seed);
+                assert_eq!(info.is_some(), hint != ActionType::StopBidding);
+                game.apply_action_mut(action);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cardplay_hint() {
+        let mut game = started_game(4);
+        while !matches!(game.state.phase, GamePhase::Raising | GamePhase::StartTrick | GamePhase::Trick) {
+            let mut player = CheaterV1::new("Player", game.state.player_at_turn.0, 1);
+            game.apply_action_mut(player.select_action(game.clone()).unwrap());
+        }
+        let hint = cardplay_hint(game.clone(), Some(3)).unwrap();
+        let (_, best_value) = alpha_beta_search(AlphaBetaGameState::new(game.state.player_at_turn.clone(), game).unwrap(), Some(3));
+        assert!(hint.ranked_actions.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert_eq!(hint.ranked_actions[0].1, best_value);
+        assert_eq!(hint.continuation[0], hint.ranked_actions[0].0);
+        assert!(hint.continuation.len() <= 4);
+    }
+}
@@ -1,8 +1,14 @@
-use std::collections::HashMap;
-
 use marjapussi::game::{cards::{halves, pairs, Card, Suit, Value}, gameevent::ActionType, player::PlaceAtTable};
 
-#[derive(PartialEq, Debug)]
+use serde::{Deserialize, Serialize};
+
+use super::knowledge::Knowledge;
+use crate::ai::AgentError;
+use crate::log::{Level, Logger};
+
+const LOG_MODULE: &str = "cheater::bidding";
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum BiddingInfos {
     Ace,
     BigPair,
@@ -11,37 +17,88 @@ pub enum BiddingInfos {
     Halves2
 }
 
+impl BiddingInfos {
+    pub fn description(&self) -> &'static str {
+        match self {
+            BiddingInfos::Ace => "an ace (step of 5)",
+            BiddingInfos::BigPair => "a pair of red or bells (step of 15)",
+            BiddingInfos::SmallPair => "a pair of acorns or green (step of 10)",
+            BiddingInfos::Halves3_4 => "three or four halves without a pair (step of 10)",
+            BiddingInfos::Halves2 => "two halves without a pair, after an ace of the party (step of 5)"
+        }
+    }
+}
+
+
+// what happened to a bidding step that was considered
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepDecision {
+    // an ace is not announced if the partner already did
+    PartnerAnnouncedAce,
+    // two halves are only announced after an ace of the party
+    NoAceInParty,
+    // the step would exceed the game limit, so the bidding stops
+    OverLimit,
+    // the step would go over 140 without a secure pair of the party
+    NoSecurePair,
+    Bid(i32)
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BiddingStep {
+    pub info: BiddingInfos,
+    pub decision: StepDecision
+}
+
+// the reasoning behind one bidding decision, as it is attached to game records
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct BiddingReasoning {
+    pub place: u8,
+    // the considered steps in the order they were considered
+    pub steps: Vec<BiddingStep>,
+    // the new game value, 0 to stop bidding
+    pub value: i32,
+    // the information that is communicated by the new game value
+    pub info: Option<BiddingInfos>
+}
+
 
 pub fn next_bidding_step(own_position: &PlaceAtTable,
-                         hand_cards: &Vec<Card>,
-                         partner_cards: &Vec<Card>,
+                         hand_cards: &[Card],
+                         partner_cards: &[Card],
                          bidding_history: &Vec<(ActionType, PlaceAtTable)>, 
-                         knowledge: &mut HashMap<String, String>, 
-                         to_communicate: &mut Vec<BiddingInfos>) -> i32 {
+                         knowledge: &mut Knowledge,
+                         logger: &Logger) -> Result<BiddingReasoning, AgentError> {
     /*
         This function derives the next bidding step from the hand cards, the bidding history and previously derived information.
+        Returns the new game value (0 to stop bidding), the information that is communicated by it and how it was found.
      */
         
     // if this is our first bidding action, we have to 
     // find out what information we want to share in our bidding
+    let to_communicate = &mut knowledge.to_communicate;
     if bidding_history.len() < 4 {
         to_communicate.extend(assess_hand(hand_cards));
     }
     
     let partner_position = PlaceAtTable((own_position.0 + 2) % 4);
+    let mut reasoning = BiddingReasoning { place: own_position.0, steps: vec![], value: 0, info: None };
 
     // get the next information we want to share
     while let Some(next_info) = to_communicate.pop() {
 
-        println!("  next bidding step: {:?}", next_info);
+        logger.log(Level::Debug, LOG_MODULE, || format!("next bidding step: {:?}", next_info));
         // skip bidding steps under certain conditions
-        if next_info == BiddingInfos::Ace && player_announced_ace(bidding_history, &partner_position) {
+        if next_info == BiddingInfos::Ace && player_announced_ace(bidding_history, &partner_position, logger)? {
             // don't announce an ace if your partner already did
+            reasoning.steps.push(BiddingStep { info: next_info, decision: StepDecision::PartnerAnnouncedAce });
             continue;
         } else if next_info == BiddingInfos::Halves2 
-                  && !player_announced_ace(bidding_history, &partner_position)
-                  && !player_announced_ace(bidding_history, &own_position) {
+                  && !player_announced_ace(bidding_history, &partner_position, logger)?
+                  && !player_announced_ace(bidding_history, own_position, logger)? {
             // don't announce two halves if no ace was announced in the party yet
+            reasoning.steps.push(BiddingStep { info: next_info, decision: StepDecision::NoAceInParty });
             continue;
         }
         
@@ -55,19 +112,14 @@ pub fn next_bidding_step(own_position: &PlaceAtTable,
         };
 
         // get the current game value (last bidding step) and the new value after bidding
-        let last_bidding_action = bidding_history
+        let current_value = bidding_history
             .iter()
             .rev()
-            .find(|(action, _)| {
-                matches!(action, ActionType::NewBid(_))
+            .find_map(|(action, _)| match action {
+                ActionType::NewBid(value) => Some(*value),
+                _ => None
             })
-            .unwrap_or(&(ActionType::NewBid(115), PlaceAtTable(u8::MAX))).0
-            .clone();
-        let current_value = if let ActionType::NewBid(value) = last_bidding_action {
-            value
-        } else {
-            panic!()
-        };
+            .unwrap_or(115);
         let mut next_value = current_value + step;
         
         if current_value < 140 && next_value >= 140 {
@@ -75,37 +127,42 @@ pub fn next_bidding_step(own_position: &PlaceAtTable,
         }
         
         if next_value > 420 {
-            println!(
-                "  folding since I can't exceed the game limit, remaining steps were {:?}",
+            logger.log(Level::Info, LOG_MODULE, || format!(
+                "folding since I can't exceed the game limit, remaining steps were {:?}",
                 std::iter::once(&next_info).chain(to_communicate.iter()).collect::<Vec<_>>()
-            );
-            return 0;
+            ));
+            reasoning.steps.push(BiddingStep { info: next_info, decision: StepDecision::OverLimit });
+            return Ok(reasoning);
         }
         
         if next_value < 140 {
-            println!("  bidding {} for {:?} while staying under 140", next_value, next_info);
-            return next_value;
+            logger.log(Level::Info, LOG_MODULE, || format!("bidding {} for {:?} while staying under 140", next_value, next_info));
+            reasoning.steps.push(BiddingStep { info: next_info, decision: StepDecision::Bid(next_value) });
+            return Ok(BiddingReasoning { value: next_value, info: Some(next_info), ..reasoning });
         } else {
-            let cards_together: Vec<Card> = hand_cards.clone()
-                .into_iter()
-                .chain(partner_cards.clone())
+            let cards_together: Vec<Card> = hand_cards.iter()
+                .chain(partner_cards)
+                .cloned()
                 .collect();
             let have_secure_pair = !pairs(cards_together).is_empty();
             if have_secure_pair {
-                println!("  bidding {} for {:?} while being sure that we have a pair", next_value, next_info);
-                return next_value;
-            } else {
-                println!("  not going over 140 for {:?} since I am not sure if we have a pair; trying next bidding step", next_info);
+                logger.log(Level::Info, LOG_MODULE, || format!("bidding {} for {:?} while being sure that we have a pair", next_value, next_info));
+                reasoning.steps.push(BiddingStep { info: next_info, decision: StepDecision::Bid(next_value) });
+                return Ok(BiddingReasoning { value: next_value, info: Some(next_info), ..reasoning });
             }
+            logger.log(Level::Debug, LOG_MODULE, || format!(
+                "not going over 140 for {:?} since I am not sure if we have a pair; trying next bidding step", next_info
+            ));
+            reasoning.steps.push(BiddingStep { info: next_info, decision: StepDecision::NoSecurePair });
         }
     }
     
-    println!("  folding since there is nothing to communicate");
-    0
+    logger.log(Level::Info, LOG_MODULE, || String::from("folding since there is nothing to communicate"));
+    Ok(reasoning)
 }
 
 
-pub fn assess_hand(cards: &Vec<Card>) -> Vec<BiddingInfos> {
+pub fn assess_hand(cards: &[Card]) -> Vec<BiddingInfos> {
     /* 
     get all information that is relevant for the bidding phase and gather it in a vector
     */ 
@@ -118,7 +175,7 @@ pub fn assess_hand(cards: &Vec<Card>) -> Vec<BiddingInfos> {
     }
 
     // find out if we have pairs
-    let own_pairs = pairs(cards.clone());
+    let own_pairs = pairs(cards.to_vec());
     // we assume that the pairs function sorts the pairs ascendingly
     assert!(own_pairs.is_sorted());
     own_pairs
@@ -136,7 +193,7 @@ pub fn assess_hand(cards: &Vec<Card>) -> Vec<BiddingInfos> {
         });
 
     // find out how many single halves we have
-    let count_single_halves = halves(cards.clone())
+    let count_single_halves = halves(cards.to_vec())
         .iter()
         .filter(|suit| {!own_pairs.contains(suit)})
         .count();
@@ -154,35 +211,66 @@ pub fn assess_hand(cards: &Vec<Card>) -> Vec<BiddingInfos> {
 }
 
 
-pub fn player_announced_ace(bidding_history: &Vec<(ActionType, PlaceAtTable)>, player: &PlaceAtTable) -> bool {
+pub fn player_announced_ace(bidding_history: &Vec<(ActionType, PlaceAtTable)>, player: &PlaceAtTable, logger: &Logger) -> Result<bool, AgentError> {
     /*
         !!!! Not working properly!
      */
 
-    println!("  checking if player {} announced an ace", player.0);
+    logger.log(Level::Trace, LOG_MODULE, || format!("checking if player {} announced an ace", player.0));
 
     // find out if the partner announced an ace
-    let result = vec!((ActionType::NewBid(115), PlaceAtTable(0)))
+    // only the steps of the player are evaluated, and the search stops at the first announcement
+    let all_steps = vec!((ActionType::NewBid(115), PlaceAtTable(0)))
                 .into_iter()
                 .chain(bidding_history.clone())
-                .collect::<Vec<(ActionType, PlaceAtTable)>>()   // at this point, we have a Vec with all bidding steps, including the imaginary first step of 115
-                .windows(4)
-                .map(|window| {
-                    let step = match (&window[0].0, &window[1].0, &window[2].0, &window[3].0) {
-                        (_, _, _, ActionType::StopBidding) => 0,
-                        (_, ActionType::NewBid(bid1), ActionType::StopBidding, ActionType::NewBid(bid2)) => bid2 - bid1,
-                        (ActionType::NewBid(bid1), ActionType::StopBidding, ActionType::StopBidding, ActionType::NewBid(bid2)) => bid2 - bid1,
-                        (_, _, ActionType::NewBid(bid1), ActionType::NewBid(bid2)) => bid2 - bid1,
-                        _ => panic!("Don't know how to handle this bidding history: {:?}", bidding_history)
-                    };
-                    (step, &window[3].1)
-                })
-                .any(|(step, player_num)| {
-                    *player_num == *player && step == 5
-                });
-    match result {
-        true => println!("  he did"),
-        false => println!("  he didn't")
-    };
-    result
-}
\ No newline at end of file
+                .collect::<Vec<(ActionType, PlaceAtTable)>>();   // at this point, we have a Vec with all bidding steps, including the imaginary first step of 115
+    let mut result = false;
+    for window in all_steps.windows(4).filter(|window| window[3].1 == *player) {
+        let step = match (&window[0].0, &window[1].0, &window[2].0, &window[3].0) {
+            (_, _, _, ActionType::StopBidding) => 0,
+            (_, ActionType::NewBid(bid1), ActionType::StopBidding, ActionType::NewBid(bid2)) => bid2 - bid1,
+            (ActionType::NewBid(bid1), ActionType::StopBidding, ActionType::StopBidding, ActionType::NewBid(bid2)) => bid2 - bid1,
+            (_, _, ActionType::NewBid(bid1), ActionType::NewBid(bid2)) => bid2 - bid1,
+            _ => return Err(AgentError::UnknownBidding(format!("{:?}", bidding_history)))
+        };
+        if step == 5 {
+            result = true;
+            break;
+        }
+    }
+    logger.log(Level::Trace, LOG_MODULE, || String::from(if result { "he did" } else { "he didn't" }));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::LogFilter;
+
+    #[test]
+    fn test_player_announced_ace() {
+        // place 0 announces an ace with 135, the bid of 140 after three stops is not understood
+        let bids = [
+            (ActionType::NewBid(120), 1),
+            (ActionType::NewBid(125), 2),
+            (ActionType::NewBid(130), 3),
+            (ActionType::NewBid(135), 0),
+            (ActionType::StopBidding, 1),
+            (ActionType::StopBidding, 2),
+            (ActionType::StopBidding, 3),
+            (ActionType::NewBid(140), 0)
+        ];
+        let history: Vec<(ActionType, PlaceAtTable)> = bids.into_iter().map(|(bid, place)| (bid, PlaceAtTable(place))).collect();
+        let logger = Logger::new(LogFilter::off());
+
+        // the announcement is found before the unknown step
+        assert!(player_announced_ace(&history, &PlaceAtTable(0), &logger).unwrap());
+        // the unknown step is not one of the steps of place 1
+        assert!(!player_announced_ace(&history, &PlaceAtTable(1), &logger).unwrap());
+        // without the announcement, the unknown step has to be evaluated
+        let mut without_ace = history.clone();
+        without_ace[3].0 = ActionType::NewBid(140);
+        without_ace[7].0 = ActionType::NewBid(145);
+        assert!(matches!(player_announced_ace(&without_ace, &PlaceAtTable(0), &logger), Err(AgentError::UnknownBidding(_))));
+    }
+}
@@ -0,0 +1,255 @@
+/*
+    What a cheater knows about the game: the signals of every player's bids, the cards and voids of every player,
+    the information it still wants to communicate in the bidding and the plan of its party.
+
+    The knowledge is updated from the events of the game, every event is only processed once.
+    It can be serialized, so the state of an agent can be inspected and saved in the middle of a game.
+ */
+
+use marjapussi::game::cards::{Card, Suit};
+use marjapussi::game::gameevent::{ActionType, GameCallback};
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::player::PlaceAtTable;
+use marjapussi::game::Game;
+
+use serde::{Deserialize, Serialize};
+
+use super::bidding::BiddingInfos;
+
+
+// what a bid of a player may have communicated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BidSignal {
+    pub value: i32,
+    // the step of the bid, without the extra 5 for going over 140
+    pub step: i32,
+    // the information the step stands for by the bidding conventions, more than one if the step is ambiguous
+    pub candidates: Vec<BiddingInfos>
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PlayerKnowledge {
+    pub signals: Vec<BidSignal>,
+    // a cheater sees all cards, so these are the current cards of the player
+    pub known_cards: Vec<Card>,
+    // suits the player could not follow
+    #[serde(with = "crate::record::suits")]
+    pub voids: Vec<Suit>,
+    // suits of the pairs the player announced
+    #[serde(with = "crate::record::suits")]
+    pub announced_trumps: Vec<Suit>,
+    // the game value the player raised to after the passing, a raise is no signal of the bidding
+    pub raise: Option<i32>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartnershipPlan {
+    // the bidding is not finished yet
+    Bidding,
+    // the own party won the bidding and has to reach the game value
+    Playing { game_value: i32 },
+    // the opponents play, the own party tries to keep them under the game value
+    Defending { game_value: i32 },
+    // nobody bid
+    NoGame
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Knowledge {
+    pub place: u8,
+    // indexed by the place at the table
+    pub players: [PlayerKnowledge; 4],
+    // the information that is still to be communicated in the bidding, the next one is the last element
+    pub to_communicate: Vec<BiddingInfos>,
+    pub plan: PartnershipPlan,
+    // the number of events of the game that are already processed
+    seen_events: usize,
+    // the cards of the current trick, to find out who could not follow
+    trick: Vec<Card>,
+    // the last bid, which the step of the next bid is measured from
+    last_bid: i32,
+    // the bidding ends before the passing, a bid after a pass is a raise
+    bidding_ended: bool
+}
+
+impl Knowledge {
+    pub fn new(place: u8) -> Self {
+        Knowledge {
+            place,
+            players: Default::default(),
+            to_communicate: vec![],
+            plan: PartnershipPlan::Bidding,
+            seen_events: 0,
+            trick: vec![],
+            last_bid: 115,
+            bidding_ended: false
+        }
+    }
+
+    pub fn update(&mut self, game: &Game) {
+        /*
+            Processes the events of the game that happened since the last update.
+            A game with fewer events than already seen is a different game (e.g. one built from a position), so the knowledge starts over.
+         */
+
+        if game.all_events.len() < self.seen_events {
+            *self = Knowledge::new(self.place);
+        }
+        for event in &game.all_events[self.seen_events..] {
+            let player = event.last_action.player.0 as usize;
+            match &event.last_action.action_type {
+                ActionType::NewBid(value) if self.bidding_ended => {
+                    self.players[player].raise = Some(*value);
+                },
+                ActionType::NewBid(value) => {
+                    let signal = self.bid_signal(player, *value);
+                    self.players[player].signals.push(signal);
+                    self.last_bid = *value;
+                },
+                ActionType::Pass(_) => {
+                    self.bidding_ended = true;
+                },
+                ActionType::CardPlayed(card) => {
+                    // the first card of the trick has to be followed if possible
+                    if let Some(first) = self.trick.first() {
+                        if card.suit != first.suit && !self.players[player].voids.contains(&first.suit) {
+                            self.players[player].voids.push(first.suit);
+                        }
+                    }
+                    self.trick.push(card.clone());
+                    if self.trick.len() == 4 {
+                        self.trick.clear();
+                    }
+                },
+                _ => ()
+            }
+            if let Some(GameCallback::NewTrump(suit)) = event.callback {
+                self.players[player].announced_trumps.push(suit);
+            }
+        }
+        self.seen_events = game.all_events.len();
+
+        for (place, player) in self.players.iter_mut().enumerate() {
+            player.known_cards = game.state.player_at_place(PlaceAtTable(place as u8)).cards.clone();
+        }
+        self.plan = self.partnership_plan(game);
+    }
+
+    fn bid_signal(&self, player: usize, value: i32) -> BidSignal {
+        /*
+            Interprets a bid by the conventions of next_bidding_step.
+         */
+
+        let mut step = value - self.last_bid;
+        if self.last_bid < 140 && value >= 140 {
+            step -= 5;
+        }
+        let party_announced_ace = [player, (player + 2) % 4]
+            .iter()
+            .any(|place| self.players[*place].signals.iter().any(|signal| signal.candidates == [BiddingInfos::Ace]));
+        let candidates = match step {
+            // an ace is only announced once per party, two halves only after an ace
+            5 if party_announced_ace => vec![BiddingInfos::Halves2],
+            5 => vec![BiddingInfos::Ace],
+            10 => vec![BiddingInfos::SmallPair, BiddingInfos::Halves3_4],
+            15 => vec![BiddingInfos::BigPair],
+            _ => vec![]
+        };
+        BidSignal { value, step, candidates }
+    }
+
+    fn partnership_plan(&self, game: &Game) -> PartnershipPlan {
+        if matches!(game.state.phase, GamePhase::WaitingForStart | GamePhase::Bidding) {
+            return PartnershipPlan::Bidding;
+        }
+        let game_value = game.state.value.0;
+        let highest_bidder = game.all_events
+            .iter()
+            .rev()
+            .find(|event| matches!(event.last_action.action_type, ActionType::NewBid(_)))
+            .map(|event| event.last_action.player.0);
+        match highest_bidder {
+            None => PartnershipPlan::NoGame,
+            Some(bidder) if bidder % 2 == self.place % 2 => PartnershipPlan::Playing { game_value },
+            Some(_) => PartnershipPlan::Defending { game_value }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::MarjapussiCheater;
+    use crate::cheater::CheaterV1;
+    use crate::deal::deal_from_seed;
+    use crate::log::LogFilter;
+
+    #[test]
+    fn test_update() {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut game = Game::new(String::from("Test"), player_names, Some(deal_from_seed(4)));
+        let mut knowledge = Knowledge::new(1);
+        let mut players: Vec<CheaterV1> = (0..4).map(|place| CheaterV1::new("Player", place, 1)).collect();
+        for player in players.iter_mut() {
+            player.set_log_filter(LogFilter::off());
+        }
+        while game.state.phase != GamePhase::Ended {
+            let action = players[game.state.player_at_turn.0 as usize].select_action(game.clone()).unwrap();
+            game.apply_action_mut(action);
+            knowledge.update(&game);
+
+            // a player with a card of a suit can not be void in it
+            for player in &knowledge.players {
+                assert!(player.known_cards.iter().all(|card| !player.voids.contains(&card.suit)));
+            }
+            if game.state.phase == GamePhase::Bidding {
+                assert_eq!(knowledge.plan, PartnershipPlan::Bidding);
+            }
+        }
+
+        let bids = game.all_events.iter().filter(|event| matches!(event.last_action.action_type, ActionType::NewBid(_))).count();
+        assert_eq!(knowledge.players.iter().map(|player| player.signals.len()).sum::<usize>(), bids);
+        assert!(knowledge.players.iter().all(|player| player.known_cards.is_empty()));
+        assert!(knowledge.players.iter().flat_map(|player| &player.signals).all(|signal| !signal.candidates.is_empty()));
+        assert!(!matches!(knowledge.plan, PartnershipPlan::Bidding));
+
+        // the knowledge can be saved and loaded in the middle of a game
+        let json = serde_json::to_string(&knowledge).unwrap();
+        assert_eq!(serde_json::from_str::<Knowledge>(&json).unwrap(), knowledge);
+    }
+
+    #[test]
+    fn test_raise() {
+        // play deals until one of them reaches the raising
+        let mut players: Vec<CheaterV1> = (0..4).map(|place| CheaterV1::new("Player", place, 1)).collect();
+        let game = (0..)
+            .map(|seed| {
+                let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+                let mut game = Game::new(String::from("Test"), player_names, Some(deal_from_seed(seed)));
+                while !matches!(game.state.phase, GamePhase::Raising | GamePhase::Trick | GamePhase::Ended) {
+                    let action = players[game.state.player_at_turn.0 as usize].select_action(game.clone()).unwrap();
+                    game.apply_action_mut(action);
+                }
+                game
+            })
+            .find(|game| game.state.phase == GamePhase::Raising)
+            .unwrap();
+        let mut knowledge = Knowledge::new(0);
+        knowledge.update(&game);
+        let signals: Vec<BidSignal> = knowledge.players.iter().flat_map(|player| player.signals.clone()).collect();
+
+        // the raise is recorded, but it is no signal of the bidding
+        let raise = game.legal_actions()
+            .into_iter()
+            .find(|action| matches!(action.action_type, ActionType::NewBid(_)))
+            .unwrap();
+        let raising_player = raise.player.0 as usize;
+        let game = game.apply_action(raise).unwrap();
+        knowledge.update(&game);
+        assert_eq!(knowledge.players[raising_player].raise, Some(game.state.value.0));
+        assert_eq!(knowledge.players.iter().flat_map(|player| player.signals.clone()).collect::<Vec<BidSignal>>(), signals);
+        assert!(knowledge.players.iter().enumerate().all(|(place, player)| place == raising_player || player.raise.is_none()));
+    }
+}
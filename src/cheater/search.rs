@@ -1,18 +1,54 @@
 mod optimizations;
+#[cfg(test)]
+mod differential;
 
+use optimizations::EqChecker;
+use crate::ai::AgentError;
 use crate::alpha_beta::State;
+use crate::alpha_beta::trace::MoveLabel;
+use crate::notation::position::Position;
 
-use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 use std::vec;
 
 use marjapussi::game::cards::Card;
 use marjapussi::game::gamestate::GamePhase;
 use marjapussi::game::player::PlaceAtTable;
-use marjapussi::game::{legal_actions, Game};
+use marjapussi::game::Game;
 use marjapussi::game::gameevent::{ActionType, AnswerType, GameAction, GameCallback};
 use marjapussi::game::points::{points_pair, Points};
 
 
+// switches for the optimizations that reduce the number of moves in the search tree
+// every optimization must leave the value of the search unchanged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchOptimizations {
+    // never ask questions that can't change the trump (see AlphaBetaGameState::useless_question)
+    pub drop_useless_questions: bool,
+    // only play one card of every group of equivalent cards (see optimizations::EqChecker)
+    pub card_equivalence: bool
+}
+
+impl SearchOptimizations {
+    #[cfg(test)]
+    pub fn none() -> Self {
+        SearchOptimizations {
+            drop_useless_questions: false,
+            card_equivalence: false
+        }
+    }
+}
+
+impl Default for SearchOptimizations {
+    fn default() -> Self {
+        SearchOptimizations {
+            drop_useless_questions: true,
+            card_equivalence: true
+        }
+    }
+}
+
+
 // a node in the search tree
 pub struct AlphaBetaGameState {
     owning_player: PlaceAtTable,
@@ -20,11 +56,21 @@ pub struct AlphaBetaGameState {
     remaining_cards: Vec<Card>,
     points_per_party: [i32; 2],
     tricks_per_party: [i8; 2],
-    playing_party: Option<u8>
+    playing_party: Option<u8>,
+    optimizations: SearchOptimizations,
+    eq_checker: Option<EqChecker>
 }
 
 impl AlphaBetaGameState {
-    pub fn new(owning_player: PlaceAtTable, game: Game) -> Self {
+    pub fn new(owning_player: PlaceAtTable, game: Game) -> Result<Self, AgentError> {
+        /*
+            Creates a new AlphaBetaGameState from a marjapussi game, using all search optimizations.
+        */
+
+        Self::with_optimizations(owning_player, game, SearchOptimizations::default())
+    }
+
+    pub fn with_optimizations(owning_player: PlaceAtTable, game: Game, optimizations: SearchOptimizations) -> Result<Self, AgentError> {
         /*
             Creates a new AlphaBetaGameState from a marjapussi game.
         */
@@ -35,7 +81,7 @@ impl AlphaBetaGameState {
             GamePhase::Bidding | 
             GamePhase::PassingForth | 
             GamePhase::PassingBack | 
-            GamePhase::Ended => panic!("AlphaBetaGameState can only be created from a game that is past the passing phase."),
+            GamePhase::Ended => return Err(AgentError::NotInCardplay(format!("{:?}", game.state.phase))),
             _ => ()
         }
 
@@ -46,53 +92,41 @@ impl AlphaBetaGameState {
         }
         remaining_cards.sort();
         
-        // get the current points in the game
-        // this will hold the points of each player
-        let mut players_points = [Points(0); 4];
-        // here we store how many tricks each player got
-        let mut players_tricks: [i8; 4] = [0, 0, 0, 0];
-        // calculate the points from winning tricks and count the tricks per player
-        for trick in &game.state.all_tricks {
-            players_tricks[trick.winner.0 as usize] += 1;
-            players_points[trick.winner.0 as usize] += trick.points;
-        }
-        // points from winning the last trick (+20)
-        if game.state.all_tricks.len() == 9 {
-            let last_trick = game.state.all_tricks.last().unwrap();
-            players_points[last_trick.winner.0 as usize] += Points(20);
-        }
-        // calculate the points from announcing pairs
-        // also get the playing party
-        let mut playing_party = None;
-        for event in &game.all_events {
-            if ActionType::NewBid(game.state.value.0) == event.last_action.action_type {
-                playing_party = Some(event.last_action.player.0 % 2);
-            }
-            if let Some(GameCallback::NewTrump(suit)) = event.callback {
-                players_points[event.last_action.player.clone().0 as usize] +=
-                    points_pair(suit);
-            }
-        }
-        // add the player points together to get the party points
-        let points_per_party = [
-            players_points[0].0 + players_points[2].0,
-            players_points[1].0 + players_points[3].0
-        ];
-        // find out how many tricks each party got
-        let tricks_per_party = [
-            players_tricks[0] + players_tricks[2],
-            players_tricks[1] + players_tricks[3]
-        ];
+        // get the current points and tricks in the game
+        let (points_per_party, tricks_per_party, playing_party) = score_so_far(&game);
+
+        // group the equivalent cards, if this optimization is used
+        let eq_checker = if optimizations.card_equivalence {
+            Some(EqChecker::new(&game))
+        } else {
+            None
+        };
 
         // create the AlphaBetaGameState
-        AlphaBetaGameState {
+        Ok(AlphaBetaGameState {
             owning_player,
             game,
             remaining_cards,
             points_per_party,
             tricks_per_party,
-            playing_party
-        }
+            playing_party,
+            optimizations,
+            eq_checker
+        })
+    }
+
+    pub fn from_position(owning_player: PlaceAtTable, position: &Position) -> Result<Self, AgentError> {
+        /*
+            Creates a new AlphaBetaGameState from a position, using all search optimizations.
+            The points, tricks and the playing party are taken from the position, since the game built from it has no history.
+        */
+
+        let game = position.to_game();
+        let mut state = Self::new(owning_player, game)?;
+        state.points_per_party = position.points;
+        state.tricks_per_party = position.tricks.map(|tricks| tricks as i8);
+        state.playing_party = position.playing_party;
+        Ok(state)
     }
 
 
@@ -107,64 +141,26 @@ impl AlphaBetaGameState {
             .filter(|action| {
                 action.action_type != ActionType::UndoRequest && 
                 !matches!(action.action_type, ActionType::NewBid(_)) &&
-                !self.useless_question(action)
+                !(self.optimizations.drop_useless_questions && self.useless_question(action))
             })
             .collect();
 
-        unsafe {
-            super::COUNT_NODES_PER_CHILDREN[legal_actions.len() as usize] += 1;
-        }
-
-        legal_actions
-        
-        // // if two cards have no other cards between them, they are equally strong in cardplay
-        // // if such equivalences exist, we sort out all of these equivalent cards except the weakest one
-        // // for now, this is only applied to low cards, since they all give the same amount of points (0)
-
-        // // first, we have to separate cardplay moves from all other moves
-        // let mut legal_cardplay_actions = vec![];
-        // let mut legal_other_actions = vec![];
-        // legal_actions
-        //     .into_iter()
-        //     .for_each(|action| {
-        //         if matches!(action.action_type, ActionType::CardPlayed(_)) {
-        //             legal_cardplay_actions.push(action);
-        //         } else {
-        //             legal_other_actions.push(action);
-        //         }
-        //     });
-
-        // // if there are no legal cardplay actions, we can skip this
-        // // else, we will now sort out equivalent moves
-        // if legal_cardplay_actions.len() > 0 {
-
-        //     // the legal cardplay actions have to be sorted ascendingly, since the remaining cards are too
-        //     legal_cardplay_actions
-        //         .sort_by_key(|action| {
-        //             if let ActionType::CardPlayed(card) = action.action_type {
-        //                 action
-        //             } else {
-        //                 panic!("action should be a cardplay action, but something else was given")
-        //             }
-        //         });
-
-        //     // iterate through the remaining cards and drop equivalent actions
-        //     let mut iter_cardplay = legal_cardplay_actions.iter();
-        //     let mut current_action = iter_cardplay.next().unwrap();
-        //     let mut last_matched = false;
-        //     for current_remaining in &self.remaining_cards {
-        //         if matches!(current_action.action_type, ActionType::CardPlayed()) {
-        //             if last_matched == true {
-
-        //             }
-        //         }
-        //     }
-        // }
+        // if two cards of one player have no other cards between them, they are equally strong in cardplay
+        // if such equivalences exist, we sort out all of these equivalent cards except one
+        let legal_actions = match &self.eq_checker {
+            Some(eq_checker) => {
+                let mut trick = self.game.state.current_trick.clone();
+                if trick.len() == 4 {
+                    trick = vec![];
+                }
+                eq_checker.reduce_legal_actions(legal_actions, &trick, self.game.state.trump)
+            },
+            None => legal_actions
+        };
 
+        super::COUNT_NODES_PER_CHILDREN[legal_actions.len()].fetch_add(1, Ordering::Relaxed);
 
-        // let mut legal_actions_cut = legal_cardplay_actions;
-        // legal_actions_cut.extend(legal_other_actions);
-        // legal_actions_cut
+        legal_actions
     }
 
 
@@ -212,6 +208,119 @@ impl AlphaBetaGameState {
     }
 }
 
+
+pub fn score_so_far(game: &Game) -> ([i32; 2], [i8; 2], Option<u8>) {
+    /*
+        The points and tricks each party got so far (including the pairs and the last trick), and the playing party.
+    */
+
+    // this will hold the points of each player
+    let mut players_points = [Points(0); 4];
+    // here we store how many tricks each player got
+    let mut players_tricks: [i8; 4] = [0, 0, 0, 0];
+    // calculate the points from winning tricks and count the tricks per player
+    for trick in &game.state.all_tricks {
+        players_tricks[trick.winner.0 as usize] += 1;
+        players_points[trick.winner.0 as usize] += trick.points;
+    }
+    // points from winning the last trick (+20)
+    if game.state.all_tricks.len() == 9 {
+        let last_trick = game.state.all_tricks.last().unwrap();
+        players_points[last_trick.winner.0 as usize] += Points(20);
+    }
+    // calculate the points from announcing pairs
+    // also get the playing party
+    let mut playing_party = None;
+    for event in &game.all_events {
+        if ActionType::NewBid(game.state.value.0) == event.last_action.action_type {
+            playing_party = Some(event.last_action.player.0 % 2);
+        }
+        if let Some(GameCallback::NewTrump(suit)) = event.callback {
+            players_points[event.last_action.player.clone().0 as usize] +=
+                points_pair(suit);
+        }
+    }
+    // add the player points together to get the party points
+    let points_per_party = [
+        players_points[0].0 + players_points[2].0,
+        players_points[1].0 + players_points[3].0
+    ];
+    // find out how many tricks each party got
+    let tricks_per_party = [
+        players_tricks[0] + players_tricks[2],
+        players_tricks[1] + players_tricks[3]
+    ];
+    (points_per_party, tricks_per_party, playing_party)
+}
+
+
+pub fn score(points_per_party: [i32; 2], tricks_per_party: [i8; 2], playing_party: Option<u8>, game_value: i32, own_party: u8) -> i32 {
+    /*
+        The final point difference between the own party and the opponent party,
+        for the points and tricks of both parties, the party that plays the game and the value of the game.
+    */
+
+    // get the important values
+    let opponent_party = (own_party + 1) % 2;
+    let own_party_points = points_per_party[own_party as usize];
+    let opponent_party_points = points_per_party[opponent_party as usize];
+
+    // find out if this was a schwarz game
+    let tricks_party_zero = tricks_per_party[0];
+    let schwarz_game = tricks_party_zero == 0 || tricks_party_zero == 9;
+
+    if playing_party.is_none() {
+
+        // if nobody played: every party gets the points it reached
+        own_party_points - opponent_party_points
+
+    } else if playing_party == Some(own_party) {
+        if !schwarz_game {
+            if own_party_points >= game_value {
+                // we played and won the game without playing schwarz
+                game_value - opponent_party_points
+            } else {
+                // we played the game, but lost it; no one played schwarz
+                - game_value - opponent_party_points
+            }
+        } else if opponent_party_points == 0 {
+            // we played the game and won schwarz
+            game_value + 2 * game_value
+        } else {
+            // we played the game, but got played schwarz
+            - 2 * game_value - opponent_party_points
+        }
+    } else if playing_party == Some(opponent_party) {
+        if !schwarz_game {
+            if opponent_party_points >= game_value {
+                // the opponents played and won without playing schwarz
+                own_party_points - game_value
+            } else {
+                // the opponents played and lost, nobody played schwarz
+                own_party_points + game_value
+            }
+        } else if opponent_party_points == 0 {
+            // we played the opponents schwarz
+            game_value + 2 * game_value
+        } else {
+            // the opponents played us schwarz
+            - 2 * game_value - game_value
+        }
+    } else {
+        panic!("Invalid playing party")
+    }
+}
+
+
+impl MoveLabel for GameAction {
+    fn label(&self) -> String {
+        match &self.action_type {
+            ActionType::CardPlayed(card) => format!("{:?} {}", self.player, card),
+            action_type => format!("{:?} {:?}", self.player, action_type)
+        }
+    }
+}
+
 impl State<GameAction> for AlphaBetaGameState {
     fn legal_moves(&self) -> Vec<GameAction> {
 
@@ -225,10 +334,7 @@ impl State<GameAction> for AlphaBetaGameState {
 
         // get the legal moves
         let legal_moves = self.legal_moves_unordered();
-        unsafe {
-            // execution is serial, so at most one AlphaBetaGameState will execute this at a time
-            super::COUNT_CHILDREN += legal_moves.len() as u64;
-        }
+        super::COUNT_CHILDREN.fetch_add(legal_moves.len() as u64, Ordering::Relaxed);
         legal_moves
         // order the legal moves according to a heuristic
 
@@ -269,6 +375,18 @@ impl State<GameAction> for AlphaBetaGameState {
             new_points[usize::from(resulting_event.last_action.player.0 % 2)] += points_pair(announced_pair).0
         }
 
+        // move a played card to the table and remove the cards of a finished trick from the EqSets
+        let new_eq_checker = self.eq_checker.as_ref().map(|eq_checker| {
+            let mut new_eq_checker = eq_checker.clone();
+            if let ActionType::CardPlayed(played_card) = &resulting_event.last_action.action_type {
+                new_eq_checker.play_card(played_card);
+                if new_game.state.phase == GamePhase::StartTrick || new_game.state.phase == GamePhase::Ended {
+                    new_eq_checker.finish_trick(&new_game.state.all_tricks.last().unwrap().cards);
+                }
+            }
+            new_eq_checker
+        });
+
         // create the resulting gamestate
         AlphaBetaGameState {
             owning_player: self.owning_player.clone(),
@@ -276,15 +394,14 @@ impl State<GameAction> for AlphaBetaGameState {
             remaining_cards: new_remaining_cards,
             points_per_party: new_points,
             tricks_per_party: new_tricks,
-            playing_party: self.playing_party
+            playing_party: self.playing_party,
+            optimizations: self.optimizations,
+            eq_checker: new_eq_checker
         }
     }
 
     fn is_leaf(&self) -> bool {
-        unsafe {
-            // execution is serial, so at most one AlphaBetaGameState will execute this at a time
-            super::COUNT_NODES += 1;
-        }
+        super::COUNT_NODES.fetch_add(1, Ordering::Relaxed);
         self.game.ended()
     }
 
@@ -304,62 +421,140 @@ impl State<GameAction> for AlphaBetaGameState {
         //     panic!("Tried to evaluate an unfinished game");
         // }
 
+        score(self.points_per_party, self.tricks_per_party, self.playing_party, self.game.state.value.0, self.owning_player.0 % 2)
+    }
+}
 
-        // get the important values
-        let own_party_points = self.points_per_party[(self.owning_player.0 % 2) as usize];
-        let opponent_party_points = self.points_per_party[((self.owning_player.0 + 1) % 2) as usize];
-        let game_value = &self.game.state.value.0;
-
-        // find out if this was a schwarz game
-        let tricks_party_zero = self.tricks_per_party[0];
-        let schwarz_game = tricks_party_zero == 0 || tricks_party_zero == 9;
-
-        // final point difference between the own party and the opponent party
-        let own_party = self.owning_player.0 % 2;
-        let opponent_party = (own_party + 1) % 2;
-        if self.playing_party == None {
-
-            // if nobody played: every party gets the points it reached
-            own_party_points - opponent_party_points
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alpha_beta::alpha_beta_search;
+
+    // the deals in deals/wrong_points.txt and deals/bidding_bug.txt
+    const DEALS: [[&str; 4]; 2] = [
+        [
+            "r-U g-9 g-A r-Z r-K g-7 g-Z s-7 r-7",
+            "s-6 e-O g-K e-A e-6 e-8 g-O s-O r-8",
+            "e-K g-6 s-A e-9 s-9 e-U r-9 r-O g-8",
+            "s-K e-Z r-6 s-8 e-7 g-U r-A s-Z s-U"
+        ],
+        [
+            "s-7 e-9 e-K r-6 g-7 g-U e-8 r-K s-9",
+            "s-8 g-6 e-U r-7 s-6 e-Z g-K g-Z s-K",
+            "s-Z r-O e-6 r-8 s-U r-9 g-9 r-Z g-O",
+            "g-A s-O e-O r-U e-7 g-8 s-A r-A e-A"
+        ]
+    ];
+
+    fn game_from_deal(deal: &[&str; 4]) -> Game {
+        let cards = deal.map(|hand| {
+            hand.split(' ').map(|card| card.parse().unwrap()).collect::<Vec<Card>>()
+        });
+        let player_names = [
+            String::from("Player 1"),
+            String::from("Player 2"),
+            String::from("Player 3"),
+            String::from("Player 4")
+        ];
+        Game::new(String::from("Test Game"), player_names, Some(cards))
+    }
 
-        } else if self.playing_party == Some(own_party) {
-            if !schwarz_game {
-                if own_party_points >= *game_value {
-                    // we played and won the game without playing schwarz
-                    *game_value - opponent_party_points
-                } else {
-                    // we played the game, but lost it; no one played schwarz
-                    - *game_value - opponent_party_points
+    fn position(deal: &[&str; 4], cards_left: usize, variation: usize) -> Game {
+        /*
+            Plays a game with the given deal until the player at turn has only cards_left cards left.
+            Player 1 plays a 120 game, all other decisions are derived from the variation number.
+         */
+
+        let mut game = game_from_deal(deal);
+        let mut step = 0;
+        while !(matches!(game.state.phase, GamePhase::StartTrick | GamePhase::Trick)
+                && game.state.player_at_turn().cards.len() == cards_left) {
+            let legal_actions: Vec<GameAction> = game.legal_actions()
+                .into_iter()
+                .filter(|action| action.action_type != ActionType::UndoRequest)
+                .collect();
+            let action = match game.state.phase {
+                GamePhase::Bidding => legal_actions
+                    .into_iter()
+                    .find(|action| {
+                        if action.player.0 == 0 && game.state.value.0 == 115 {
+                            action.action_type == ActionType::NewBid(120)
+                        } else {
+                            action.action_type == ActionType::StopBidding
+                        }
+                    })
+                    .unwrap(),
+                GamePhase::Raising => legal_actions
+                    .into_iter()
+                    .find(|action| matches!(action.action_type, ActionType::CardPlayed(_)))
+                    .unwrap(),
+                _ => {
+                    let index = (variation + 7 * step) % legal_actions.len();
+                    legal_actions.into_iter().nth(index).unwrap()
                 }
-            } else {
-                if opponent_party_points == 0 {
-                    // we played the game and won schwarz
-                    *game_value + 2 * *game_value
-                } else {
-                    // we played the game, but got played schwarz
-                    - 2 * game_value - opponent_party_points
+            };
+            game = game.apply_action(action).unwrap();
+            step += 1;
+        }
+        game
+    }
+
+    #[test]
+    fn test_card_equivalence_keeps_values() {
+        for deal in &DEALS {
+            for variation in 0..4 {
+                for cards_left in [2, 3] {
+                    let game = position(deal, cards_left, variation);
+                    let owning_player = game.state.player_at_turn.clone();
+                    let with_optimization = AlphaBetaGameState::with_optimizations(
+                        owning_player.clone(),
+                        game.clone(),
+                        SearchOptimizations { card_equivalence: true, ..SearchOptimizations::none() }).unwrap();
+                    let without_optimization = AlphaBetaGameState::with_optimizations(
+                        owning_player,
+                        game,
+                        SearchOptimizations::none()).unwrap();
+                    assert_eq!(
+                        alpha_beta_search(with_optimization, None).1,
+                        alpha_beta_search(without_optimization, None).1
+                    );
                 }
             }
-        } else if self.playing_party == Some(opponent_party) {
-            if !schwarz_game {
-                if opponent_party_points >= *game_value {
-                    // the opponents played and won without playing schwarz
-                    own_party_points - *game_value
-                } else {
-                    // the opponents played and lost, nobody played schwarz
-                    own_party_points + *game_value
-                }
-            } else {
-                if opponent_party_points == 0 {
-                    // we played the opponents schwarz
-                    *game_value + 2 * *game_value
-                } else {
-                    // the opponents played us schwarz
-                    - 2 * game_value - *game_value
-                }
+        }
+    }
+
+    #[test]
+    fn test_search_from_position() {
+        /*
+            The search on a position built from a game must give the same result as the search on the game itself.
+         */
+
+        let record = crate::simulate_game::play_game(
+            &mut (0..4).map(|place| Box::new(crate::cheater::CheaterV1::new("Cheater", place, 1)) as Box<dyn crate::ai::MarjapussiCheater>).collect::<Vec<_>>(),
+            [0, 1, 2, 3].map(|place| format!("Player {}", place + 1)),
+            Some(crate::deal::deal_from_seed(11)),
+            crate::log::LogFilter::off(),
+            &mut []
+        ).unwrap().record(None);
+
+        let mut game = Game::new(record.game_name.clone(), record.player_names.clone(), Some(record.deal.clone()));
+        let mut positions = 0;
+        for action in &record.actions {
+            if let Some(position) = Position::from_game(&game) {
+                let notation = crate::notation::position::format_position(&position);
+                let parsed = crate::notation::position::parse_position(&notation).unwrap();
+                assert_eq!(parsed, position);
+
+                let owning_player = game.state.player_at_turn.clone();
+                let from_game = alpha_beta_search(AlphaBetaGameState::new(owning_player.clone(), game.clone()).unwrap(), Some(3));
+                let from_position = alpha_beta_search(AlphaBetaGameState::from_position(owning_player, &parsed).unwrap(), Some(3));
+                // moves with the same value may be ordered differently, so only the values have to match
+                assert_eq!(from_position.1, from_game.1, "different values in {}", notation);
+                assert!(game.legal_actions().contains(&from_position.0), "{:?} is illegal in {} {:?}", from_position.0, notation, game.legal_actions());
+                positions += 1;
             }
-        } else {
-            panic!("Invalid playing party")
+            game = game.apply_action(action.clone()).unwrap();
         }
+        assert!(positions >= 36);
     }
-}
\ No newline at end of file
+}
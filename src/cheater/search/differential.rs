@@ -0,0 +1,325 @@
+/*
+    Differential tests for the pruning in AlphaBetaGameState::legal_moves_unordered.
+
+    Random deals are played to random mid-game positions, or to the raising before the first card, where the hands are
+    cut down to a few cards. At every position, the root value of alpha_beta_search is compared to the value of a plain
+    minimax search that neither prunes branches nor sorts out any moves: it tries every legal action of the game except
+    UndoRequest, so raising (NewBid) is part of the comparison as well.
+    This is done for every optimization flag on its own and for all flags together.
+    Raising is not part of the search (CheaterV1 never raises), so in the raising the test takes this decision itself:
+    the best of the search values of playing a card and of every raise is compared.
+
+    If a position fails, it is shrunk as long as the failure persists: a card is taken out of every hand, or an action is followed.
+    So the report shows the smallest position (and the deal and steps that lead to it) that still fails.
+    Positions with removed cards are shortened games: the search handles them like any other position,
+    their hands are just smaller than the number of tricks implies.
+ */
+
+use super::{AlphaBetaGameState, SearchOptimizations};
+use crate::alpha_beta::{alpha_beta_search, State};
+use crate::deal::{deal_from_seed, SeededRng};
+use crate::notation::format_deal;
+
+use marjapussi::game::cards::Card;
+use marjapussi::game::gameevent::{ActionType, GameAction};
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::Game;
+
+use std::collections::HashMap;
+
+
+// every raise is searched on its own, so the hands in the raising are kept small
+const RAISING_CARDS: usize = 2;
+
+
+// a step on the way to a test position: an action in the game, or a card that is taken out of the hand that holds it
+#[derive(Clone, PartialEq)]
+enum Step {
+    Action(GameAction),
+    RemoveCard(Card)
+}
+
+// a position that can be reproduced from its deal and the steps leading to it
+#[derive(Clone)]
+struct TestPosition {
+    deal: [Vec<Card>; 4],
+    steps: Vec<Step>
+}
+
+impl TestPosition {
+    fn game(&self) -> Game {
+        let mut game = new_game(self.deal.clone());
+        for step in &self.steps {
+            game = apply_step(game, step);
+        }
+        game
+    }
+
+    fn followed_by(&self, step: Step) -> TestPosition {
+        let mut position = self.clone();
+        position.steps.push(step);
+        position
+    }
+
+    fn report(&self) -> String {
+        let game = self.game();
+        let mut report = format!("deal:\n{}\nsteps:\n", format_deal(&self.deal));
+        for step in &self.steps {
+            report += &match step {
+                Step::Action(action) => format!("  {:?}: {:?}\n", action.player, action.action_type),
+                Step::RemoveCard(card) => format!("  remove {:?}\n", card)
+            };
+        }
+        report += &format!("position: {:?}, player at turn {:?}, trump {:?}, current trick {:?}\n",
+                           game.state.phase, game.state.player_at_turn, game.state.trump, game.state.current_trick);
+        for player in &game.state.players {
+            report += &format!("{:?}: {:?}\n", player.place_at_table, player.cards);
+        }
+        report
+    }
+}
+
+
+fn new_game(deal: [Vec<Card>; 4]) -> Game {
+    let player_names = [
+        String::from("Player 1"),
+        String::from("Player 2"),
+        String::from("Player 3"),
+        String::from("Player 4")
+    ];
+    Game::new(String::from("Differential Test"), player_names, Some(deal))
+}
+
+fn apply_step(mut game: Game, step: &Step) -> Game {
+    match step {
+        Step::Action(action) => game.apply_action(action.clone()).unwrap(),
+        Step::RemoveCard(card) => {
+            for player in game.state.players.iter_mut() {
+                player.cards.retain(|held| held != card);
+            }
+            // the game stores its legal actions, so they have to follow the smaller hand
+            game.legal_actions = game.legal_actions();
+            game
+        }
+    }
+}
+
+fn random_position(rng: &mut SeededRng, cards_left: usize) -> TestPosition {
+    /*
+        Plays random actions until the player at turn has cards_left cards left in the cardplay phase.
+        In every fourth game, the game stops at the raising instead and every hand is cut down to at most RAISING_CARDS random cards.
+     */
+
+    let stop_at_raising = rng.below(4) == 0;
+    let mut position = TestPosition { deal: deal_from_seed(rng.next_u64()), steps: vec![] };
+    let mut game = position.game();
+    loop {
+        if stop_at_raising && game.state.phase == GamePhase::Raising {
+            for place in 0..4 {
+                while game.state.players[place].cards.len() > cards_left.min(RAISING_CARDS) {
+                    let hand = &game.state.players[place].cards;
+                    let step = Step::RemoveCard(hand[rng.below(hand.len())].clone());
+                    game = apply_step(game, &step);
+                    position.steps.push(step);
+                }
+            }
+            return position;
+        }
+        if matches!(game.state.phase, GamePhase::StartTrick | GamePhase::Trick)
+                && game.state.player_at_turn().cards.len() == cards_left {
+            return position;
+        }
+
+        let legal_actions = all_actions(&game);
+        let action = match game.state.phase {
+            // keep the game values low, so that most games are played
+            GamePhase::Bidding => {
+                if rng.below(3) == 0 || game.state.value.0 >= 200 {
+                    legal_actions[0].clone()
+                } else {
+                    legal_actions[1].clone()
+                }
+            },
+            // never raise
+            GamePhase::Raising => {
+                let cards: Vec<GameAction> = legal_actions
+                    .into_iter()
+                    .filter(|action| matches!(action.action_type, ActionType::CardPlayed(_)))
+                    .collect();
+                cards[rng.below(cards.len())].clone()
+            },
+            _ => legal_actions[rng.below(legal_actions.len())].clone()
+        };
+        game = game.apply_action(action.clone()).unwrap();
+        position.steps.push(Step::Action(action));
+    }
+}
+
+
+fn reference_minimax(state: &AlphaBetaGameState, values: &mut HashMap<String, i32>) -> i32 {
+    /*
+        Plain minimax over all legal actions of the game (except UndoRequest), without any pruning.
+        The moves are taken from the game itself, not from the search state, so nothing that the search sorts out is missing.
+        The value of every visited state is stored under the complete state of the game and the score,
+        so the same state that is reached with another order of moves is not searched again.
+     */
+
+    if state.is_leaf() {
+        return state.evaluate();
+    }
+    let game_state = &state.game.state;
+    // the debug output of the players only shows the number of cards
+    let players: Vec<String> = game_state.players.iter().map(|player| format!("{:?} {:?}", player.cards, player.trump)).collect();
+    let key = format!("{:?} {:?} {:?} {:?} {:?} {:?} {:?} {} {:?} {:?}",
+                      game_state.phase, players, game_state.current_trick, game_state.trump, game_state.trump_called,
+                      game_state.value, game_state.player_at_turn, game_state.all_tricks.len(), state.points_per_party, state.tricks_per_party);
+    if let Some(value) = values.get(&key) {
+        return *value;
+    }
+    let child_values = all_actions(&state.game)
+        .into_iter()
+        .map(|next_move| reference_minimax(&state.apply_move(&next_move), values));
+    let value = if state.is_maximizing() {
+        child_values.max().unwrap()
+    } else {
+        child_values.min().unwrap()
+    };
+    values.insert(key, value);
+    value
+}
+
+fn all_actions(game: &Game) -> Vec<GameAction> {
+    game.legal_actions()
+        .into_iter()
+        .filter(|action| action.action_type != ActionType::UndoRequest)
+        .collect()
+}
+
+fn optimization_variants() -> Vec<(&'static str, SearchOptimizations)> {
+    vec![
+        ("none", SearchOptimizations::none()),
+        ("drop_useless_questions", SearchOptimizations { drop_useless_questions: true, ..SearchOptimizations::none() }),
+        ("card_equivalence", SearchOptimizations { card_equivalence: true, ..SearchOptimizations::none() }),
+        ("all", SearchOptimizations::default())
+    ]
+}
+
+fn find_mismatch(game: &Game) -> Option<String> {
+    /*
+        Compares the search with every optimization variant to the reference.
+        Returns a description of the first mismatch.
+     */
+
+    let owning_player = game.state.player_at_turn.clone();
+    let reference_state = AlphaBetaGameState::with_optimizations(owning_player.clone(), game.clone(), SearchOptimizations::none()).unwrap();
+    let reference_value = reference_minimax(&reference_state, &mut HashMap::new());
+    for (name, optimizations) in optimization_variants() {
+        let state = AlphaBetaGameState::with_optimizations(owning_player.clone(), game.clone(), optimizations).unwrap();
+        // the player who raises plays the first card afterwards, so the search starts again after every raise
+        let raise_values: Vec<i32> = all_actions(game)
+            .iter()
+            .filter(|action| matches!(action.action_type, ActionType::NewBid(_)))
+            .map(|raise| alpha_beta_search(state.apply_move(raise), None).1)
+            .collect();
+        let (_, card_value) = alpha_beta_search(state, None);
+        let value = raise_values.into_iter().fold(card_value, i32::max);
+        if value != reference_value {
+            return Some(format!("optimizations {}: alpha-beta value {} != reference value {}", name, value, reference_value));
+        }
+    }
+    None
+}
+
+fn smaller_positions(position: &TestPosition) -> Vec<TestPosition> {
+    /*
+        The candidates for shrinking: first the positions with one card less in every hand (if every hand has two cards or more),
+        then the positions after one of the legal actions (if that does not end the game).
+     */
+
+    let game = position.game();
+    let mut positions = vec![];
+    if game.state.players.iter().all(|player| player.cards.len() >= 2) {
+        positions.push(position.clone());
+        for player in &game.state.players {
+            positions = positions
+                .into_iter()
+                .flat_map(|smaller| player.cards.iter().map(move |card| smaller.followed_by(Step::RemoveCard(card.clone()))))
+                .collect();
+        }
+    }
+
+    for action in all_actions(&game) {
+        if game.apply_action(action.clone()).unwrap().ended() {
+            continue;
+        }
+        positions.push(position.followed_by(Step::Action(action)));
+    }
+    positions
+}
+
+fn shrink<F>(mut position: TestPosition, find_failure: F) -> (TestPosition, String)
+where F: Fn(&Game) -> Option<String> {
+    /*
+        Replaces a failing position by a smaller one as long as it still fails.
+        Every step removes cards or follows an action, so the shrinking ends.
+     */
+
+    let mut failure = find_failure(&position.game()).expect("Only failing positions can be shrunk");
+    'shrinking: loop {
+        for smaller in smaller_positions(&position) {
+            if let Some(smaller_failure) = find_failure(&smaller.game()) {
+                position = smaller;
+                failure = smaller_failure;
+                continue 'shrinking;
+            }
+        }
+        return (position, failure);
+    }
+}
+
+fn check_random_positions(first_seed: u64, count: u64, cards_left: usize) {
+    for seed in first_seed..first_seed + count {
+        let position = random_position(&mut SeededRng::new(seed), cards_left);
+        if find_mismatch(&position.game()).is_some() {
+            let (shrunk, mismatch) = shrink(position, find_mismatch);
+            panic!("Search pruning changed the result (seed {}): {}\n{}", seed, mismatch, shrunk.report());
+        }
+    }
+}
+
+
+#[test]
+fn test_pruning_two_cards_left() {
+    check_random_positions(0, 40, 2);
+}
+
+#[test]
+fn test_pruning_three_cards_left() {
+    check_random_positions(1000, 8, 3);
+}
+
+#[test]
+fn test_shrinking() {
+    let card_count = |game: &Game| game.state.players.iter().map(|player| player.cards.len()).sum::<usize>();
+
+    // simulate a failure that occurs as long as there are five cards or more
+    let find_failure = |game: &Game| (card_count(game) >= 5).then(|| String::from("simulated failure"));
+    let position = random_position(&mut SeededRng::new(7), 2);
+    let (shrunk, failure) = shrink(position.clone(), find_failure);
+    assert_eq!(failure, "simulated failure");
+    assert_eq!(card_count(&shrunk.game()), 5);
+    assert!(shrunk.steps.starts_with(&position.steps));
+    assert!(shrunk.report().starts_with("deal:\n0: ["));
+
+    // simulate a failure in the raising, which can only be shrunk by removing cards
+    let raising = |game: &Game| game.state.phase == GamePhase::Raising;
+    let position = (0..)
+        .map(|seed| random_position(&mut SeededRng::new(seed), 3))
+        .find(|position| raising(&position.game()))
+        .unwrap();
+    assert!(position.game().state.players.iter().all(|player| player.cards.len() == RAISING_CARDS));
+    let (shrunk, _) = shrink(position, |game: &Game| raising(game).then(|| String::from("simulated failure")));
+    let game = shrunk.game();
+    assert!(raising(&game));
+    assert!(game.state.players.iter().all(|player| player.cards.len() == 1));
+}
@@ -1,242 +1,396 @@
-// use std::{collections::{BTreeSet, HashMap, HashSet}, hash::Hash};
-
-// use serde_json;
-
-// use marjapussi::game::{cards::{Card, Suit}, gameevent::{ActionType, GameAction}, Game};
-
-// type EqSetID = u8;
-// type Owner = u8;
-
-// #[derive(Debug)]
-// struct EqSet {
-//     id: EqSetID,
-//     owner: Owner,
-//     cards: BTreeSet<Card>,
-//     prev: Option<EqSetID>,
-//     next: Option<EqSetID>
-// }
-
-// struct EqChecker {
-//     card_to_id: HashMap<String, EqSetID>,
-//     id_to_eq_set: HashMap<EqSetID, EqSet>
-// }
-
-// impl EqChecker {
-//     fn reduce_legal_actions(&self, legal_actions: Vec<GameAction>, trick: Vec<Card>, trump: Option<Suit>) -> Vec<GameAction> {
-//         /*
-//             Sorts out redundant cardplay moves.
-//          */
-        
-//         let mut reduced_legal_actions = vec![];
-//         let mut already_matched: HashSet<EqSetID> = HashSet::new();
-
-//         for action in legal_actions {
-
-//             // check if "action" is a cardplay action
-//             // if so, check if it is redundant and drop or keep the action accordingly
-//             // else, keep the action
-//             if let ActionType::CardPlayed(card) = &action.action_type {
-
-//                 // get the EqualitySet corresponding to the card
-//                 let eq_set_id = self.card_to_id
-//                     .get(&serde_json::to_string(card).unwrap())
-//                     .expect("Card could not be mapped to an EqSetID");
-                
-//                 // check if an action from this EqSet was already added to the reduced_legal_actions
-//                 if already_matched.insert(*eq_set_id) {
-//                     // this EqSet was not matched yet
-//                     let unmatched_eq_set = self.id_to_eq_set
-//                         .get(eq_set_id)
-//                         .expect("EqSetID could not me mapped to an EqSet");
-//                     // if the cards in the EqSet are standing, play the highest one
-//                     // else, play the lowest one
-//                     if self.standing_in_suit(unmatched_eq_set) {
-
-//                     };
-//                 }
-
-
-//                 reduced_legal_actions.push(action);
-//             } else {
-//                 // this action is not a cardplay action, thus cannot be sorted out
-//                 reduced_legal_actions.push(action);
-//             }
-//         }
-
-//         unimplemented!()
-//     }
-
-//     fn standing(&self, eq_set: &EqSet, trick: Vec<Card>, trump: Option<Suit>) -> bool {
-//         /*
-//             Checks if the cards in the EqSet would safely win the current trick.
-//          */
-
-//         let own_suit = eq_set.cards.first().unwrap().suit;
-//         let standing_in_suit = if let Some(next) = eq_set.next {
-//             if self.id_to_eq_set.get(&next).unwrap().cards.first().unwrap().suit == own_suit {
-//                 // higher EqSet in the same suit exists
-//                 false
-//             } else {
-//                 // no higher EqSet in the same suit
-//                 true
-//             }
-//         } else {
-//             // no higher EqSet
-//             true
-//         };
-//         if let Some(trump_suit) = trump {
-//             if own_suit == trump_suit {
-//                 true
-//             } else {
-//                 false
-//             }
-//         } else {
-            
-//         }
-//     }
-// }
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-// // #[derive(Debug)]
-// // struct EqCheckerOld {
-// //     card_to_eq_set: HashMap<String, Weak<RefCell<EqSet>>>,
-// //     eq_sets: Vec<EqSet>
-// // }
-
-// // impl EqCheckerOld {
-
-// //     fn get_eq_set(&self, card: Card) -> EqSetID {
-// //         /*
-// //             Returns the ID of the EqSet the card is in.
-// //          */
-// //         self.card_to_eq_set
-// //             .get(&serde_json::to_string(&card).unwrap())
-// //             .unwrap()
-// //             .upgrade()
-// //             .unwrap()
-// //             .borrow()
-// //             .id
-// //     }
-    
-// //     fn remove_card(&mut self, card: Card) {
-// //         /*
-// //             Removes the given card from the EqCheckerOld.
-// //          */
-
-// //         // remove the card from the [card -> eq_set] translator
-// //         // and get the eq_set the card is in
-// //         let remaining_eq_set_rc = self.card_to_eq_set
-// //             .remove(&serde_json::to_string(&card).unwrap())
-// //             .expect("Card that should be removed had no EqSet assigned to it")
-// //             .upgrade()
-// //             .expect("Card that should be removed had an invalid Pointer to an EqSet assigned to it");
-
-// //         let mut remaining_eq_set = remaining_eq_set_rc.borrow_mut();
-
-// //         // remove the card from the eq_set
-// //         remaining_eq_set
-// //             .cards
-// //             .take(&card)
-// //             .expect("Card that should be removed was not in its EqSet");
-
-// //         if remaining_eq_set.cards.is_empty() {
-// //             self.remove_eq_set(Rc::clone(&remaining_eq_set_rc));
-// //         }
-// //     }
-    
-// //     fn remove_eq_set(&mut self, eq_set_rc: Rc<RefCell<EqSet>>) {
-// //         /*
-// //             Removes an EqSet from the EqCheckerOld.
-// //             If the neighbors of the removed set have the same owner, they are merged.
-// //             Else, they are linked to each other.
-// //          */
-
-// //         // get a mutable borrow of the EqSet
-// //         let mut eq_set = eq_set_rc.borrow_mut();
-    
-// //         // get references to the previous and next EqSet
-// //         // it the references are present, check if they are still valid
-// //         let prev_option = eq_set
-// //             .prev
-// //             .and_then(|prev| {
-// //                 Some(prev
-// //                         .upgrade()
-// //                         .expect("Pointer to previous EqSet was present but invalid"))
-// //             });
-// //         let next_option = eq_set
-// //             .next
-// //             .and_then(|next| {
-// //                 Some(next
-// //                         .upgrade()
-// //                         .expect("Pointer to next EqSet was present but invalid"))
-// //             });
-        
-// //         // remove the EqSet and link prev and next as required
-// //         match (prev_option, next_option) {
-// //             (Some(prev_rc), Some(next_rc)) => {
-// //                 if prev_rc.borrow().owner == next_rc.borrow().owner {
-// //                     // merge the next EqSet into the previous
-// //                     let mut merged = prev_rc.borrow_mut();
-// //                     let next = next_rc.borrow();
-// //                     merged.cards.extend(next.cards.clone());
-        
-// //                     // make all cards from "next" point to the merged EqSet
-// //                     for card in &next.cards {
-// //                         self.card_to_eq_set.insert(
-// //                             serde_json::to_string(&card).unwrap(), 
-// //                             Rc::downgrade(&prev_rc));
-// //                     }
-        
-// //                     // update the "next" pointer of the merged set
-// //                     // if "next" is present: update its "prev" pointer
-// //                     merged.next = next.next.clone();
-// //                 } else {
-// //                     // link 
-// //                 }
-// //             }
-// //             _ => {
-// //                 // Just remove the EqSet
-// //                 if let Some(prev) = EqSet.prev.as_ref().and_then(|w| w.upgrade()) {
-// //                     prev.borrow_mut().next = EqSet.next.clone();
-// //                 }
-// //                 if let Some(next) = &EqSet.next {
-// //                     next.borrow_mut().prev = EqSet.prev.clone();
-// //                 }
-// //             }
-// //         }
-    
-// //         self.EqSets.retain(|s| !Rc::ptr_eq(s, &EqSet_rc));
-// //     }
-// // }
\ No newline at end of file
+use std::collections::{BTreeMap, BTreeSet};
+
+use marjapussi::game::cards::{high_card, is_higher_card, Card, Suit};
+use marjapussi::game::gameevent::{ActionType, GameAction};
+use marjapussi::game::points::points_card;
+use marjapussi::game::Game;
+
+type EqSetID = u8;
+type Owner = u8;
+
+// cards lying in the current (unfinished) trick belong to the table instead of a player
+const TABLE: Owner = 4;
+
+
+// a set of cards that are equally strong in cardplay
+// all cards in the set are held by the same player, have the same suit and are worth the same amount of points,
+// and there is no card of another owner between them
+#[derive(Debug, Clone)]
+struct EqSet {
+    owner: Owner,
+    cards: BTreeSet<Card>,
+    prev: Option<EqSetID>,
+    next: Option<EqSetID>
+}
+
+impl EqSet {
+    fn suit(&self) -> Suit {
+        self.cards.first().unwrap().suit
+    }
+
+    fn points(&self) -> i32 {
+        points_card(self.cards.first().unwrap().clone()).0
+    }
+
+    fn can_merge_with(&self, other: &EqSet) -> bool {
+        // cards in the trick are never played again, so they don't need to be grouped
+        // O and K never get merged, since they have different points (this matters for the pairs)
+        self.owner != TABLE &&
+        self.owner == other.owner &&
+        self.suit() == other.suit() &&
+        self.points() == other.points()
+    }
+}
+
+
+// keeps track of the EqSets of all cards that are still in the game (in the hands or in the current trick)
+// the EqSets form a doubly linked list that is sorted ascendingly by the cards
+#[derive(Debug, Clone)]
+pub struct EqChecker {
+    card_to_id: BTreeMap<Card, EqSetID>,
+    id_to_eq_set: BTreeMap<EqSetID, EqSet>,
+    next_id: EqSetID
+}
+
+impl EqChecker {
+    pub fn new(game: &Game) -> Self {
+        /*
+            Builds the EqSets for all cards in the players' hands and the current trick.
+         */
+
+        let mut cards_with_owner: Vec<(Card, Owner)> = vec![];
+        for player in &game.state.players {
+            for card in &player.cards {
+                cards_with_owner.push((card.clone(), player.place_at_table.0));
+            }
+        }
+        // a trick with four cards is already finished and only kept until the next card is played
+        if game.state.current_trick.len() < 4 {
+            for card in &game.state.current_trick {
+                cards_with_owner.push((card.clone(), TABLE));
+            }
+        }
+        cards_with_owner.sort();
+
+        let mut eq_checker = EqChecker {
+            card_to_id: BTreeMap::new(),
+            id_to_eq_set: BTreeMap::new(),
+            next_id: 0
+        };
+
+        // every card gets its own EqSet first, then neighbors are merged where possible
+        let mut last_id = None;
+        for (card, owner) in cards_with_owner {
+            let id = eq_checker.insert_eq_set(owner, BTreeSet::from([card]), last_id, None);
+            if let Some(prev) = last_id {
+                eq_checker.id_to_eq_set.get_mut(&prev).unwrap().next = Some(id);
+            }
+            last_id = Some(id);
+        }
+        let ids: Vec<EqSetID> = eq_checker.id_to_eq_set.keys().copied().collect();
+        for id in ids {
+            // the set might have been merged into its predecessor already
+            while let Some(next) = eq_checker.id_to_eq_set.get(&id).and_then(|eq_set| eq_set.next) {
+                if !eq_checker.try_merge(id, next) {
+                    break;
+                }
+            }
+        }
+        eq_checker
+    }
+
+    pub fn reduce_legal_actions(&self, legal_actions: Vec<GameAction>, trick: &[Card], trump: Option<Suit>) -> Vec<GameAction> {
+        /*
+            Sorts out redundant cardplay moves.
+            From every EqSet, only one card is kept: the highest if the set is standing, else the lowest.
+            Since all cards in an EqSet are equivalent, this does not change the value of the search.
+         */
+
+        // collect the legal cards of every EqSet
+        let mut legal_cards_per_set: BTreeMap<EqSetID, Vec<Card>> = BTreeMap::new();
+        for action in &legal_actions {
+            if let ActionType::CardPlayed(card) = &action.action_type {
+                let eq_set_id = self.card_to_id
+                    .get(card)
+                    .expect("Card could not be mapped to an EqSetID");
+                legal_cards_per_set.entry(*eq_set_id).or_default().push(card.clone());
+            }
+        }
+
+        // choose the card that represents each EqSet
+        let mut chosen_cards: BTreeSet<Card> = BTreeSet::new();
+        for (eq_set_id, cards) in legal_cards_per_set {
+            let eq_set = self.id_to_eq_set
+                .get(&eq_set_id)
+                .expect("EqSetID could not be mapped to an EqSet");
+            let representative = if self.standing(eq_set, trick, trump) {
+                cards.into_iter().max()
+            } else {
+                cards.into_iter().min()
+            };
+            chosen_cards.insert(representative.unwrap());
+        }
+
+        // keep all actions that are no cardplay actions and the chosen cardplay actions
+        legal_actions
+            .into_iter()
+            .filter(|action| {
+                match &action.action_type {
+                    ActionType::CardPlayed(card) => chosen_cards.contains(card),
+                    _ => true
+                }
+            })
+            .collect()
+    }
+
+    fn standing(&self, eq_set: &EqSet, trick: &[Card], trump: Option<Suit>) -> bool {
+        /*
+            Checks if the cards in the EqSet would safely win the current trick.
+         */
+
+        let own_card = eq_set.cards.first().unwrap();
+        let own_suit = eq_set.suit();
+
+        // the cards have to beat the card that currently wins the trick
+        if let Some(winning_card) = high_card(trick.iter().collect(), trump) {
+            if !is_higher_card(own_card, winning_card, trump) {
+                return false;
+            }
+        }
+
+        // no other player may hold a higher card in the same suit
+        let mut next_option = eq_set.next;
+        while let Some(next_id) = next_option {
+            let next = self.id_to_eq_set.get(&next_id).unwrap();
+            if next.suit() != own_suit {
+                break;
+            }
+            if next.owner != TABLE && next.owner != eq_set.owner {
+                return false;
+            }
+            next_option = next.next;
+        }
+
+        // if the cards are no trumps, no other player may hold a trump
+        match trump {
+            Some(trump_suit) if trump_suit != own_suit => {
+                !self.id_to_eq_set
+                    .values()
+                    .any(|other| {
+                        other.owner != TABLE && other.owner != eq_set.owner && other.suit() == trump_suit
+                    })
+            },
+            _ => true
+        }
+    }
+
+    pub fn play_card(&mut self, card: &Card) {
+        /*
+            Moves a played card from the player's hand to the table.
+            If the card was in the middle of an EqSet, the set is split, since the card in the trick lies between the remaining parts.
+         */
+
+        let eq_set_id = *self.card_to_id
+            .get(card)
+            .expect("Played card could not be mapped to an EqSetID");
+        let eq_set = self.id_to_eq_set.get_mut(&eq_set_id).unwrap();
+        let upper_cards = eq_set.cards.split_off(card);
+        let mut upper_cards_iter = upper_cards.into_iter();
+        upper_cards_iter.next();
+        let upper_cards: BTreeSet<Card> = upper_cards_iter.collect();
+        let owner = eq_set.owner;
+        let old_next = eq_set.next;
+
+        // the played card gets its own set
+        // if there are no lower cards left, the old set is reused for the played card
+        let table_id = if eq_set.cards.is_empty() {
+            eq_set.cards.insert(card.clone());
+            eq_set.owner = TABLE;
+            eq_set_id
+        } else {
+            let table_id = self.insert_eq_set(TABLE, BTreeSet::from([card.clone()]), Some(eq_set_id), old_next);
+            self.link(Some(eq_set_id), Some(table_id));
+            self.link(Some(table_id), old_next);
+            table_id
+        };
+
+        // the higher cards form another new set
+        if !upper_cards.is_empty() {
+            let upper_id = self.insert_eq_set(owner, upper_cards, Some(table_id), old_next);
+            self.link(Some(table_id), Some(upper_id));
+            self.link(Some(upper_id), old_next);
+        }
+    }
+
+    pub fn finish_trick(&mut self, trick: &[Card]) {
+        /*
+            Removes the cards of a finished trick.
+            If the neighbors of a removed card can be merged (e.g. same owner and suit), they are merged.
+         */
+
+        for card in trick {
+            self.remove_card(card);
+        }
+    }
+
+    fn remove_card(&mut self, card: &Card) {
+        let eq_set_id = self.card_to_id
+            .remove(card)
+            .expect("Card that should be removed had no EqSet assigned to it");
+        let eq_set = self.id_to_eq_set.get_mut(&eq_set_id).unwrap();
+        eq_set.cards.remove(card);
+        if eq_set.cards.is_empty() {
+            self.remove_eq_set(eq_set_id);
+        }
+    }
+
+    fn remove_eq_set(&mut self, eq_set_id: EqSetID) {
+        /*
+            Removes an EqSet from the EqChecker.
+            If the neighbors of the removed set can be merged, they are merged.
+            Else, they are linked to each other.
+         */
+
+        let eq_set = self.id_to_eq_set
+            .remove(&eq_set_id)
+            .expect("EqSet that should be removed does not exist");
+        self.link(eq_set.prev, eq_set.next);
+        if let (Some(prev), Some(next)) = (eq_set.prev, eq_set.next) {
+            self.try_merge(prev, next);
+        }
+    }
+
+    fn try_merge(&mut self, prev_id: EqSetID, next_id: EqSetID) -> bool {
+        /*
+            Merges the EqSet "next" into its predecessor "prev", if they can be merged.
+            Returns whether the sets were merged.
+         */
+
+        let prev = self.id_to_eq_set.get(&prev_id).unwrap();
+        let next = self.id_to_eq_set.get(&next_id).unwrap();
+        if !prev.can_merge_with(next) {
+            return false;
+        }
+
+        let next = self.id_to_eq_set.remove(&next_id).unwrap();
+        for card in &next.cards {
+            self.card_to_id.insert(card.clone(), prev_id);
+        }
+        self.id_to_eq_set.get_mut(&prev_id).unwrap().cards.extend(next.cards);
+        self.link(Some(prev_id), next.next);
+        true
+    }
+
+    fn insert_eq_set(&mut self, owner: Owner, cards: BTreeSet<Card>, prev: Option<EqSetID>, next: Option<EqSetID>) -> EqSetID {
+        let id = self.next_id;
+        self.next_id += 1;
+        for card in &cards {
+            self.card_to_id.insert(card.clone(), id);
+        }
+        self.id_to_eq_set.insert(id, EqSet { owner, cards, prev, next });
+        id
+    }
+
+    fn link(&mut self, prev: Option<EqSetID>, next: Option<EqSetID>) {
+        if let Some(prev_id) = prev {
+            self.id_to_eq_set.get_mut(&prev_id).unwrap().next = next;
+        }
+        if let Some(next_id) = next {
+            self.id_to_eq_set.get_mut(&next_id).unwrap().prev = prev;
+        }
+    }
+
+    #[cfg(test)]
+    fn eq_set_of(&self, card: &str) -> Vec<Card> {
+        let id = self.card_to_id.get(&card.parse().unwrap()).unwrap();
+        self.id_to_eq_set.get(id).unwrap().cards.iter().cloned().collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cards(cards: &str) -> Vec<Card> {
+        cards.split(' ').map(|card| card.parse().unwrap()).collect()
+    }
+
+    fn test_game() -> Game {
+        let player_names = [
+            String::from("Player 1"),
+            String::from("Player 2"),
+            String::from("Player 3"),
+            String::from("Player 4")
+        ];
+        let deal = [
+            cards("g-6 g-7 g-8 g-O g-K e-6 e-8 s-A r-U"),
+            cards("g-9 g-U e-7 e-9 s-6 s-7 s-8 r-6 r-A"),
+            cards("g-Z g-A e-U e-O e-K s-9 s-U r-7 r-8"),
+            cards("e-Z e-A s-O s-K s-Z r-9 r-O r-K r-Z")
+        ];
+        Game::new(String::from("Test Game"), player_names, Some(deal))
+    }
+
+    fn card_action(player: u8, card: &str) -> GameAction {
+        GameAction {
+            action_type: ActionType::CardPlayed(card.parse().unwrap()),
+            player: marjapussi::game::player::PlaceAtTable(player)
+        }
+    }
+
+    #[test]
+    fn test_eq_sets() {
+        let eq_checker = EqChecker::new(&test_game());
+        // adjacent low cards of the same player are equivalent
+        assert_eq!(eq_checker.eq_set_of("g-7"), cards("g-6 g-7 g-8"));
+        assert_eq!(eq_checker.eq_set_of("s-7"), cards("s-6 s-7 s-8"));
+        // cards of another player in between prevent the grouping
+        assert_eq!(eq_checker.eq_set_of("e-6"), cards("e-6"));
+        // cards with different points are never grouped
+        assert_eq!(eq_checker.eq_set_of("g-O"), cards("g-O"));
+        assert_eq!(eq_checker.eq_set_of("r-8"), cards("r-7 r-8"));
+        assert_eq!(eq_checker.eq_set_of("r-Z"), cards("r-Z"));
+    }
+
+    #[test]
+    fn test_reduce_legal_actions() {
+        let eq_checker = EqChecker::new(&test_game());
+        let legal_actions: Vec<GameAction> = ["g-6", "g-7", "g-8", "g-O", "e-6", "e-8"]
+            .into_iter()
+            .map(|card| card_action(0, card))
+            .collect();
+        let reduced = eq_checker.reduce_legal_actions(legal_actions, &[], None);
+        // g-9 (player 1) beats the green low cards, so the lowest one is kept
+        assert_eq!(reduced, vec![
+            card_action(0, "g-6"),
+            card_action(0, "g-O"),
+            card_action(0, "e-6"),
+            card_action(0, "e-8")
+        ]);
+    }
+
+    #[test]
+    fn test_merge_after_trick() {
+        let mut eq_checker = EqChecker::new(&test_game());
+        assert_eq!(eq_checker.eq_set_of("e-6"), cards("e-6"));
+        assert_eq!(eq_checker.eq_set_of("e-8"), cards("e-8"));
+
+        // e-7 lies between e-6 and e-8 and is played
+        // while the trick is not finished, it still separates the cards
+        eq_checker.play_card(&"e-7".parse().unwrap());
+        assert_eq!(eq_checker.eq_set_of("e-6"), cards("e-6"));
+
+        // splitting a set when one of its middle cards is played
+        eq_checker.play_card(&"s-7".parse().unwrap());
+        assert_eq!(eq_checker.eq_set_of("s-6"), cards("s-6"));
+        assert_eq!(eq_checker.eq_set_of("s-8"), cards("s-8"));
+
+        // after the trick is finished, the neighbors are merged
+        eq_checker.play_card(&"e-U".parse().unwrap());
+        eq_checker.play_card(&"e-Z".parse().unwrap());
+        eq_checker.finish_trick(&cards("e-7 s-7 e-U e-Z"));
+        assert_eq!(eq_checker.eq_set_of("e-6"), cards("e-6 e-8"));
+        assert_eq!(eq_checker.eq_set_of("s-6"), cards("s-6 s-8"));
+    }
+}
@@ -0,0 +1,789 @@
+/*
+    The command line interface.
+
+    Every command has positional arguments and options of the form --name <value>, or --name for switches.
+    Options that are not given use their defaults. Usage errors exit with code 2, failing commands with code 1.
+ */
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::{Ipv4Addr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::str::FromStr;
+
+use marjapussi::game::Game;
+
+use crate::analysis::analyze_game;
+use crate::bench;
+use crate::cheater::{self, SearchTraceConfig};
+use crate::deal::{deal_from_seed, random_seed};
+use crate::engine;
+use crate::engine::host::{host_game, hosted_record, EngineConnection, HostError};
+use crate::human;
+use crate::log::{Level, LogFilter};
+use crate::notation;
+use crate::notation::game::{format_action, format_game};
+use crate::notation::position::{format_position, parse_position, Position};
+use crate::repro::{minimize, ReproCase, Reproduction};
+use crate::server::{ServerConfig, TableServer};
+use crate::simulate_game::{four_cheaters, load_game, save_game};
+use crate::simulate_game::observer::{GameObserver, JsonLinesObserver, TextObserver};
+use crate::suite;
+use crate::tournament::{run_duplicate, run_tournament, AgentFactory};
+
+
+const PROGRAM: &str = "marjapussi-ki";
+const DEFAULT_DEPTH: u32 = 6;
+
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    // the command line is wrong, the help of the command is shown
+    Usage(String),
+    // the command could not be executed
+    Failed(String)
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Failed(_) => 1
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(message) | CliError::Failed(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+
+struct Command {
+    name: &'static str,
+    arguments: &'static str,
+    description: &'static str,
+    // the options with their value (if they have one) and description
+    options: &'static [(&'static str, &'static str)],
+    run: fn(&Args) -> Result<(), CliError>
+}
+
+const COMMANDS: [Command; 13] = [
+    Command {
+        name: "simulate",
+        arguments: "",
+        description: "Plays deals between two teams of agents and prints statistics about the teams.",
+        options: &[
+            ("--count <n>", "number of deals (default 100)"),
+            ("--depth <n>", "search depth of agents without their own depth (default 6)"),
+            ("--seed <n>", "seed of the first deal, the following deals use the next seeds (default random)"),
+            ("--agents <a>,<b>", "agents of the two teams: cheater or cheater:<depth> (default cheater,cheater)"),
+            ("--duplicate", "play every deal twice with the teams swapped and compare the teams"),
+            ("--repro <directory>", "directory for the repro files of games in which an agent failed (default repro)")
+        ],
+        run: simulate
+    },
+    Command {
+        name: "play",
+        arguments: "",
+        description: "Plays one game with four cheaters and prints every action, or lets humans play some of the seats.",
+        options: &[
+            ("--seats <agents>", "agents of the four seats separated by ',': human, human:<hint depth>, cheater or cheater:<depth>"),
+            ("--depth <n>", "search depth (default 6)"),
+            ("--seed <n>", "seed of the deal (default random)"),
+            ("--deal <file>", "play the deal in this file (in the deal notation) instead of a seeded deal"),
+            ("--record <file>", "save the game (.json for a JSON record, otherwise the text notation)"),
+            ("--trace <directory>", "export the search trees of all decisions to this directory"),
+            ("--trace-depth <n>", "depth up to which the search trees are exported (default 2)"),
+            ("--events <file>", "write every event of the game as a JSON line to this file, '-' writes only them to stdout"),
+            ("--log <filter>", "what the cheaters log, e.g. debug or info,cheater::bidding=trace (default info)")
+        ],
+        run: play
+    },
+    Command {
+        name: "replay",
+        arguments: "<file>",
+        description: "Replays a recorded game, checks it and prints it in the text notation.",
+        options: &[
+            ("--position <n>", "print the position after the first n actions instead")
+        ],
+        run: replay
+    },
+    Command {
+        name: "repro",
+        arguments: "<file>",
+        description: "Replays a repro file of a failed game up to the failing decision and lets the agent choose again.",
+        options: &[
+            ("--log <filter>", "what the agent logs at the failing decision (default debug)")
+        ],
+        run: repro
+    },
+    Command {
+        name: "minimize",
+        arguments: "<file>",
+        description: "Shrinks a repro file while the failure still reproduces: later start, lower search depth and a more sorted deal.",
+        options: &[
+            ("--output <file>", "where to save the smallest failing case (default the file name with '.min' before the extension)")
+        ],
+        run: minimize
+    },
+    Command {
+        name: "analyze",
+        arguments: "<file>",
+        description: "Compares every decision in the cardplay of a recorded game with the best action of the search.",
+        options: &[
+            ("--depth <n>", "search depth (default 6)")
+        ],
+        run: analyze
+    },
+    Command {
+        name: "solve",
+        arguments: "<position>",
+        description: "Searches the best action of the player at turn in a position.",
+        options: &[
+            ("--depth <n>", "search depth (default 6)")
+        ],
+        run: solve
+    },
+    Command {
+        name: "bench",
+        arguments: "",
+        description: "Runs the searches of the benchmark set and measures them per entry and phase.",
+        options: &[
+            ("--depth <n>", "search depth (default 9)"),
+            ("--json <file>", "save the report as JSON"),
+            ("--baseline <file>", "compare with a report saved earlier and fail if a result got slower"),
+            ("--threshold <percent>", "slowdown in time or nodes that counts as a regression (default 10)")
+        ],
+        run: bench
+    },
+    Command {
+        name: "suite",
+        arguments: "<file>",
+        description: "Lets an agent choose an action in every position of a best-action suite and checks the actions.",
+        options: &[
+            ("--agent <a>", "the agent: cheater or cheater:<depth> (default cheater)"),
+            ("--depth <n>", "search depth of an agent without its own depth (default 6)")
+        ],
+        run: suite
+    },
+    Command {
+        name: "engine",
+        arguments: "",
+        description: "Runs an agent as engine that reads the JSON lines of the engine protocol from stdin.",
+        options: &[
+            ("--agent <a>", "the agent: cheater or cheater:<depth> (default cheater)"),
+            ("--depth <n>", "search depth if the host does not send one (default 6)")
+        ],
+        run: engine
+    },
+    Command {
+        name: "host",
+        arguments: "",
+        description: "Plays one game with four engine processes and prints every action.",
+        options: &[
+            ("--engine <command>", "the command that starts an engine (default this program with 'engine')"),
+            ("--depth <n>", "search depth sent to the engines (default the engines' own)"),
+            ("--time <ms>", "time per action sent to the engines"),
+            ("--seed <n>", "seed of the deal (default random)"),
+            ("--record <file>", "save the game (.json for a JSON record, otherwise the text notation)")
+        ],
+        run: host
+    },
+    Command {
+        name: "serve",
+        arguments: "",
+        description: "Runs a table server for one game, clients connect with TCP and empty seats are played by agents. Every client gets the whole deal, the cards of the others included.",
+        options: &[
+            ("--port <n>", "the port on localhost (default 7878)"),
+            ("--agent <a>", "the agent for empty seats: cheater or cheater:<depth> (default cheater)"),
+            ("--depth <n>", "search depth sent to the clients and of an agent without its own depth (default 6)"),
+            ("--join-timeout <ms>", "how long to wait for clients (default 30000)"),
+            ("--action-timeout <ms>", "how long a client may think about an action (default 10000)"),
+            ("--seed <n>", "seed of the deal (default random)"),
+            ("--record <file>", "save the game (.json for a JSON record, otherwise the text notation)")
+        ],
+        run: serve
+    },
+    Command {
+        name: "connect",
+        arguments: "<address>",
+        description: "Connects an agent as client to a table server, e.g. at localhost:7878.",
+        options: &[
+            ("--agent <a>", "the agent: cheater or cheater:<depth> (default cheater)"),
+            ("--depth <n>", "search depth if the server does not send one (default 6)")
+        ],
+        run: connect
+    }
+];
+
+
+pub fn run(args: &[String]) -> Result<(), CliError> {
+    /*
+        Runs the command given by the arguments (without the program name).
+     */
+
+    let Some(name) = args.first() else {
+        eprint!("{}", general_help());
+        return Err(CliError::Usage(String::from("no command given")));
+    };
+    if ["help", "--help", "-h"].contains(&name.as_str()) {
+        match args.get(1) {
+            Some(name) => print!("{}", command_help(find_command(name)?)),
+            None => print!("{}", general_help())
+        }
+        return Ok(());
+    }
+
+    let command = find_command(name)?;
+    if args[1..].iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", command_help(command));
+        return Ok(());
+    }
+    let result = Args::parse(command, &args[1..]).and_then(|args| (command.run)(&args));
+    if let Err(CliError::Usage(_)) = result {
+        eprint!("{}", command_help(command));
+    }
+    result
+}
+
+fn find_command(name: &str) -> Result<&'static Command, CliError> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name == name)
+        .ok_or(CliError::Usage(format!("unknown command '{}', see '{} help'", name, PROGRAM)))
+}
+
+fn general_help() -> String {
+    let mut help = format!("usage: {} <command> [arguments] [options]\n\ncommands:\n", PROGRAM);
+    for command in &COMMANDS {
+        help += &format!("  {:<10} {}\n", command.name, command.description);
+    }
+    help += &format!("\n'{} help <command>' shows the arguments and options of a command.\n", PROGRAM);
+    help
+}
+
+fn command_help(command: &Command) -> String {
+    let mut help = format!("usage: {} {}", PROGRAM, command.name);
+    if !command.arguments.is_empty() {
+        help += &format!(" {}", command.arguments);
+    }
+    help += &format!(" [options]\n\n{}\n\noptions:\n", command.description);
+    for (option, description) in command.options {
+        help += &format!("  {:<22} {}\n", option, description);
+    }
+    help
+}
+
+
+// the parsed arguments of a command
+#[derive(Debug)]
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<&'static str, Option<String>>
+}
+
+impl Args {
+    fn parse(command: &Command, args: &[String]) -> Result<Self, CliError> {
+        let mut positional = vec![];
+        let mut options = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg.clone());
+                continue;
+            }
+            let (name, takes_value) = command.options
+                .iter()
+                .map(|(option, _)| option.split_once(' ').map_or((*option, false), |(name, _)| (name, true)))
+                .find(|(name, _)| name == arg)
+                .ok_or(CliError::Usage(format!("unknown option '{}' for {}", arg, command.name)))?;
+            let value = if takes_value {
+                Some(args.next().ok_or(CliError::Usage(format!("option '{}' needs a value", name)))?.clone())
+            } else {
+                None
+            };
+            if options.insert(name, value).is_some() {
+                return Err(CliError::Usage(format!("option '{}' is given twice", name)));
+            }
+        }
+
+        let expected = command.arguments.split_whitespace().count();
+        if positional.len() != expected {
+            return Err(CliError::Usage(format!(
+                "{} needs {} argument(s), but {} were given", command.name, expected, positional.len()
+            )));
+        }
+        Ok(Args { positional, options })
+    }
+
+    fn argument(&self, index: usize) -> &str {
+        // the number of arguments is checked while parsing
+        &self.positional[index]
+    }
+
+    fn value<T: FromStr>(&self, option: &str) -> Result<Option<T>, CliError> {
+        match self.options.get(option) {
+            Some(Some(value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| CliError::Usage(format!("invalid value '{}' for option '{}'", value, option))),
+            _ => Ok(None)
+        }
+    }
+
+    fn value_or<T: FromStr>(&self, option: &str, default: T) -> Result<T, CliError> {
+        Ok(self.value(option)?.unwrap_or(default))
+    }
+
+    fn switch(&self, option: &str) -> bool {
+        self.options.contains_key(option)
+    }
+}
+
+
+fn agent(spec: &str, default_depth: u32) -> Result<AgentFactory, CliError> {
+    /*
+        The agent of a specification like "cheater" or "cheater:4".
+        Humans are no agents here, since the commands using them run without anybody reading stdin.
+     */
+
+    match split_spec(spec, default_depth)? {
+        ("cheater", depth) => Ok(AgentFactory::cheater(depth)),
+        ("human", _) => Err(CliError::Usage(String::from("humans can only take seats in 'play --seats'"))),
+        (kind, _) => Err(CliError::Usage(format!("unknown agent '{}', the known agents are: cheater", kind)))
+    }
+}
+
+fn seat(spec: &str, default_depth: u32) -> Result<AgentFactory, CliError> {
+    /*
+        The player of a seat in a played game: an agent or a human like "human" or "human:3".
+     */
+
+    match split_spec(spec, default_depth)? {
+        ("human", depth) => Ok(AgentFactory::human(depth)),
+        ("cheater", depth) => Ok(AgentFactory::cheater(depth)),
+        (kind, _) => Err(CliError::Usage(format!("unknown agent '{}', the known agents are: cheater, human", kind)))
+    }
+}
+
+fn split_spec(spec: &str, default_depth: u32) -> Result<(&str, u32), CliError> {
+    let (kind, depth) = spec.split_once(':').map_or((spec, None), |(kind, depth)| (kind, Some(depth)));
+    let depth = match depth {
+        Some(depth) => depth.parse().map_err(|_| CliError::Usage(format!("invalid search depth in agent '{}'", spec)))?,
+        None => default_depth
+    };
+    Ok((kind, depth))
+}
+
+fn simulate(args: &Args) -> Result<(), CliError> {
+    let depth = args.value_or("--depth", DEFAULT_DEPTH)?;
+    let specs: String = args.value_or("--agents", String::from("cheater,cheater"))?;
+    let agents = match specs.split(',').collect::<Vec<&str>>()[..] {
+        [first, second] => [agent(first, depth)?, agent(second, depth)?],
+        _ => return Err(CliError::Usage(String::from("--agents needs exactly two agents separated by ','")))
+    };
+    let deals = args.value_or("--count", 100)?;
+    let first_seed = args.value("--seed")?.unwrap_or_else(random_seed);
+
+    let repro_directory = args.value_or("--repro", PathBuf::from("repro"))?;
+
+    let result = if args.switch("--duplicate") {
+        let result = run_duplicate(&agents, deals, first_seed);
+        result.print_summary();
+        result.tournament
+    } else {
+        let result = run_tournament(&agents, deals, first_seed);
+        result.print_summary();
+        result
+    };
+    if result.failed.is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(&repro_directory)
+        .map_err(|error| CliError::Failed(format!("could not create {}: {}", repro_directory.display(), error)))?;
+    for failed in &result.failed {
+        let path = repro_directory.join(format!("seed_{}.json", failed.seed));
+        ReproCase::new(&failed.failure, Some(failed.seed))
+            .save(&path)
+            .map_err(|error| CliError::Failed(format!("could not save {}: {}", path.display(), error)))?;
+        println!("repro file of the deal with seed {}: {}", failed.seed, path.display());
+    }
+    Ok(())
+}
+
+fn play(args: &Args) -> Result<(), CliError> {
+    let seed: Option<u64> = args.value("--seed")?;
+    let deal_file: Option<PathBuf> = args.value("--deal")?;
+    let seats: Option<String> = args.value("--seats")?;
+    let events: Option<String> = args.value("--events")?;
+    // with events on stdout, nothing else is printed
+    let quiet = events.as_deref() == Some("-");
+    if quiet && seats.is_some() {
+        return Err(CliError::Usage(String::from("--events - can not be combined with --seats")));
+    }
+    // humans must not see the cards of the others, so the deal is only printed without them
+    let show_deal = seats.is_none() && !quiet;
+    let (cards, seed) = match (seed, deal_file) {
+        (Some(_), Some(_)) => return Err(CliError::Usage(String::from("--seed and --deal can not be combined"))),
+        (None, Some(path)) => {
+            let input = fs::read_to_string(&path)
+                .map_err(|error| CliError::Failed(format!("could not read {}: {}", path.display(), error)))?;
+            let cards = notation::parse_deal(&input)
+                .map_err(|error| CliError::Failed(format!("invalid deal in {}: {}", path.display(), error)))?;
+            if show_deal {
+                println!("{}", notation::format_deal(&cards));
+            }
+            (cards, None)
+        },
+        (seed, None) => {
+            // without a seed, the deal is dealt from a random seed, which is printed (and recorded) so the game can be replayed
+            let seed = seed.unwrap_or_else(random_seed);
+            if show_deal {
+                println!("\nDeal seed: {}", seed);
+            }
+            (deal_from_seed(seed), Some(seed))
+        }
+    };
+    let trace = args.value("--trace")?.map(|directory| SearchTraceConfig { depth: 2, directory });
+    let trace = match args.value("--trace-depth")? {
+        Some(_) if trace.is_none() => return Err(CliError::Usage(String::from("--trace-depth needs --trace"))),
+        Some(depth) => trace.map(|trace| SearchTraceConfig { depth, ..trace }),
+        None => trace
+    };
+    let mut event_stream = match &events {
+        None => None,
+        Some(path) if path == "-" => Some(JsonLinesObserver::stdout()),
+        Some(path) => Some(JsonLinesObserver::file(Path::new(path))
+            .map_err(|error| CliError::Failed(format!("could not create {}: {}", path, error)))?)
+    };
+    let mut text = TextObserver::default();
+    let mut observers: Vec<&mut dyn GameObserver> = vec![];
+    if let Some(event_stream) = &mut event_stream {
+        observers.push(event_stream);
+    }
+
+    let log_filter = match args.value::<String>("--log")? {
+        Some(filter) => filter.parse().map_err(|error| CliError::Usage(format!("invalid log filter: {}", error)))?,
+        None if quiet => LogFilter::off(),
+        None => LogFilter::level(Level::Info)
+    };
+    let depth = args.value_or("--depth", DEFAULT_DEPTH)?;
+    let game_record = match seats {
+        None => {
+            if !quiet {
+                observers.push(&mut text);
+            }
+            four_cheaters(depth, cards, seed, trace, log_filter, &mut observers)
+        },
+        Some(_) if trace.is_some() => return Err(CliError::Usage(String::from("--trace can not be combined with --seats"))),
+        Some(specs) => {
+            let seats = specs.split(',').map(|spec| seat(spec, depth)).collect::<Result<Vec<AgentFactory>, CliError>>()?;
+            if seats.len() != 4 {
+                return Err(CliError::Usage(String::from("--seats needs exactly four agents separated by ','")));
+            }
+            human::play_against_agents(&seats, cards, seed, &mut observers)
+        }
+    };
+    if let Some(event_stream) = event_stream {
+        event_stream.finish().map_err(|error| CliError::Failed(format!("could not write the events: {}", error)))?;
+    }
+    let game_record = game_record.map_err(|failure| CliError::Failed(format!("the game was aborted: {}", failure)))?;
+    if let Some(path) = args.value::<PathBuf>("--record")? {
+        save_game(&game_record, &path).map_err(|error| CliError::Failed(format!("could not save the record: {}", error)))?;
+        if !quiet {
+            println!("\nRecord saved to {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn replay(args: &Args) -> Result<(), CliError> {
+    let path = PathBuf::from(args.argument(0));
+    let game_record = load_game(&path).map_err(|error| CliError::Failed(format!("the record is invalid: {}", error)))?;
+
+    let Some(actions) = args.value::<usize>("--position")? else {
+        println!("{}", format_game(&game_record).expect("Verified records can be formatted"));
+        println!("The record is valid ({} actions)", game_record.actions.len());
+        return Ok(());
+    };
+    if actions > game_record.actions.len() {
+        return Err(CliError::Failed(format!("the game has only {} actions", game_record.actions.len())));
+    }
+    let mut game = Game::new(game_record.game_name.clone(), game_record.player_names.clone(), Some(game_record.deal.clone()));
+    for action in &game_record.actions[..actions] {
+        game = game.apply_action(action.clone()).expect("Verified records contain only legal actions");
+    }
+    let position = Position::from_game(&game)
+        .ok_or(CliError::Failed(format!("there is no position in the game phase {:?}", game.state.phase)))?;
+    println!("{}", format_position(&position));
+    Ok(())
+}
+
+fn repro(args: &Args) -> Result<(), CliError> {
+    let path = PathBuf::from(args.argument(0));
+    let case = ReproCase::load(&path).map_err(|error| CliError::Failed(format!("the repro file is invalid: {}", error)))?;
+    let log_filter = args.value_or("--log", String::from("debug"))?
+        .parse()
+        .map_err(|error| CliError::Usage(format!("invalid log filter: {}", error)))?;
+
+    let game = case.game().map_err(|error| CliError::Failed(format!("the repro file is invalid: {}", error)))?;
+    if let Some(seed) = case.seed {
+        println!("deal seed: {}", seed);
+    }
+    println!("{} failed after {} actions: {}", case.player_names[case.place as usize], case.actions.len(), case.error);
+    match &case.start {
+        // the game built from the start position does not know the score, so its positions would be wrong
+        Some(start) => println!("start position: {} (followed by {} actions)", start, case.actions.len()),
+        None => if let Some(position) = Position::from_game(&game) {
+            println!("position: {}", format_position(&position));
+        }
+    }
+    let reproduction = case.reproduce(log_filter)
+        .map_err(|error| CliError::Failed(format!("could not reproduce the failure: {}", error)))?;
+    match reproduction {
+        Reproduction::Failed { decision, error } if decision < case.actions.len() => {
+            Err(CliError::Failed(format!("the agent already failed after {} actions: {}", decision, error)))
+        },
+        Reproduction::Failed { error, .. } => Err(CliError::Failed(format!("the failure reproduces: {}", error))),
+        Reproduction::Chose(action) => {
+            println!("the failure does not reproduce, the agent chose {}", format_action(&action.action_type));
+            Ok(())
+        }
+    }
+}
+
+fn minimize(args: &Args) -> Result<(), CliError> {
+    let path = PathBuf::from(args.argument(0));
+    let output = args.value_or("--output", path.with_extension("min.json"))?;
+    let case = ReproCase::load(&path).map_err(|error| CliError::Failed(format!("the repro file is invalid: {}", error)))?;
+
+    let fails = minimize::same_failure(&case)
+        .map_err(|error| CliError::Failed(format!("could not reproduce the failure: {}", error)))?;
+    let Some(mut fails) = fails else {
+        return Err(CliError::Failed(String::from("the failure does not reproduce, there is nothing to minimize")));
+    };
+    let minimized = minimize::minimize(&case, &mut fails);
+    for reduction in &minimized.reductions {
+        println!("- {}", reduction);
+    }
+    let depth = minimized.case.agents.get(case.place as usize).and_then(|agent| agent.search_depth);
+    println!(
+        "{} of {} candidates kept: {} actions (instead of {}), search depth {:?}",
+        minimized.reductions.len(), minimized.attempts, minimized.case.actions.len(), case.actions.len(), depth
+    );
+    if let Some(start) = &minimized.case.start {
+        println!("start position: {}", start);
+    }
+    minimized.case.save(&output).map_err(|error| CliError::Failed(format!("could not save {}: {}", output.display(), error)))?;
+    println!("Smallest failing case saved to {}", output.display());
+    Ok(())
+}
+
+fn analyze(args: &Args) -> Result<(), CliError> {
+    let path = PathBuf::from(args.argument(0));
+    let max_depth = args.value_or("--depth", DEFAULT_DEPTH)?;
+    let game_analysis = load_game(&path)
+        .and_then(|game_record| analyze_game(&game_record, Some(max_depth)))
+        .map_err(|error| CliError::Failed(format!("the record is invalid: {}", error)))?;
+    game_analysis.print_summary();
+    Ok(())
+}
+
+fn solve(args: &Args) -> Result<(), CliError> {
+    let position = parse_position(args.argument(0))
+        .map_err(|error| CliError::Failed(format!("invalid position: {}", error)))?;
+    let max_depth = args.value_or("--depth", DEFAULT_DEPTH)?;
+    let (best_action, value) = cheater::search_position(&position, Some(max_depth))
+        .map_err(|error| CliError::Failed(format!("could not search the position: {}", error)))?;
+    println!("best action: {}:{}", best_action.player.0, format_action(&best_action.action_type));
+    println!("value for the party at turn: {}", value);
+    Ok(())
+}
+
+fn bench(args: &Args) -> Result<(), CliError> {
+    let report = bench::run_bench(args.value_or("--depth", bench::DEFAULT_DEPTH)?)
+        .map_err(|error| CliError::Failed(error.to_string()))?;
+    report.print_summary();
+    cheater::print_avg_tree_size();
+
+    if let Some(path) = args.value::<PathBuf>("--json")? {
+        report.save(&path).map_err(|error| CliError::Failed(error.to_string()))?;
+        println!("\nReport saved to {}", path.display());
+    }
+    let Some(path) = args.value::<PathBuf>("--baseline")? else {
+        return Ok(());
+    };
+    let threshold = args.value_or("--threshold", 10.0)?;
+    let comparisons = bench::BenchReport::load(&path)
+        .and_then(|baseline| bench::compare(&report, &baseline))
+        .map_err(|error| CliError::Failed(error.to_string()))?;
+    bench::print_comparison(&comparisons, threshold);
+    let regressions: Vec<String> = comparisons
+        .iter()
+        .filter(|comparison| comparison.regression(threshold))
+        .map(|comparison| format!("{} ({}) in {}", comparison.name, comparison.phase, comparison.regressed_metrics(threshold).join(" and ")))
+        .collect();
+    if !regressions.is_empty() {
+        return Err(CliError::Failed(format!("{} result(s) grew by more than {}% compared to the baseline: {}",
+                                            regressions.len(), threshold, regressions.join(", "))));
+    }
+    Ok(())
+}
+
+fn suite(args: &Args) -> Result<(), CliError> {
+    let path = PathBuf::from(args.argument(0));
+    let spec: String = args.value_or("--agent", String::from("cheater"))?;
+    let agent = agent(&spec, args.value_or("--depth", DEFAULT_DEPTH)?)?;
+    let entries = fs::read_to_string(&path)
+        .map_err(|error| CliError::Failed(format!("could not read {}: {}", path.display(), error)))
+        .and_then(|input| suite::parse_suite(&input)
+            .map_err(|error| CliError::Failed(format!("invalid suite {}: {}", path.display(), error))))?;
+
+    let outcomes = suite::run_suite(&entries, &agent);
+    suite::print_outcomes(&entries, &outcomes);
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed).count();
+    if failed > 0 {
+        return Err(CliError::Failed(format!("{} chose a wrong action in {} position(s)", agent.name, failed)));
+    }
+    Ok(())
+}
+
+fn engine(args: &Args) -> Result<(), CliError> {
+    let depth = args.value_or("--depth", DEFAULT_DEPTH)?;
+    let spec: String = args.value_or("--agent", String::from("cheater"))?;
+    let engine = engine::Engine::new(agent(&spec, depth)?, depth);
+    engine::run_engine(io::stdin().lock(), io::stdout(), engine)
+        .map_err(|error| CliError::Failed(format!("the connection to the host failed: {}", error)))
+}
+
+fn host(args: &Args) -> Result<(), CliError> {
+    let (program, engine_args) = match args.value::<String>("--engine")? {
+        Some(command) => {
+            let mut words = command.split_whitespace().map(String::from);
+            let program = words.next().ok_or(CliError::Usage(String::from("--engine needs a command")))?;
+            (program, words.collect())
+        },
+        None => {
+            let program = env::current_exe().map_err(|error| CliError::Failed(format!("could not find this program: {}", error)))?;
+            (program.display().to_string(), vec![String::from("engine")])
+        }
+    };
+    let seed = args.value("--seed")?.unwrap_or_else(random_seed);
+    println!("\nDeal seed: {}", seed);
+
+    let failed = |error: HostError| CliError::Failed(error.to_string());
+    let mut engines = (0..4)
+        .map(|_| EngineConnection::spawn(&program, &engine_args))
+        .collect::<Result<Vec<EngineConnection>, HostError>>()
+        .map_err(failed)?;
+    let depth = args.value("--depth")?;
+    let game = host_game(&mut engines, deal_from_seed(seed), depth, args.value("--time")?, true).map_err(failed)?;
+    let game_record = hosted_record(&game, &engines, Some(seed), depth);
+    for engine in engines {
+        engine.close().map_err(failed)?;
+    }
+
+    println!("\n{}", format_game(&game_record).expect("Hosted games can be formatted"));
+    if let Some(path) = args.value::<PathBuf>("--record")? {
+        save_game(&game_record, &path).map_err(|error| CliError::Failed(format!("could not save the record: {}", error)))?;
+        println!("\nRecord saved to {}", path.display());
+    }
+    Ok(())
+}
+
+fn serve(args: &Args) -> Result<(), CliError> {
+    let depth = args.value_or("--depth", DEFAULT_DEPTH)?;
+    let spec: String = args.value_or("--agent", String::from("cheater"))?;
+    let config = ServerConfig {
+        join_timeout: Duration::from_millis(args.value_or("--join-timeout", 30000)?),
+        action_timeout: Duration::from_millis(args.value_or("--action-timeout", 10000)?),
+        depth: Some(depth),
+        verbose: true
+    };
+    let server = TableServer::bind((Ipv4Addr::LOCALHOST, args.value_or("--port", 7878)?), config, agent(&spec, depth)?)
+        .map_err(|error| CliError::Failed(format!("could not start the server: {}", error)))?;
+    let address = server.local_addr().map_err(|error| CliError::Failed(error.to_string()))?;
+    let seed = args.value("--seed")?.unwrap_or_else(random_seed);
+    println!("\nDeal seed: {}", seed);
+    println!("Waiting for clients at {}", address);
+
+    let served = server.play_game(deal_from_seed(seed))
+        .map_err(|failure| CliError::Failed(format!("the game was aborted: {}", failure)))?;
+    let game_record = served.record(Some(seed));
+    println!("\n{}", format_game(&game_record).expect("Served games can be formatted"));
+    for (place, reason) in &served.replaced {
+        println!("The client at place {} was replaced by an agent: {}", place, reason);
+    }
+    if let Some(path) = args.value::<PathBuf>("--record")? {
+        save_game(&game_record, &path).map_err(|error| CliError::Failed(format!("could not save the record: {}", error)))?;
+        println!("\nRecord saved to {}", path.display());
+    }
+    Ok(())
+}
+
+fn connect(args: &Args) -> Result<(), CliError> {
+    let depth = args.value_or("--depth", DEFAULT_DEPTH)?;
+    let spec: String = args.value_or("--agent", String::from("cheater"))?;
+    let engine = engine::Engine::new(agent(&spec, depth)?, depth);
+    let failed = |error: io::Error| CliError::Failed(format!("the connection to the server failed: {}", error));
+    let stream = TcpStream::connect(args.argument(0)).map_err(failed)?;
+    engine::run_engine(BufReader::new(stream.try_clone().map_err(failed)?), stream, engine).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &str, args: &[&str]) -> Result<Args, CliError> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Args::parse(find_command(command)?, &args)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let parsed = args("simulate", &["--count", "20", "--duplicate", "--agents", "cheater:4,cheater"]).unwrap();
+        assert_eq!(parsed.value::<u32>("--count"), Ok(Some(20)));
+        assert_eq!(parsed.value_or("--depth", 3), Ok(3));
+        assert!(parsed.switch("--duplicate"));
+        assert!(!parsed.switch("--seed"));
+
+        let parsed = args("solve", &["r-U/s-6 - - - 115 - 0:0 8:0 0", "--depth", "3"]).unwrap();
+        assert_eq!(parsed.argument(0), "r-U/s-6 - - - 115 - 0:0 8:0 0");
+        assert_eq!(parsed.value::<u32>("--depth"), Ok(Some(3)));
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        let usage = |result: Result<Args, CliError>| matches!(result, Err(CliError::Usage(_)));
+        assert!(usage(args("simulate", &["--rounds", "3"])));
+        assert!(usage(args("simulate", &["--count"])));
+        assert!(usage(args("simulate", &["--count", "1", "--count", "2"])));
+        assert!(usage(args("analyze", &[])));
+        assert!(usage(args("bench", &["extra"])));
+        assert!(matches!(args("simulate", &["--count", "many"]).unwrap().value::<u32>("--count"), Err(CliError::Usage(_))));
+
+        assert_eq!(run(&[String::from("unknown")]).unwrap_err().exit_code(), 2);
+        let missing_file = run(&[String::from("replay"), String::from("does/not/exist.json")]).unwrap_err();
+        assert_eq!(missing_file.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_agent() {
+        assert_eq!(agent("cheater", 5).unwrap().name, "CheaterV1 (depth 5)");
+        assert_eq!(agent("cheater:3", 5).unwrap().name, "CheaterV1 (depth 3)");
+        assert!(agent("cheater:x", 5).is_err());
+        assert!(agent("human", 5).is_err());
+        assert!(agent("robot", 5).is_err());
+        assert_eq!(seat("human", 5).unwrap().name, "Human");
+        assert_eq!(seat("cheater:3", 5).unwrap().name, "CheaterV1 (depth 3)");
+        assert!(seat("robot", 5).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use marjapussi::game::cards::{get_all_cards, Card};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+// small splitmix64 generator, so deals (and test data) can be reproduced from a seed
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, upper: usize) -> usize {
+        // the modulo bias is negligible for the small ranges we need
+        (self.next_u64() % upper as u64) as usize
+    }
+}
+
+
+pub fn deal_from_seed(seed: u64) -> [Vec<Card>; 4] {
+    /*
+        Shuffles all cards (Fisher-Yates) and deals nine cards to every player.
+        The same seed always leads to the same deal.
+     */
+
+    let mut rng = SeededRng::new(seed);
+    let mut deck = get_all_cards();
+    for index in (1..deck.len()).rev() {
+        deck.swap(index, rng.below(index + 1));
+    }
+    let mut deal: [Vec<Card>; 4] = [vec![], vec![], vec![], vec![]];
+    for (index, card) in deck.into_iter().enumerate() {
+        deal[index / 9].push(card);
+    }
+    deal
+}
+
+pub fn random_seed() -> u64 {
+    /*
+        A seed for a new random deal. It is logged together with the game, so the deal can be replayed.
+     */
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    // mix the time, so seeds drawn in quick succession are not close to each other
+    SeededRng::new(nanos).next_u64()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deal_from_seed() {
+        let deal = deal_from_seed(42);
+        assert_eq!(deal, deal_from_seed(42));
+        assert_ne!(deal, deal_from_seed(43));
+
+        // every card is dealt exactly once
+        let mut cards: Vec<Card> = deal.concat();
+        assert!(deal.iter().all(|hand| hand.len() == 9));
+        let mut all_cards = get_all_cards();
+        cards.sort();
+        all_cards.sort();
+        assert_eq!(cards, all_cards);
+    }
+}
@@ -0,0 +1,363 @@
+/*
+    The engine protocol: a line-based protocol similar to UCI in chess, to connect agents to other frontends and game servers.
+
+    The host and the engine exchange JSON objects, one per line. The "type" field names the message.
+    Actions are written in the game notation (e.g. "140", "r-A", "?pair", "pass [r-6, s-7, g-8, e-9]"), cards as in the deal notation.
+
+    Messages of the host:
+        {"type": "hello"}                                            the engine answers with "ready"
+        {"type": "new_game", "place": 0, "player_names": [...], "deal": [["r-A", ...], ...]}
+                                                                     a new game in which the engine plays the given place
+        {"type": "position", "position": "<position notation>"}      a single position in which the engine plays the player at turn
+        {"type": "action", "place": 2, "action": "r-A"}              an action that was applied to the game, including the engine's own
+        {"type": "go", "depth": 6, "time_ms": 1000}                  the engine has to choose an action, both limits are optional
+                                                                     (the bidding and passing of a game have no time limit and no "info")
+        {"type": "inspect"}                                          the engine answers with its state in the current game
+        {"type": "error", "message": "..."}                          the host rejected the last answer of the engine
+        {"type": "quit"}                                             the engine exits
+
+    Messages of the engine:
+        {"type": "ready", "name": "CheaterV1 (depth 6)", "protocol": 2}
+        {"type": "info", "depth": 3, "score": 120, "pv": ["r-A", "r-6", ...], "nodes": 5123, "time_ms": 12}
+                                                                     a finished iteration of the search in the cardplay
+        {"type": "best_action", "place": 0, "action": "r-A"}         the answer to "go"
+        {"type": "state", "state": {...}}                            the answer to "inspect", null if the agent has no state to show
+        {"type": "error", "message": "..."}                          the answer to a message the engine could not handle
+
+    Our agents are cheaters, so the deal contains the cards of all players.
+ */
+
+pub mod host;
+
+use marjapussi::game::cards::Card;
+use marjapussi::game::gameevent::{ActionType, GameAction};
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::player::PlaceAtTable;
+use marjapussi::game::Game;
+
+use serde::{Deserialize, Serialize};
+
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use crate::ai::{AgentError, MarjapussiCheater};
+use crate::cheater::{search_iteratively, SearchIteration};
+use crate::log::LogFilter;
+use crate::notation::game::{format_action, parse_action};
+use crate::notation::parse_hand;
+use crate::notation::position::{parse_position, Position};
+use crate::tournament::AgentFactory;
+
+
+// increase this whenever the protocol changes in an incompatible way
+pub const PROTOCOL_VERSION: u32 = 2;
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostMessage {
+    Hello,
+    NewGame { place: u8, player_names: [String; 4], deal: [Vec<Card>; 4] },
+    Position { position: String },
+    Action { place: u8, action: String },
+    Go { depth: Option<u32>, time_ms: Option<u64> },
+    Inspect,
+    Error { message: String },
+    Quit
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineMessage {
+    Ready { name: String, protocol: u32 },
+    Info { depth: u32, score: i32, pv: Vec<String>, nodes: u64, time_ms: u64 },
+    BestAction { place: u8, action: String },
+    State { state: Option<serde_json::Value> },
+    Error { message: String }
+}
+
+
+pub fn parse_protocol_action(place: u8, text: &str) -> Option<GameAction> {
+    /*
+        Parses an action of the protocol, i.e. any action in the game notation including passing.
+     */
+
+    let action_type = match text.strip_prefix("pass ") {
+        Some(hand) => ActionType::Pass(parse_hand(hand).ok()?),
+        None => parse_action(text)?
+    };
+    Some(GameAction { action_type, player: PlaceAtTable(place) })
+}
+
+
+// what the engine plays: a game from its start or a single position
+enum Task {
+    Game { game: Game, agent: Box<dyn MarjapussiCheater> },
+    Position { position: Position, game: Game }
+}
+
+pub struct Engine {
+    agent: AgentFactory,
+    default_depth: u32,
+    task: Option<Task>
+}
+
+impl Engine {
+    pub fn new(agent: AgentFactory, default_depth: u32) -> Self {
+        Engine { agent, default_depth, task: None }
+    }
+
+    pub fn handle(&mut self, message: HostMessage, send: &mut dyn FnMut(EngineMessage)) -> Result<(), String> {
+        /*
+            Handles a message of the host, every answer is passed to send.
+            An error is returned if the message can not be handled in the current state.
+         */
+
+        match message {
+            HostMessage::Hello => send(EngineMessage::Ready { name: self.agent.name.clone(), protocol: PROTOCOL_VERSION }),
+            HostMessage::NewGame { place, player_names, deal } => {
+                if place > 3 {
+                    return Err(format!("there is no place {}", place));
+                }
+                let mut agent = self.agent.build(&player_names[place as usize], place);
+                agent.set_log_filter(LogFilter::off());
+                let game = Game::new(String::from("Engine Game"), player_names, Some(deal));
+                self.task = Some(Task::Game { game, agent });
+            },
+            HostMessage::Position { position } => {
+                let position = parse_position(&position).map_err(|error| format!("invalid position: {}", error))?;
+                let game = position.to_game();
+                self.task = Some(Task::Position { position, game });
+            },
+            HostMessage::Action { place, action } => {
+                let Some(Task::Game { game, .. }) = &mut self.task else {
+                    return Err(String::from("actions can only be applied after new_game"));
+                };
+                let action = parse_protocol_action(place, &action).ok_or(format!("invalid action '{}'", action))?;
+                if !game.legal_actions().contains(&action) {
+                    return Err(format!("the action {} of place {} is illegal", format_action(&action.action_type), place));
+                }
+                game.apply_action_mut(action);
+            },
+            HostMessage::Go { depth, time_ms } => {
+                let time_limit = time_ms.map(Duration::from_millis);
+                let action = match &mut self.task {
+                    None => return Err(String::from("there is no game or position to play")),
+                    // the cardplay is searched within the limits of the host, the agent only learns from the game
+                    Some(Task::Game { game, agent }) if in_cardplay(game) => {
+                        agent.observe(game);
+                        let depth = depth.or(agent.config().search_depth).unwrap_or(self.default_depth);
+                        choose_by_search(game, None, depth, time_limit, send)
+                    },
+                    // the agent bids, passes and answers itself
+                    Some(Task::Game { game, agent }) => agent.select_action(game.clone()),
+                    Some(Task::Position { position, game }) => {
+                        let depth = depth.unwrap_or(self.default_depth);
+                        choose_by_search(game, Some(position), depth, time_limit, send)
+                    }
+                }.map_err(|error| format!("could not choose an action: {}", error))?;
+                send(EngineMessage::BestAction { place: action.player.0, action: format_action(&action.action_type) });
+            },
+            HostMessage::Inspect => {
+                let Some(Task::Game { agent, .. }) = &self.task else {
+                    return Err(String::from("only the agent of a game has a state"));
+                };
+                send(EngineMessage::State { state: agent.state() });
+            },
+            // the host does not expect an answer to an error, the connection loop ends on quit
+            HostMessage::Error { .. } | HostMessage::Quit => ()
+        }
+        Ok(())
+    }
+}
+
+fn in_cardplay(game: &Game) -> bool {
+    // a decision in the cardplay is one in which a card can be played
+    matches!(game.state.phase, GamePhase::Raising | GamePhase::StartTrick | GamePhase::Trick)
+        && game.legal_actions().iter().any(|action| matches!(action.action_type, ActionType::CardPlayed(_)))
+}
+
+fn choose_by_search(game: &Game, position: Option<&Position>, depth: u32, time_limit: Option<Duration>,
+                    send: &mut dyn FnMut(EngineMessage)) -> Result<GameAction, AgentError> {
+    // a single legal action needs no search
+    let legal_actions: Vec<GameAction> = game.legal_actions()
+        .into_iter()
+        .filter(|action| action.action_type != ActionType::UndoRequest)
+        .collect();
+    if let [action] = &legal_actions[..] {
+        return Ok(action.clone());
+    }
+    let mut report = |iteration: &SearchIteration| send(EngineMessage::Info {
+        depth: iteration.depth,
+        score: iteration.value,
+        pv: iteration.continuation.iter().map(|action| format_action(&action.action_type)).collect(),
+        nodes: iteration.nodes,
+        time_ms: iteration.elapsed.as_millis() as u64
+    });
+    Ok(search_iteratively(game, position, depth, time_limit, &mut report)?.best_action)
+}
+
+
+pub fn run_engine(input: impl BufRead, mut output: impl Write, mut engine: Engine) -> io::Result<()> {
+    /*
+        Reads the messages of the host line by line and writes the answers, until the host quits or closes the input.
+     */
+
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut answers = vec![];
+        let quit = match serde_json::from_str::<HostMessage>(&line) {
+            Ok(message) => {
+                let quit = message == HostMessage::Quit;
+                if let Err(message) = engine.handle(message, &mut |answer| answers.push(answer)) {
+                    answers.push(EngineMessage::Error { message });
+                }
+                quit
+            },
+            Err(error) => {
+                answers.push(EngineMessage::Error { message: format!("invalid message: {}", error) });
+                false
+            }
+        };
+        for answer in answers {
+            writeln!(output, "{}", serde_json::to_string(&answer).expect("Engine messages can be serialized"))?;
+        }
+        output.flush()?;
+        if quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheater::CheaterV1;
+    use crate::deal::deal_from_seed;
+    use crate::simulate_game::play_game;
+    use std::time::Instant;
+
+    fn run(engine: &mut Engine, message: HostMessage) -> Vec<EngineMessage> {
+        let mut answers = vec![];
+        engine.handle(message, &mut |answer| answers.push(answer)).unwrap();
+        answers
+    }
+
+    #[test]
+    fn test_messages() {
+        let message: HostMessage = serde_json::from_str(r#"{"type": "go", "depth": 4, "time_ms": null}"#).unwrap();
+        assert_eq!(message, HostMessage::Go { depth: Some(4), time_ms: None });
+        let message: HostMessage = serde_json::from_str(r#"{"type": "action", "place": 1, "action": "pass [r-6, s-7, g-8, e-9]"}"#).unwrap();
+        let HostMessage::Action { place, action } = message else { panic!("expected an action") };
+        assert!(matches!(parse_protocol_action(place, &action).unwrap().action_type, ActionType::Pass(cards) if cards.len() == 4));
+        assert!(parse_protocol_action(0, "pass r-6").is_none());
+        let answer = EngineMessage::BestAction { place: 2, action: String::from("r-A") };
+        assert_eq!(serde_json::to_string(&answer).unwrap(), r#"{"type":"best_action","place":2,"action":"r-A"}"#);
+    }
+
+    #[test]
+    fn test_engine_game() {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4)
+            .map(|place| Box::new(CheaterV1::new(&player_names[place], place as u8, 2)) as Box<dyn MarjapussiCheater>)
+            .collect();
+        let record = play_game(&mut players, player_names.clone(), Some(deal_from_seed(5)), LogFilter::off(), &mut [])
+            .unwrap()
+            .record(Some(5));
+
+        // the engine plays a decision in the cardplay with the depth of the host instead of its own
+        let decision = record.actions.len() - 10;
+        let place = record.actions[decision].player.0;
+        let mut engine = Engine::new(AgentFactory::cheater(4), 4);
+        run(&mut engine, HostMessage::NewGame { place, player_names, deal: record.deal.clone() });
+        for action in &record.actions[..decision] {
+            let action = HostMessage::Action { place: action.player.0, action: format_action(&action.action_type) };
+            run(&mut engine, action);
+        }
+        let answers = run(&mut engine, HostMessage::Go { depth: Some(2), time_ms: None });
+        let expected = EngineMessage::BestAction { place, action: format_action(&record.actions[decision].action_type) };
+        assert_eq!(answers.last(), Some(&expected));
+        let depths: Vec<u32> = answers.iter().filter_map(|answer| match answer {
+            EngineMessage::Info { depth, .. } => Some(*depth),
+            _ => None
+        }).collect();
+        assert_eq!(depths, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_engine_time_limit() {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4)
+            .map(|place| Box::new(CheaterV1::new(&player_names[place], place as u8, 1)) as Box<dyn MarjapussiCheater>)
+            .collect();
+        let record = play_game(&mut players, player_names.clone(), Some(deal_from_seed(5)), LogFilter::off(), &mut [])
+            .unwrap()
+            .record(Some(5));
+
+        // a search of the first card without a depth limit takes far longer than the time limit
+        let decision = record.actions.iter().position(|action| matches!(action.action_type, ActionType::CardPlayed(_))).unwrap();
+        let place = record.actions[decision].player.0;
+        let mut engine = Engine::new(AgentFactory::cheater(2), 2);
+        run(&mut engine, HostMessage::NewGame { place, player_names, deal: record.deal.clone() });
+        for action in &record.actions[..decision] {
+            run(&mut engine, HostMessage::Action { place: action.player.0, action: format_action(&action.action_type) });
+        }
+        let start = Instant::now();
+        let answers = run(&mut engine, HostMessage::Go { depth: Some(30), time_ms: Some(200) });
+        assert!(start.elapsed() < Duration::from_millis(200), "the answer took {:?}", start.elapsed());
+        assert!(matches!(answers.last(), Some(EngineMessage::BestAction { place: answer_place, .. }) if *answer_place == place));
+        assert!(answers.iter().any(|answer| matches!(answer, EngineMessage::Info { .. })));
+    }
+
+    #[test]
+    fn test_engine_position() {
+        let mut engine = Engine::new(AgentFactory::cheater(2), 2);
+        let position = "e-9,e-K/s-9,e-A,e-Z/s-Z,g-9,e-O/s-7,e-U r-7,e-7 - - 135 1 0:80 0:6 1";
+        run(&mut engine, HostMessage::Position { position: String::from(position) });
+        let answers = run(&mut engine, HostMessage::Go { depth: Some(20), time_ms: None });
+
+        // the search ends before depth 20, since the end of the game is reached
+        let infos = answers.iter().filter(|answer| matches!(answer, EngineMessage::Info { .. })).count();
+        assert!(infos > 1 && infos < 20);
+        assert_eq!(answers.last(), Some(&EngineMessage::BestAction { place: 1, action: String::from("s-9") }));
+        let mut errors = vec![];
+        let result = engine.handle(HostMessage::Action { place: 1, action: String::from("s-9") }, &mut |answer| errors.push(answer));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_run_engine() {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let new_game = HostMessage::NewGame { place: 0, player_names, deal: deal_from_seed(5) };
+        let input = [
+            serde_json::to_string(&HostMessage::Hello).unwrap(),
+            serde_json::to_string(&new_game).unwrap(),
+            String::from(r#"{"type": "action", "place": 0, "action": "r-A"}"#),
+            String::from("no json"),
+            serde_json::to_string(&HostMessage::Go { depth: None, time_ms: None }).unwrap(),
+            serde_json::to_string(&HostMessage::Inspect).unwrap(),
+            serde_json::to_string(&HostMessage::Quit).unwrap(),
+            serde_json::to_string(&HostMessage::Hello).unwrap()
+        ].join("\n");
+        let mut output = vec![];
+        run_engine(input.as_bytes(), &mut output, Engine::new(AgentFactory::cheater(2), 2)).unwrap();
+
+        let answers: Vec<EngineMessage> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(answers.len(), 5, "{:?}", answers);
+        assert_eq!(answers[0], EngineMessage::Ready { name: String::from("CheaterV1 (depth 2)"), protocol: PROTOCOL_VERSION });
+        assert!(matches!(&answers[1], EngineMessage::Error { message } if message.contains("illegal")));
+        assert!(matches!(&answers[2], EngineMessage::Error { message } if message.starts_with("invalid message")));
+        // before the start, every player may start the game
+        assert!(matches!(&answers[3], EngineMessage::BestAction { action, .. } if action == "start"));
+        assert!(matches!(&answers[4], EngineMessage::State { state: Some(state) } if state["place"] == 0));
+    }
+}
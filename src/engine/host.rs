@@ -0,0 +1,211 @@
+/*
+    A reference host for the engine protocol: drives four engines through a full game.
+    The host keeps the game, asks the engine of the player at turn for an action and sends every applied action to all engines.
+ */
+
+use marjapussi::game::cards::Card;
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::Game;
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+
+use super::{parse_protocol_action, EngineMessage, HostMessage};
+use crate::ai::AgentConfig;
+use crate::notation::game::format_action;
+use crate::record::GameRecord;
+
+
+#[derive(Debug)]
+pub enum HostError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    // the engine closed its output
+    Closed(String),
+    // the engine sent a message that does not fit, or an error message
+    Protocol { engine: String, message: String }
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostError::Io(error) => write!(f, "could not communicate with the engine: {}", error),
+            HostError::Json(error) => write!(f, "invalid message of the engine: {}", error),
+            HostError::Closed(engine) => write!(f, "the engine {} closed the connection", engine),
+            HostError::Protocol { engine, message } => write!(f, "engine {}: {}", engine, message)
+        }
+    }
+}
+
+impl std::error::Error for HostError {}
+
+impl From<io::Error> for HostError {
+    fn from(error: io::Error) -> Self {
+        HostError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for HostError {
+    fn from(error: serde_json::Error) -> Self {
+        HostError::Json(error)
+    }
+}
+
+
+// the connection to one engine, usually a child process
+pub struct EngineConnection {
+    // the name of the engine, as sent in "ready"
+    pub name: String,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    process: Option<Child>
+}
+
+impl EngineConnection {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        EngineConnection {
+            name: String::from("(not ready)"),
+            input,
+            output,
+            process: None
+        }
+    }
+
+    pub fn spawn(program: &str, args: &[String]) -> Result<Self, HostError> {
+        let mut process = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let input = BufReader::new(process.stdout.take().expect("The output of the engine is piped"));
+        let output = process.stdin.take().expect("The input of the engine is piped");
+        Ok(EngineConnection {
+            process: Some(process),
+            ..EngineConnection::new(Box::new(input), Box::new(output))
+        })
+    }
+
+    pub fn send(&mut self, message: &HostMessage) -> Result<(), HostError> {
+        writeln!(self.output, "{}", serde_json::to_string(message)?)?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    pub fn receive(&mut self) -> Result<EngineMessage, HostError> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(HostError::Closed(self.name.clone()));
+        }
+        match serde_json::from_str(&line)? {
+            EngineMessage::Error { message } => Err(self.protocol_error(&message)),
+            message => Ok(message)
+        }
+    }
+
+    pub fn close(mut self) -> Result<(), HostError> {
+        self.send(&HostMessage::Quit)?;
+        if let Some(mut process) = self.process.take() {
+            process.wait()?;
+        }
+        Ok(())
+    }
+
+    fn protocol_error(&self, message: &str) -> HostError {
+        HostError::Protocol { engine: self.name.clone(), message: String::from(message) }
+    }
+}
+
+
+pub fn host_game(engines: &mut [EngineConnection], cards: [Vec<Card>; 4], depth: Option<u32>, time_ms: Option<u64>,
+                 verbose: bool) -> Result<Game, HostError> {
+    /*
+        Plays a game with four engines (indexed by their place at the table) and returns the finished game.
+        If verbose is true, every action and the search info of the engines are printed.
+     */
+
+    let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+    for (place, engine) in engines.iter_mut().enumerate() {
+        engine.send(&HostMessage::Hello)?;
+        match engine.receive()? {
+            EngineMessage::Ready { name, .. } => engine.name = format!("{} ({})", name, place),
+            message => return Err(engine.protocol_error(&format!("expected ready, got {:?}", message)))
+        }
+        engine.send(&HostMessage::NewGame { place: place as u8, player_names: player_names.clone(), deal: cards.clone() })?;
+    }
+
+    let mut game = Game::new(String::from("Hosted Game"), player_names, Some(cards));
+    while game.state.phase != GamePhase::Ended {
+        let engine = &mut engines[game.state.player_at_turn.0 as usize];
+        engine.send(&HostMessage::Go { depth, time_ms })?;
+        let action = loop {
+            match engine.receive()? {
+                EngineMessage::Info { depth, score, pv, nodes, time_ms } if verbose => {
+                    println!("  {}: depth {} score {} nodes {} time {} ms pv {}", engine.name, depth, score, nodes, time_ms, pv.join(" "));
+                },
+                EngineMessage::Info { .. } => (),
+                EngineMessage::BestAction { place, action } => {
+                    break parse_protocol_action(place, &action)
+                        .filter(|action| game.legal_actions().contains(action))
+                        .ok_or(engine.protocol_error(&format!("the best action '{}' of place {} is illegal", action, place)))?;
+                },
+                message => return Err(engine.protocol_error(&format!("expected best_action, got {:?}", message)))
+            }
+        };
+        if verbose {
+            println!("{}: {}", engine.name, format_action(&action.action_type));
+        }
+
+        let message = HostMessage::Action { place: action.player.0, action: format_action(&action.action_type) };
+        for engine in engines.iter_mut() {
+            engine.send(&message)?;
+        }
+        game.apply_action_mut(action);
+    }
+    Ok(game)
+}
+
+pub fn hosted_record(game: &Game, engines: &[EngineConnection], seed: Option<u64>, depth: Option<u32>) -> GameRecord {
+    let agents = engines
+        .iter()
+        .map(|engine| AgentConfig { agent: engine.name.clone(), search_depth: depth })
+        .collect();
+    GameRecord::new(game, seed, agents)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deal::deal_from_seed;
+    use crate::engine::{run_engine, Engine};
+    use crate::tournament::AgentFactory;
+    use std::thread;
+
+    fn engine_thread() -> (EngineConnection, thread::JoinHandle<()>) {
+        // the engine runs in a thread and is connected by pipes, just like a child process
+        let (host_input, engine_output) = io::pipe().unwrap();
+        let (engine_input, host_output) = io::pipe().unwrap();
+        let handle = thread::spawn(move || {
+            run_engine(BufReader::new(engine_input), engine_output, Engine::new(AgentFactory::cheater(1), 1)).unwrap();
+        });
+        (EngineConnection::new(Box::new(BufReader::new(host_input)), Box::new(host_output)), handle)
+    }
+
+    #[test]
+    fn test_host_game() {
+        let (mut engines, handles): (Vec<EngineConnection>, Vec<thread::JoinHandle<()>>) = (0..4).map(|_| engine_thread()).unzip();
+        let game = host_game(&mut engines, deal_from_seed(6), Some(2), None, false).unwrap();
+        assert_eq!(game.state.phase, GamePhase::Ended);
+        assert_eq!(engines[0].name, "CheaterV1 (depth 1) (0)");
+
+        let record = hosted_record(&game, &engines, Some(6), Some(2));
+        record.replay().unwrap();
+        for engine in engines {
+            engine.close().unwrap();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
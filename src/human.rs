@@ -0,0 +1,410 @@
+/*
+    A human player in the terminal.
+
+    At every turn the player sees what happened since their last turn, the bidding, the trump, the points,
+    the current trick and their hand, and chooses one of the numbered legal actions. Instead of the number,
+    an action can also be typed in the game notation, e.g. "r-A" or "140". Cards to pass are typed as a list
+    of cards, e.g. "r-6 s-7 g-8 e-9". Undoing is not listed, but can be requested by typing "undo".
+    Typing "hint" shows what the cheater would do: the ranked actions of the search with the expected continuation,
+    or in the bidding the next step of the bidding conventions and what it communicates.
+ */
+
+use marjapussi::game::cards::Card;
+use marjapussi::game::gameevent::{ActionType, AnswerType, GameAction, GameCallback, QuestionType};
+use marjapussi::game::gameinfo::GameFinishedInfo;
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::Game;
+
+use std::io::{self, BufRead, Write};
+
+use crate::ai::{AgentConfig, AgentError, MarjapussiCheater};
+use crate::cheater::{bidding_hint, cardplay_hint, score_so_far};
+use crate::notation::game::{format_action, parse_action};
+use crate::notation::{format_card, parse_card};
+use crate::record::GameRecord;
+use crate::log::LogFilter;
+use crate::simulate_game::{play_game, print_evaluation, GameFailure, GameResult};
+use crate::simulate_game::observer::GameObserver;
+use crate::tournament::AgentFactory;
+
+
+// the number of bids that are listed, the list of all bids up to 420 would be too long
+const LISTED_BIDS: usize = 6;
+
+
+pub struct HumanPlayer {
+    place: u8,
+    // the search depth of the hints
+    hint_depth: u32,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    // the events and tricks of the game that were already shown
+    seen_events: usize,
+    seen_tricks: usize
+}
+
+impl HumanPlayer {
+    pub fn new(place: u8, hint_depth: u32, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        HumanPlayer {
+            place,
+            hint_depth,
+            input,
+            output,
+            seen_events: 0,
+            seen_tricks: 0
+        }
+    }
+
+    pub fn terminal(place: u8, hint_depth: u32) -> Self {
+        HumanPlayer::new(place, hint_depth, Box::new(io::stdin().lock()), Box::new(io::stdout()))
+    }
+
+    fn choose(&mut self, game: &Game) -> io::Result<GameAction> {
+        let legal_actions = game.legal_actions();
+        // there is nothing to decide before the game started, the player at turn starts for everyone
+        if game.state.phase == GamePhase::WaitingForStart {
+            return Ok(legal_actions[0].clone());
+        }
+        // only the lowest bids are listed, higher bids can be typed
+        let bids = legal_actions.iter().filter(|action| matches!(action.action_type, ActionType::NewBid(_))).count();
+        let listed: Vec<GameAction> = legal_actions
+            .iter()
+            .filter(|action| action.action_type != ActionType::UndoRequest)
+            .filter(|action| match action.action_type {
+                ActionType::NewBid(value) => bids <= LISTED_BIDS || legal_actions
+                    .iter()
+                    .filter(|other| matches!(other.action_type, ActionType::NewBid(other_value) if other_value < value))
+                    .count() < LISTED_BIDS,
+                _ => true
+            })
+            .cloned()
+            .collect();
+
+        if let [action] = &listed[..] {
+            self.show(game)?;
+            writeln!(self.output, "only one legal action: {}", describe(&action.action_type))?;
+            return Ok(action.clone());
+        }
+
+        self.show(game)?;
+        let passing = matches!(game.state.phase, GamePhase::PassingForth | GamePhase::PassingBack);
+        if passing {
+            writeln!(self.output, "pass {} cards by typing them, e.g. r-6 s-7 g-8 e-9", passed_cards(&listed))?;
+        } else {
+            writeln!(self.output, "actions:")?;
+            for (number, action) in listed.iter().enumerate() {
+                writeln!(self.output, "  {:>2}) {}", number + 1, describe(&action.action_type))?;
+            }
+            if let Some(highest) = highest_bid(&legal_actions).filter(|_| bids > LISTED_BIDS) {
+                writeln!(self.output, "  or type any higher bid up to {}", highest)?;
+            }
+            writeln!(self.output, "type 'hint' to see what the cheater would do")?;
+        }
+
+        loop {
+            write!(self.output, "your choice: ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the input ended during the game"));
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "hint" {
+                self.show_hint(game)?;
+                continue;
+            }
+            match read_choice(line, &listed, &legal_actions, passing) {
+                Some(action) => return Ok(action),
+                None => writeln!(self.output, "'{}' is not a legal action, type its number or notation", line)?
+            }
+        }
+    }
+
+    fn show_hint(&mut self, game: &Game) -> io::Result<()> {
+        let name = |place: u8| game.state.players[place as usize].name.clone();
+        match game.state.phase {
+            GamePhase::Bidding => {
+                match bidding_hint(game) {
+                    Ok((action_type, Some(info))) => writeln!(self.output, "hint: {}, which communicates {}", format_action(&action_type), info.description()),
+                    Ok((action_type, None)) => writeln!(self.output, "hint: {}, there is nothing (more) to communicate", format_action(&action_type)),
+                    Err(error) => writeln!(self.output, "there is no hint: {}", error)
+                }
+            },
+            GamePhase::Raising | GamePhase::StartTrick | GamePhase::Trick | GamePhase::AnsweringPair | GamePhase::AnsweringHalf(_) => {
+                let hint = match cardplay_hint(game.clone(), Some(self.hint_depth)) {
+                    Ok(hint) => hint,
+                    Err(error) => return writeln!(self.output, "there is no hint: {}", error)
+                };
+                writeln!(self.output, "hint (search depth {}, values for your party):", self.hint_depth)?;
+                for (action, value) in &hint.ranked_actions {
+                    writeln!(self.output, "  {:<10} {:>+5}", format_action(&action.action_type), value)?;
+                }
+                let continuation: Vec<String> = hint.continuation
+                    .iter()
+                    .map(|action| format!("{} {}", name(action.player.0), format_action(&action.action_type)))
+                    .collect();
+                writeln!(self.output, "expected continuation: {}", continuation.join(", "))
+            },
+            _ => writeln!(self.output, "there are no hints for passing cards")
+        }
+    }
+
+    fn show(&mut self, game: &Game) -> io::Result<()> {
+        let state = &game.state;
+        let name = |place: u8| {
+            let name = &state.players[place as usize].name;
+            if place == self.place { format!("{} (you)", name) } else { name.clone() }
+        };
+
+        writeln!(self.output, "\n---------- {} at turn ----------", name(self.place))?;
+
+        // everything that happened since the last turn, the cards passed by other players are hidden
+        for event in &game.all_events[self.seen_events..] {
+            let player = event.last_action.player.0;
+            match (&event.last_action.action_type, &event.callback) {
+                (ActionType::Pass(cards), _) if player != self.place => {
+                    writeln!(self.output, "{}: passes {} cards", name(player), cards.len())?;
+                },
+                (action_type, callback) => {
+                    writeln!(self.output, "{}: {}", name(player), describe(action_type))?;
+                    if let Some(callback) = callback {
+                        writeln!(self.output, "  {}", describe_callback(callback))?;
+                    }
+                }
+            }
+        }
+        for trick in &state.all_tricks[self.seen_tricks..] {
+            writeln!(self.output, "trick {} goes to {} ({} points)", format_cards(&trick.cards), name(trick.winner.0), trick.points.0)?;
+        }
+        self.seen_events = game.all_events.len();
+        self.seen_tricks = state.all_tricks.len();
+
+        // in the cardplay, the value of the game is shown instead
+        let before_cardplay = matches!(state.phase, GamePhase::Bidding | GamePhase::PassingForth | GamePhase::PassingBack);
+        if before_cardplay && !state.bidding_history.is_empty() {
+            let bids: Vec<String> = state.bidding_history
+                .iter()
+                .map(|(action_type, place)| format!("{} {}", name(place.0), format_action(action_type)))
+                .collect();
+            writeln!(self.output, "bidding: {}", bids.join(", "))?;
+        }
+
+        if !matches!(state.phase, GamePhase::WaitingForStart | GamePhase::Bidding) {
+            let (points, tricks, playing_party) = score_so_far(game);
+            let party = |party: u8| format!("{} and {}", name(party), name(party + 2));
+            match playing_party {
+                Some(playing) => writeln!(self.output, "game value: {}, played by {}", state.value.0, party(playing))?,
+                None => writeln!(self.output, "no one plays the game")?
+            }
+            let trump = state.trump.map_or(String::from("none"), |suit| suit.to_string());
+            let pairs: Vec<String> = state.trump_called.iter().map(|suit| suit.to_string()).collect();
+            writeln!(self.output, "trump: {}, announced pairs: {}", trump, if pairs.is_empty() { String::from("none") } else { pairs.join(", ") })?;
+            for party_place in [0, 1] {
+                writeln!(self.output, "{}: {} points, {} tricks", party(party_place), points[party_place as usize], tricks[party_place as usize])?;
+            }
+        }
+
+        // a trick with four cards is already finished and only kept until the next card is played
+        if !state.current_trick.is_empty() && state.current_trick.len() < 4 {
+            let leader = (state.player_at_turn.0 + 4 - state.current_trick.len() as u8) % 4;
+            let cards: Vec<String> = state.current_trick
+                .iter()
+                .enumerate()
+                .map(|(index, card)| format!("{} {}", name((leader + index as u8) % 4), format_card(card)))
+                .collect();
+            writeln!(self.output, "trick: {}", cards.join(", "))?;
+        }
+        writeln!(self.output, "hand: {}", format_cards(&state.players[self.place as usize].cards))
+    }
+}
+
+impl MarjapussiCheater for HumanPlayer {
+    fn select_action(&mut self, gamestate: Game) -> Result<GameAction, AgentError> {
+        Ok(self.choose(&gamestate)?)
+    }
+
+    fn config(&self) -> AgentConfig {
+        AgentConfig {
+            agent: String::from("human"),
+            search_depth: None
+        }
+    }
+}
+
+
+fn read_choice(line: &str, listed: &[GameAction], legal_actions: &[GameAction], passing: bool) -> Option<GameAction> {
+    /*
+        The action chosen by a line of input: the number of a listed action, an action in the game notation,
+        "undo" or the cards to pass. None if the line does not describe a legal action.
+     */
+
+    if passing {
+        let mut cards = line
+            .split([' ', ','])
+            .filter(|word| !word.is_empty())
+            .map(|word| parse_card(word).ok())
+            .collect::<Option<Vec<Card>>>()?;
+        cards.sort();
+        return listed.iter().find(|action| match &action.action_type {
+            ActionType::Pass(passed) => {
+                let mut passed = passed.clone();
+                passed.sort();
+                passed == cards
+            },
+            _ => false
+        }).cloned();
+    }
+    if let Ok(number) = line.parse::<usize>() {
+        // bids are numbers as well, but far larger than the number of listed actions
+        if (1..=listed.len()).contains(&number) {
+            return Some(listed[number - 1].clone());
+        }
+    }
+    let action_type = parse_action(line)?;
+    legal_actions.iter().find(|action| action.action_type == action_type).cloned()
+}
+
+fn highest_bid(legal_actions: &[GameAction]) -> Option<i32> {
+    legal_actions
+        .iter()
+        .filter_map(|action| match action.action_type {
+            ActionType::NewBid(value) => Some(value),
+            _ => None
+        })
+        .max()
+}
+
+fn passed_cards(listed: &[GameAction]) -> usize {
+    listed
+        .iter()
+        .find_map(|action| match &action.action_type {
+            ActionType::Pass(cards) => Some(cards.len()),
+            _ => None
+        })
+        .unwrap_or_default()
+}
+
+fn describe(action_type: &ActionType) -> String {
+    /*
+        The action in the game notation, followed by an explanation for actions whose notation is not obvious.
+     */
+
+    let explanation = match action_type {
+        ActionType::Start => String::from("start the game"),
+        ActionType::NewBid(value) => format!("bid {}", value),
+        ActionType::StopBidding => String::from("stop bidding"),
+        ActionType::Question(QuestionType::Yours) => String::from("ask the partner for a pair"),
+        ActionType::Question(QuestionType::YourHalf(suit)) => format!("ask the partner for a half of {}", suit),
+        ActionType::Answer(AnswerType::YesPair(suit)) => format!("answer: a pair of {}", suit),
+        ActionType::Answer(AnswerType::NoPair) => String::from("answer: no pair"),
+        ActionType::Answer(AnswerType::YesHalf(suit)) => format!("answer: a half of {}", suit),
+        ActionType::Answer(AnswerType::NoHalf(suit)) => format!("answer: no half of {}", suit),
+        ActionType::AnnounceTrump(suit) => format!("announce the pair of {}", suit),
+        ActionType::UndoRequest => String::from("request to undo the last action"),
+        ActionType::UndoDecline => String::from("decline to undo"),
+        ActionType::UndoAccept => String::from("accept to undo"),
+        ActionType::Pass(_) | ActionType::CardPlayed(_) => return format_action(action_type)
+    };
+    format!("{:<10} {}", format_action(action_type), explanation)
+}
+
+fn describe_callback(callback: &GameCallback) -> String {
+    match callback {
+        GameCallback::NewTrump(suit) => format!("{} is trump now", suit),
+        GameCallback::StillTrump(suit) => format!("{} stays trump", suit),
+        GameCallback::NoHalf(suit) => format!("no half of {}", suit),
+        GameCallback::OnlyHalf(suit) => format!("only a half of {}", suit)
+    }
+}
+
+fn format_cards(cards: &[Card]) -> String {
+    cards.iter().map(format_card).collect::<Vec<String>>().join(" ")
+}
+
+
+pub fn play_against_agents(seats: &[AgentFactory], cards: [Vec<Card>; 4], seed: Option<u64>,
+                           observers: &mut [&mut dyn GameObserver]) -> Result<GameRecord, GameFailure> {
+    /*
+        Plays a game in which every seat is played by an agent of its factory, usually some of them humans.
+        Only the human players show the game, so no one sees the cards of the others.
+     */
+
+    let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+    let mut players: Vec<Box<dyn MarjapussiCheater>> = seats
+        .iter()
+        .enumerate()
+        .map(|(place, seat)| seat.build(&player_names[place], place as u8))
+        .collect();
+    let played_game = play_game(&mut players, player_names.clone(), Some(cards), LogFilter::off(), observers)?;
+
+    println!("\n---------- Game ended ----------");
+    if let Some(last_trick) = played_game.game.state.all_tricks.last() {
+        println!("last trick {} goes to {}", format_cards(&last_trick.cards), player_names[last_trick.winner.0 as usize]);
+    }
+    print_evaluation(&GameResult::from_game(&played_game.game), GameFinishedInfo::from(played_game.game.clone()).won);
+    Ok(played_game.record(seed))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deal::deal_from_seed;
+    use marjapussi::game::player::PlaceAtTable;
+    use std::io::Cursor;
+
+    fn human(place: u8, input: &str) -> HumanPlayer {
+        HumanPlayer::new(place, 2, Box::new(Cursor::new(String::from(input))), Box::new(io::sink()))
+    }
+
+    fn game() -> Game {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut game = Game::new(String::from("Test"), player_names, Some(deal_from_seed(3)));
+        for place in 0..4 {
+            game.apply_action_mut(GameAction { action_type: ActionType::Start, player: PlaceAtTable(place) });
+        }
+        game
+    }
+
+    #[test]
+    fn test_select_action() {
+        let game = game();
+        assert_eq!(game.state.phase, GamePhase::Bidding);
+        let listed: Vec<ActionType> = game.legal_actions()
+            .into_iter()
+            .map(|action| action.action_type)
+            .filter(|action_type| *action_type != ActionType::UndoRequest)
+            .collect();
+
+        // invalid input is asked again
+        assert_eq!(human(0, "2\n").select_action(game.clone()).unwrap().action_type, listed[1]);
+        assert_eq!(human(0, "\nfoo\n99\n1\n").select_action(game.clone()).unwrap().action_type, listed[0]);
+        assert_eq!(human(0, "stop\n").select_action(game.clone()).unwrap().action_type, ActionType::StopBidding);
+        assert_eq!(human(0, "140\n").select_action(game.clone()).unwrap().action_type, ActionType::NewBid(140));
+    }
+
+    #[test]
+    fn test_read_choice() {
+        let player = PlaceAtTable(0);
+        let cards: Vec<Card> = ["r-6", "s-7", "g-8", "e-9"].iter().map(|card| parse_card(card).unwrap()).collect();
+        let mut reversed = cards.clone();
+        reversed.reverse();
+        let pass = GameAction { action_type: ActionType::Pass(reversed), player: player.clone() };
+        let other = GameAction { action_type: ActionType::Pass(cards[..3].to_vec()), player: player.clone() };
+        let listed = vec![other, pass.clone()];
+        assert_eq!(read_choice("r-6 s-7 g-8 e-9", &listed, &listed, true), Some(pass));
+        assert_eq!(read_choice("r-6, s-7", &listed, &listed, true), None);
+        assert_eq!(read_choice("1", &listed, &listed, true), None);
+
+        // undo is not listed, but can be requested
+        let undo = GameAction { action_type: ActionType::UndoRequest, player: player.clone() };
+        let card = GameAction { action_type: ActionType::CardPlayed(cards[0].clone()), player };
+        let legal_actions = vec![card.clone(), undo.clone()];
+        assert_eq!(read_choice("undo", &legal_actions[..1], &legal_actions, false), Some(undo));
+        assert_eq!(read_choice("r-6", &legal_actions[..1], &legal_actions, false), Some(card));
+        assert_eq!(read_choice("2", &legal_actions[..1], &legal_actions, false), None);
+    }
+}
@@ -0,0 +1,167 @@
+/*
+    Logging for agents and the simulator.
+
+    Every entry has a level and the module it comes from (e.g. "cheater::bidding").
+    A filter decides per module up to which level entries are kept. It is written like "info,cheater::bidding=debug":
+    a default level followed by levels for single modules, where "off" keeps nothing.
+    A module filter also applies to the modules below it, "cheater=off" silences "cheater::bidding" as well.
+
+    The owner of a logger (usually an agent) sets its filter. The entries that pass the filter are printed.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown log level '{}'", input))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    // the highest level that is kept, None keeps nothing
+    default: Option<Level>,
+    modules: Vec<(String, Option<Level>)>
+}
+
+impl LogFilter {
+    pub fn off() -> Self {
+        LogFilter { default: None, modules: vec![] }
+    }
+
+    pub fn level(level: Level) -> Self {
+        LogFilter { default: Some(level), modules: vec![] }
+    }
+
+    pub fn enabled(&self, level: Level, module: &str) -> bool {
+        /*
+            The most specific module filter decides, without one the default level.
+         */
+
+        let module_level = self.modules
+            .iter()
+            .filter(|(name, _)| module == name || module.strip_prefix(name.as_str()).is_some_and(|rest| rest.starts_with("::")))
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.default, |(_, level)| *level);
+        module_level.is_some_and(|highest| level <= highest)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let parse_level = |level: &str| match level.trim() {
+            "off" => Ok(None),
+            level => level.parse().map(Some)
+        };
+        let mut filter = LogFilter::off();
+        for part in input.split(',').filter(|part| !part.trim().is_empty()) {
+            match part.split_once('=') {
+                Some((module, level)) => filter.modules.push((String::from(module.trim()), parse_level(level)?)),
+                None => filter.default = parse_level(part)?
+            }
+        }
+        Ok(filter)
+    }
+}
+
+
+pub struct Logger {
+    filter: LogFilter
+}
+
+impl Logger {
+    pub fn new(filter: LogFilter) -> Self {
+        Logger { filter }
+    }
+
+    pub fn silent() -> Self {
+        Logger::new(LogFilter::off())
+    }
+
+    pub fn set_filter(&mut self, filter: LogFilter) {
+        self.filter = filter;
+    }
+
+    pub fn enabled(&self, level: Level, module: &str) -> bool {
+        self.filter.enabled(level, module)
+    }
+
+    pub fn log(&self, level: Level, module: &str, message: impl FnOnce() -> String) {
+        /*
+            Logs the message if the filter lets it pass. The message is only built in this case.
+         */
+
+        if !self.enabled(level, module) {
+            return;
+        }
+        println!("  [{} {}] {}", level, module, message());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter: LogFilter = "warn,cheater::bidding=debug,cheater::search=off".parse().unwrap();
+        assert!(filter.enabled(Level::Warn, "cheater"));
+        assert!(!filter.enabled(Level::Info, "cheater"));
+        assert!(filter.enabled(Level::Debug, "cheater::bidding"));
+        assert!(!filter.enabled(Level::Trace, "cheater::bidding"));
+        assert!(!filter.enabled(Level::Error, "cheater::search"));
+        // a module filter does not apply to modules that only start with the same letters
+        assert!(!filter.enabled(Level::Debug, "cheater::biddings"));
+
+        assert_eq!("off".parse::<LogFilter>(), Ok(LogFilter::off()));
+        assert_eq!("".parse::<LogFilter>(), Ok(LogFilter::off()));
+        assert!("loud".parse::<LogFilter>().is_err());
+        assert!("cheater=loud".parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn test_logger() {
+        let mut logger = Logger::silent();
+        assert!(!logger.enabled(Level::Error, "cheater"));
+        logger.set_filter(LogFilter::level(Level::Info));
+        assert!(logger.enabled(Level::Info, "cheater::bidding"));
+        logger.log(Level::Debug, "cheater", || panic!("filtered messages are not built"));
+    }
+}
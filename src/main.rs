@@ -1,31 +1,31 @@
 mod alpha_beta;
+mod analysis;
+mod bench;
 mod simulate_game;
 mod ai;
 mod cheater;
+mod cli;
+mod deal;
+mod engine;
+mod human;
+mod log;
+mod notation;
+mod record;
+mod repro;
+mod server;
+mod suite;
+mod tournament;
 
-use std::time::Instant;
+use std::process::ExitCode;
 
-fn main() {
-
-    // simulate_game::bug();
-
-    // run some games and measure the execution time
-    let iterations = 1000;
-    let search_depth = 6;
-    let now = Instant::now();
-    for _ in 0..iterations {
-        simulate_game::four_cheaters(search_depth, None);
-    }
-    let elapsed = now.elapsed();
-    let average = elapsed.div_f64(f64::from(iterations));
-
-    // print the execution time and some information about the calculated search trees
-    println!("\n ---------- Test results ----------");
-    println!("number of simulated games: {}", iterations);
-    println!("total elapsed time: {:?}", elapsed);
-    println!("avg execution time per game: {:?}", average);
-    println!("max search depth: {}", search_depth);
-    unsafe {
-        cheater::print_avg_tree_size();
+fn main() -> ExitCode {
+    // see "marjapussi-ki help" for the commands
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::from(error.exit_code())
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,370 @@
+/*
+    The card notation we use in logs and bug reports.
+
+    card: <suit>-<value>, with the suits g (green), e (acorns), s (bells), r (red)
+          and the values 6, 7, 8, 9, U (Unter), O (Ober), K (King), Z (Ten), A (Ace)
+    hand: [g-U, e-9, s-A]
+    deal: one line per player, e.g. "0: [g-U, e-9, ...]", for the places 0 to 3 in that order
+
+    Cards and hands are formatted exactly like their Debug output, so everything printed by the simulation can be parsed again.
+    The notations for whole games (see game.rs) and positions (see position.rs) build on these.
+ */
+
+pub mod game;
+pub mod position;
+
+use marjapussi::game::cards::{Card, Suit, Value};
+
+use std::fmt;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotationErrorKind {
+    UnexpectedEnd,
+    UnexpectedChar(char),
+    UnknownSuit(char),
+    UnknownValue(char),
+    WrongPlace { expected: usize, found: String },
+    WrongNumberOfCards { place: usize, count: usize },
+    DuplicateCard(Card),
+    UnknownTag(String),
+    InvalidTag { tag: String, value: String },
+    TagMismatch { tag: String, expected: String, found: String },
+    UnknownSection(String),
+    WrongSection { expected: String, found: String },
+    InvalidPlace(String),
+    UnknownAction(String),
+    IllegalAction(String),
+    UnfinishedGame,
+    InvalidPosition(String)
+}
+
+// an error together with the position (1-based line and column) where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotationError {
+    pub kind: NotationErrorKind,
+    pub line: usize,
+    pub column: usize
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match &self.kind {
+            NotationErrorKind::UnexpectedEnd => write!(f, "unexpected end of input"),
+            NotationErrorKind::UnexpectedChar(char) => write!(f, "unexpected character '{}'", char),
+            NotationErrorKind::UnknownSuit(char) => write!(f, "unknown suit '{}' (expected one of g, e, s, r)", char),
+            NotationErrorKind::UnknownValue(char) => write!(f, "unknown value '{}' (expected one of 6, 7, 8, 9, U, O, K, Z, A)", char),
+            NotationErrorKind::WrongPlace { expected, found } => write!(f, "expected the cards of place {}, found '{}'", expected, found),
+            NotationErrorKind::WrongNumberOfCards { place, count } => write!(f, "place {} has {} cards instead of 9", place, count),
+            NotationErrorKind::DuplicateCard(card) => write!(f, "{} was dealt twice", card),
+            NotationErrorKind::UnknownTag(tag) => write!(f, "unknown tag '{}'", tag),
+            NotationErrorKind::InvalidTag { tag, value } => write!(f, "invalid value \"{}\" for the tag {}", value, tag),
+            NotationErrorKind::TagMismatch { tag, expected, found } => write!(f, "the tag {} is \"{}\", but the game says \"{}\"", tag, found, expected),
+            NotationErrorKind::UnknownSection(section) => write!(f, "unknown section '{}'", section),
+            NotationErrorKind::WrongSection { expected, found } => write!(f, "the next action belongs to '{}', not to '{}'", expected, found),
+            NotationErrorKind::InvalidPlace(place) => write!(f, "invalid place '{}' (expected one of 0, 1, 2, 3)", place),
+            NotationErrorKind::UnknownAction(action) => write!(f, "unknown action '{}'", action),
+            NotationErrorKind::IllegalAction(action) => write!(f, "the action '{}' is illegal here", action),
+            NotationErrorKind::UnfinishedGame => write!(f, "the game is not finished"),
+            NotationErrorKind::InvalidPosition(reason) => write!(f, "invalid position: {}", reason)
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+
+pub fn parse_card(input: &str) -> Result<Card, NotationError> {
+    let mut parser = Parser::new(input);
+    let card = parser.card()?;
+    parser.end()?;
+    Ok(card)
+}
+
+pub fn parse_hand(input: &str) -> Result<Vec<Card>, NotationError> {
+    let mut parser = Parser::new(input);
+    parser.skip_whitespace();
+    let hand = parser.hand()?;
+    parser.skip_whitespace();
+    parser.end()?;
+    Ok(hand.into_iter().map(|(_, card)| card).collect())
+}
+
+pub fn parse_deal(input: &str) -> Result<[Vec<Card>; 4], NotationError> {
+    /*
+        Parses the four hands of a deal. Every player must have nine cards and no card may be dealt twice.
+     */
+
+    let mut parser = Parser::new(input);
+    let deal = parser.deal()?;
+    parser.skip_whitespace();
+    parser.end()?;
+    Ok(deal)
+}
+
+
+pub fn format_card(card: &Card) -> String {
+    card.to_string()
+}
+
+pub fn format_hand(cards: &[Card]) -> String {
+    let cards: Vec<String> = cards.iter().map(format_card).collect();
+    format!("[{}]", cards.join(", "))
+}
+
+pub fn format_deal(deal: &[Vec<Card>; 4]) -> String {
+    deal.iter()
+        .enumerate()
+        .map(|(place, cards)| format!("{}: {}", place, format_hand(cards)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+
+fn suit_from_char(char: char) -> Option<Suit> {
+    match char {
+        'g' => Some(Suit::Green),
+        'e' => Some(Suit::Acorns),
+        's' => Some(Suit::Bells),
+        'r' => Some(Suit::Red),
+        _ => None
+    }
+}
+
+fn suit_char(suit: Suit) -> char {
+    match suit {
+        Suit::Green => 'g',
+        Suit::Acorns => 'e',
+        Suit::Bells => 's',
+        Suit::Red => 'r'
+    }
+}
+
+
+// the position of the next character in the input
+#[derive(Clone, Copy)]
+struct Position {
+    line: usize,
+    column: usize
+}
+
+impl Position {
+    fn error(self, kind: NotationErrorKind) -> NotationError {
+        NotationError {
+            kind,
+            line: self.line,
+            column: self.column
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    position: Position
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser {
+            chars: input.chars().peekable(),
+            position: Position { line: 1, column: 1 }
+        }
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Result<char, NotationError> {
+        let char = self.chars.next().ok_or(self.position.error(NotationErrorKind::UnexpectedEnd))?;
+        if char == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Ok(char)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(char) = self.peek().filter(|char| predicate(*char)) {
+            taken.push(char);
+            let _ = self.next();
+        }
+        taken
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), NotationError> {
+        let position = self.position;
+        match self.next()? {
+            char if char == expected => Ok(()),
+            char => Err(position.error(NotationErrorKind::UnexpectedChar(char)))
+        }
+    }
+
+    fn end(&mut self) -> Result<(), NotationError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(char) => Err(self.position.error(NotationErrorKind::UnexpectedChar(char)))
+        }
+    }
+
+    fn card(&mut self) -> Result<Card, NotationError> {
+        let suit_position = self.position;
+        let char = self.next()?;
+        let suit = suit_from_char(char).ok_or(suit_position.error(NotationErrorKind::UnknownSuit(char)))?;
+        self.expect('-')?;
+        let value_position = self.position;
+        let value = match self.next()? {
+            '6' => Value::Six,
+            '7' => Value::Seven,
+            '8' => Value::Eight,
+            '9' => Value::Nine,
+            'U' => Value::Unter,
+            'O' => Value::Ober,
+            'K' => Value::King,
+            'Z' => Value::Ten,
+            'A' => Value::Ace,
+            char => return Err(value_position.error(NotationErrorKind::UnknownValue(char)))
+        };
+        // a card is always followed by a separator
+        if let Some(char) = self.peek().filter(|char| char.is_alphanumeric()) {
+            return Err(self.position.error(NotationErrorKind::UnexpectedChar(char)));
+        }
+        Ok(Card { suit, value })
+    }
+
+    fn hand(&mut self) -> Result<Vec<(Position, Card)>, NotationError> {
+        // every card together with its position, so errors about single cards can point to them
+        let mut cards = vec![];
+        self.expect('[')?;
+        self.skip_whitespace();
+        if self.peek() != Some(']') {
+            loop {
+                cards.push((self.position, self.card()?));
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    break;
+                }
+                self.expect(',')?;
+                self.skip_whitespace();
+            }
+        }
+        self.expect(']')?;
+        Ok(cards)
+    }
+
+    fn deal(&mut self) -> Result<[Vec<Card>; 4], NotationError> {
+        let mut deal: [Vec<Card>; 4] = [vec![], vec![], vec![], vec![]];
+        let mut dealt_cards: Vec<Card> = vec![];
+        for (place, hand) in deal.iter_mut().enumerate() {
+            self.skip_whitespace();
+
+            // the place at the table
+            let place_start = self.position();
+            let place_string = self.take_while(|char| char.is_ascii_digit());
+            if place_string != place.to_string() {
+                let found = match self.peek() {
+                    _ if !place_string.is_empty() => place_string,
+                    Some(char) => String::from(char),
+                    None => return Err(place_start.error(NotationErrorKind::UnexpectedEnd))
+                };
+                return Err(place_start.error(NotationErrorKind::WrongPlace { expected: place, found }));
+            }
+            self.expect(':')?;
+            self.skip_whitespace();
+
+            // the cards of the player
+            let hand_start = self.position();
+            let cards = self.hand()?;
+            for (card_start, card) in &cards {
+                if dealt_cards.contains(card) {
+                    return Err(card_start.error(NotationErrorKind::DuplicateCard(card.clone())));
+                }
+                dealt_cards.push(card.clone());
+            }
+            if cards.len() != 9 {
+                return Err(hand_start.error(NotationErrorKind::WrongNumberOfCards { place, count: cards.len() }));
+            }
+            *hand = cards.into_iter().map(|(_, card)| card).collect();
+        }
+        Ok(deal)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deal::deal_from_seed;
+
+    #[test]
+    fn test_round_trip() {
+        for seed in 0..20 {
+            let deal = deal_from_seed(seed);
+            let formatted = format_deal(&deal);
+            assert_eq!(parse_deal(&formatted), Ok(deal.clone()));
+            for hand in &deal {
+                // the notation is the same as the Debug output in our logs
+                assert_eq!(format_hand(hand), format!("{:?}", hand));
+                assert_eq!(parse_hand(&format!("{:?}", hand)).as_ref(), Ok(hand));
+                for card in hand {
+                    assert_eq!(parse_card(&format!("{:?}", card)).as_ref(), Ok(card));
+                }
+            }
+        }
+        assert_eq!(parse_hand(" [ ] "), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_errors() {
+        fn error<T>(kind: NotationErrorKind, line: usize, column: usize) -> Result<T, NotationError> {
+            Err(NotationError { kind, line, column })
+        }
+
+        assert_eq!(parse_card("x-U"), error(NotationErrorKind::UnknownSuit('x'), 1, 1));
+        assert_eq!(parse_card("g-X"), error(NotationErrorKind::UnknownValue('X'), 1, 3));
+        assert_eq!(parse_card("g-"), error(NotationErrorKind::UnexpectedEnd, 1, 3));
+        assert_eq!(parse_card("g-UU"), error(NotationErrorKind::UnexpectedChar('U'), 1, 4));
+        assert_eq!(parse_hand("[g-U e-9]"), error(NotationErrorKind::UnexpectedChar('e'), 1, 6));
+
+        let deal = format_deal(&deal_from_seed(1));
+        let mut lines: Vec<String> = deal.lines().map(String::from).collect();
+
+        // the same card twice
+        let first_card = &lines[0][4..7];
+        let duplicate = lines[2].replacen(&lines[2][4..7], first_card, 1);
+        let with_duplicate = [lines[0].clone(), lines[1].clone(), duplicate, lines[3].clone()].join("\n");
+        assert_eq!(
+            parse_deal(&with_duplicate),
+            error(NotationErrorKind::DuplicateCard(parse_card(first_card).unwrap()), 3, 5)
+        );
+
+        // missing cards and places
+        lines[1] = format!("1: [{} {}]", &lines[1][4..7], &lines[1][9..12]);
+        assert_eq!(parse_deal(&lines[..2].join("\n")), error(NotationErrorKind::UnexpectedChar(lines[1].chars().nth(8).unwrap()), 2, 9));
+        lines[1] = format!("1: [{}]", &lines[1][4..7]);
+        assert_eq!(
+            parse_deal(&lines[..2].join("\n")),
+            error(NotationErrorKind::WrongNumberOfCards { place: 1, count: 1 }, 2, 4)
+        );
+        assert_eq!(
+            parse_deal(&lines[..1].join("\n")),
+            error(NotationErrorKind::UnexpectedEnd, 1, 49)
+        );
+        assert_eq!(
+            parse_deal("1: []"),
+            error(NotationErrorKind::WrongPlace { expected: 0, found: String::from("1") }, 1, 1)
+        );
+    }
+}
@@ -0,0 +1,400 @@
+/*
+    A compact text notation for whole games, similar to PGN in chess.
+
+    [Event "Cheater Game"]
+    [Seed "99"]
+    [Player0 "Player 1"]
+    ...
+    [Value "140"]
+    [PlayingParty "0"]
+    [Result "won"]
+
+    0: [e-7, g-6, g-Z, r-U, g-8, s-Z, r-O, s-A, r-K]
+    ...
+
+    bidding: 0:120 1:125 2:stop 3:stop ...
+    passing: 2:pass [g-A, r-A, e-A, s-A] 0:pass [g-6, e-6, r-6, s-6]
+    raising: 2:150
+    trick 1: 2:g-A 3:g-6 0:g-U 1:g-7
+    trick 2: 2:?pair 0:!pair-r ...
+
+    All tags are optional. Value, PlayingParty and Result are not needed to replay the game,
+    but if they are present, they must match the replayed game.
+    The deal is written in the deal notation. Every action is written as <place>:<action>:
+        bids: 120, stop
+        passing: pass <hand>
+        cards: g-U
+        questions: ?pair, ?half-g
+        answers: !pair-g, !nopair, !half-g, !nohalf-g
+        announcing a pair: trump-g
+        undo: undo, undo-accept, undo-decline
+    The actions are grouped into the sections bidding, passing, raising and trick <number>.
+    Starting the game is implicit.
+ */
+
+use marjapussi::game::gameevent::{ActionType, AnswerType, GameAction, QuestionType};
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::player::PlaceAtTable;
+use marjapussi::game::Game;
+
+use super::{format_card, format_deal, format_hand, parse_card, suit_char, suit_from_char};
+use super::{NotationError, NotationErrorKind, Parser, Position};
+use crate::record::{GameRecord, RecordError};
+use crate::simulate_game::GameResult;
+
+
+pub fn format_game(record: &GameRecord) -> Result<String, RecordError> {
+    /*
+        Writes a recorded game in the text notation.
+        Fails if the recorded actions cannot be replayed.
+     */
+
+    // replay the game to find out which section each action belongs to
+    let mut game = Game::new(record.game_name.clone(), record.player_names.clone(), Some(record.deal.clone()));
+    let mut sections: Vec<(String, Vec<String>)> = vec![];
+    for (index, action) in record.actions.iter().enumerate() {
+        if let Some(section) = section(&game.state.phase, &game, &action.action_type) {
+            let action_string = format!("{}:{}", action.player.0, format_action(&action.action_type));
+            match sections.last_mut() {
+                Some((last_section, actions)) if *last_section == section => actions.push(action_string),
+                _ => sections.push((section, vec![action_string]))
+            }
+        }
+        game = game
+            .apply_action(action.clone())
+            .map_err(|_| RecordError::IllegalAction { index, action: action.clone() })?;
+    }
+    if !game.ended() {
+        return Err(RecordError::Unfinished);
+    }
+
+    let mut tags = vec![(String::from("Event"), record.game_name.clone())];
+    if let Some(seed) = record.seed {
+        tags.push((String::from("Seed"), seed.to_string()));
+    }
+    for (place, name) in record.player_names.iter().enumerate() {
+        tags.push((format!("Player{}", place), name.clone()));
+    }
+    tags.extend(result_tags(&game).map(|(tag, value)| (String::from(tag), value)));
+
+    let mut text = String::new();
+    for (tag, value) in tags {
+        text += &format!("[{} \"{}\"]\n", tag, value.replace('\\', "\\\\").replace('"', "\\\""));
+    }
+    text += &format!("\n{}\n\n", format_deal(&record.deal));
+    for (section, actions) in sections {
+        text += &format!("{}: {}\n", section, actions.join(" "));
+    }
+    Ok(text)
+}
+
+pub fn parse_game(input: &str) -> Result<GameRecord, NotationError> {
+    /*
+        Reads a game in the text notation and replays it.
+        The game must be finished. The agents that played the game are not part of the notation, so they are left empty.
+     */
+
+    let mut parser = Parser::new(input);
+
+    // the tags
+    let mut game_name = String::from("Game");
+    let mut seed = None;
+    let mut player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+    let mut result_checks = vec![];
+    parser.skip_whitespace();
+    while parser.peek() == Some('[') {
+        let (position, tag, value) = parser.tag()?;
+        let invalid = || position.error(NotationErrorKind::InvalidTag { tag: tag.clone(), value: value.clone() });
+        match tag.as_str() {
+            "Event" => game_name = value,
+            "Seed" => seed = Some(value.parse().map_err(|_| invalid())?),
+            "Player0" | "Player1" | "Player2" | "Player3" => {
+                let place = usize::from(tag.as_bytes()[6] - b'0');
+                player_names[place] = value;
+            },
+            "Value" | "PlayingParty" | "Result" => result_checks.push((position, tag, value)),
+            _ => return Err(position.error(NotationErrorKind::UnknownTag(tag)))
+        }
+        parser.skip_whitespace();
+    }
+
+    // the deal
+    let deal = parser.deal()?;
+    let mut game = Game::new(game_name, player_names, Some(deal));
+
+    // the actions
+    let mut current_section: Option<String> = None;
+    loop {
+        start_game(&mut game);
+        parser.skip_whitespace();
+        let Some(next_char) = parser.peek() else {
+            break;
+        };
+        let start = parser.position();
+
+        // a new section
+        if next_char.is_ascii_alphabetic() {
+            let name = parser.take_while(|char| char != ':' && char != '\n');
+            let known = ["bidding", "passing", "raising"].contains(&name.as_str())
+                || name.strip_prefix("trick ").is_some_and(|number| number.parse::<usize>().is_ok());
+            if !known {
+                return Err(start.error(NotationErrorKind::UnknownSection(name)));
+            }
+            parser.expect(':')?;
+            current_section = Some(name);
+            continue;
+        }
+
+        // an action
+        let place = parser.take_while(|char| char.is_ascii_digit());
+        if !["0", "1", "2", "3"].contains(&place.as_str()) {
+            return Err(start.error(NotationErrorKind::InvalidPlace(place)));
+        }
+        parser.expect(':')?;
+        let word = parser.take_while(|char| char.is_ascii_alphanumeric() || "-?!".contains(char));
+        let action_string = format!("{}:{}", place, word);
+        let action_type = if word == "pass" {
+            parser.skip_whitespace();
+            ActionType::Pass(parser.hand()?.into_iter().map(|(_, card)| card).collect())
+        } else {
+            parse_action(&word).ok_or(start.error(NotationErrorKind::UnknownAction(action_string.clone())))?
+        };
+
+        let expected_section = section(&game.state.phase, &game, &action_type).unwrap_or_default();
+        let found_section = current_section.clone().unwrap_or_default();
+        if expected_section != found_section {
+            return Err(start.error(NotationErrorKind::WrongSection { expected: expected_section, found: found_section }));
+        }
+
+        let action = GameAction {
+            action_type,
+            player: PlaceAtTable(place.parse().unwrap())
+        };
+        game = game
+            .apply_action(action)
+            .map_err(|_| start.error(NotationErrorKind::IllegalAction(action_string)))?;
+    }
+    if !game.ended() {
+        return Err(parser.position().error(NotationErrorKind::UnfinishedGame));
+    }
+
+    // the tags about the result must match the game
+    let results = result_tags(&game);
+    for (position, tag, found) in result_checks {
+        let (_, expected) = results.iter().find(|(result_tag, _)| *result_tag == tag).unwrap();
+        if *expected != found {
+            return Err(position.error(NotationErrorKind::TagMismatch { tag, expected: expected.clone(), found }));
+        }
+    }
+
+    Ok(GameRecord::new(&game, seed, vec![]))
+}
+
+
+pub fn section(phase: &GamePhase, game: &Game, action_type: &ActionType) -> Option<String> {
+    /*
+        The section an action belongs to if it is applied in the given phase.
+        None for starting the game, which is implicit.
+     */
+
+    match phase {
+        GamePhase::WaitingForStart => None,
+        GamePhase::Bidding => Some(String::from("bidding")),
+        GamePhase::PassingForth | GamePhase::PassingBack => Some(String::from("passing")),
+        GamePhase::Raising if !matches!(action_type, ActionType::CardPlayed(_)) => Some(String::from("raising")),
+        GamePhase::PendingUndo(phase) => section(phase, game, action_type),
+        _ => Some(format!("trick {}", game.state.all_tricks.len() + 1))
+    }
+}
+
+fn start_game(game: &mut Game) {
+    while game.state.phase == GamePhase::WaitingForStart {
+        let start = game
+            .legal_actions
+            .iter()
+            .find(|action| action.action_type == ActionType::Start)
+            .expect("A game that was not started can always be started")
+            .clone();
+        game.apply_action_mut(start);
+    }
+}
+
+fn result_tags(game: &Game) -> [(&'static str, String); 3] {
+    let result = GameResult::from_game(game);
+    [
+        ("Value", result.game_value.to_string()),
+        ("PlayingParty", result.playing_party.map_or(String::from("-"), |party| party.to_string())),
+        ("Result", match result.won() {
+            Some(true) => String::from("won"),
+            Some(false) => String::from("lost"),
+            None => String::from("-")
+        })
+    ]
+}
+
+pub fn format_action(action_type: &ActionType) -> String {
+    match action_type {
+        ActionType::Start => String::from("start"),
+        ActionType::NewBid(value) => value.to_string(),
+        ActionType::StopBidding => String::from("stop"),
+        ActionType::Pass(cards) => format!("pass {}", format_hand(cards)),
+        ActionType::CardPlayed(card) => format_card(card),
+        ActionType::Question(QuestionType::Yours) => String::from("?pair"),
+        ActionType::Question(QuestionType::YourHalf(suit)) => format!("?half-{}", suit_char(*suit)),
+        ActionType::Answer(AnswerType::YesPair(suit)) => format!("!pair-{}", suit_char(*suit)),
+        ActionType::Answer(AnswerType::NoPair) => String::from("!nopair"),
+        ActionType::Answer(AnswerType::YesHalf(suit)) => format!("!half-{}", suit_char(*suit)),
+        ActionType::Answer(AnswerType::NoHalf(suit)) => format!("!nohalf-{}", suit_char(*suit)),
+        ActionType::AnnounceTrump(suit) => format!("trump-{}", suit_char(*suit)),
+        ActionType::UndoRequest => String::from("undo"),
+        ActionType::UndoDecline => String::from("undo-decline"),
+        ActionType::UndoAccept => String::from("undo-accept")
+    }
+}
+
+pub fn parse_action(word: &str) -> Option<ActionType> {
+    /*
+        Parses every action except passing, which is followed by a hand.
+     */
+
+    // actions that refer to a suit
+    let suit = |prefix: &str| {
+        let mut chars = word.strip_prefix(prefix)?.chars();
+        let suit = suit_from_char(chars.next()?)?;
+        chars.next().is_none().then_some(suit)
+    };
+
+    let action_type = match word {
+        "start" => ActionType::Start,
+        "stop" => ActionType::StopBidding,
+        "?pair" => ActionType::Question(QuestionType::Yours),
+        "!nopair" => ActionType::Answer(AnswerType::NoPair),
+        "undo" => ActionType::UndoRequest,
+        "undo-decline" => ActionType::UndoDecline,
+        "undo-accept" => ActionType::UndoAccept,
+        _ if !word.is_empty() && word.chars().all(|char| char.is_ascii_digit()) => ActionType::NewBid(word.parse().ok()?),
+        _ => {
+            if let Some(suit) = suit("?half-") {
+                ActionType::Question(QuestionType::YourHalf(suit))
+            } else if let Some(suit) = suit("!pair-") {
+                ActionType::Answer(AnswerType::YesPair(suit))
+            } else if let Some(suit) = suit("!half-") {
+                ActionType::Answer(AnswerType::YesHalf(suit))
+            } else if let Some(suit) = suit("!nohalf-") {
+                ActionType::Answer(AnswerType::NoHalf(suit))
+            } else if let Some(suit) = suit("trump-") {
+                ActionType::AnnounceTrump(suit)
+            } else {
+                ActionType::CardPlayed(parse_card(word).ok()?)
+            }
+        }
+    };
+    Some(action_type)
+}
+
+
+impl Parser<'_> {
+    fn tag(&mut self) -> Result<(Position, String, String), NotationError> {
+        /*
+            Parses a tag like [Seed "99"]. Quotes and backslashes in the value are escaped with a backslash.
+         */
+
+        let position = self.position();
+        self.expect('[')?;
+        let tag = self.take_while(|char| char.is_ascii_alphanumeric());
+        self.skip_whitespace();
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.next()? {
+                '"' => break,
+                '\\' => value.push(self.next()?),
+                char => value.push(char)
+            }
+        }
+        self.skip_whitespace();
+        self.expect(']')?;
+        Ok((position, tag, value))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::MarjapussiCheater;
+    use crate::cheater::CheaterV1;
+    use crate::deal::deal_from_seed;
+    use crate::log::LogFilter;
+    use crate::simulate_game::play_game;
+
+    fn recorded_game(seed: u64) -> GameRecord {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player \"{}\"", place + 1));
+        let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4)
+            .map(|place| Box::new(CheaterV1::new(&player_names[place], place as u8, 2)) as Box<dyn MarjapussiCheater>)
+            .collect();
+        play_game(&mut players, player_names, Some(deal_from_seed(seed)), LogFilter::off(), &mut []).unwrap().record(Some(seed))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for seed in 0..5 {
+            let record = recorded_game(seed);
+            let text = format_game(&record).unwrap();
+            let parsed = parse_game(&text).unwrap();
+
+            assert_eq!(parsed.actions, record.actions);
+            assert_eq!(parsed.player_names, record.player_names);
+            assert_eq!(parsed.seed, Some(seed));
+            assert!(parsed.replay().is_ok());
+            assert_eq!(format_game(&parsed).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_action_notation() {
+        let actions = [
+            ActionType::NewBid(140),
+            ActionType::StopBidding,
+            ActionType::Question(QuestionType::YourHalf(marjapussi::game::cards::Suit::Red)),
+            ActionType::Answer(AnswerType::NoHalf(marjapussi::game::cards::Suit::Green)),
+            ActionType::Answer(AnswerType::NoPair),
+            ActionType::AnnounceTrump(marjapussi::game::cards::Suit::Bells),
+            ActionType::CardPlayed(parse_card("e-Z").unwrap()),
+            ActionType::UndoAccept
+        ];
+        for action in actions {
+            assert_eq!(parse_action(&format_action(&action)), Some(action));
+        }
+        assert_eq!(parse_action("trump-x"), None);
+        assert_eq!(parse_action("trump-gg"), None);
+        assert_eq!(parse_action("s-X"), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let text = format_game(&recorded_game(1)).unwrap();
+        let error_kind = |text: &str| parse_game(text).unwrap_err().kind;
+
+        // a wrong result tag
+        let wrong_result = text.replace("[Value \"", "[Value \"1");
+        assert!(matches!(error_kind(&wrong_result), NotationErrorKind::TagMismatch { .. }));
+
+        // an unfinished game
+        let last_line_start = text.trim_end().rfind('\n').unwrap();
+        assert_eq!(error_kind(&text[..last_line_start]), NotationErrorKind::UnfinishedGame);
+
+        // the first bid by the wrong player
+        let wrong_player = text.replacen("bidding: 0:", "bidding: 1:", 1);
+        assert!(matches!(error_kind(&wrong_player), NotationErrorKind::IllegalAction(_)));
+
+        // a bid in the wrong section, with the position of the error
+        let wrong_section = text.replacen("bidding:", "trick 1:", 1);
+        let error = parse_game(&wrong_section).unwrap_err();
+        assert_eq!(error.kind, NotationErrorKind::WrongSection { expected: String::from("bidding"), found: String::from("trick 1") });
+        let bidding_line = wrong_section.lines().position(|line| line.starts_with("trick 1:")).unwrap();
+        assert_eq!((error.line, error.column), (bidding_line + 1, 10));
+
+        assert_eq!(error_kind("[Round \"1\"]"), NotationErrorKind::UnknownTag(String::from("Round")));
+    }
+}
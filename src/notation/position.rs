@@ -0,0 +1,419 @@
+/*
+    A one-line notation for positions in the cardplay, similar to FEN in chess.
+
+    r-U,g-9/s-6,e-O,e-8/e-K,g-6,s-A/s-K,e-Z,r-6 g-A r gr 140 0 85:40 5:1 1
+
+    The fields are separated by spaces:
+        hands:           the remaining cards of the places 0 to 3, separated by '/' (an empty hand is written as '-')
+        current trick:   the cards on the table in the order they were played, or '-'
+                         ('?' if the trick is empty and the player at turn already asked a question, so only cards are left)
+        trump:           the suit of the trump, or '-'
+        announced pairs: the suits of all pairs that were announced so far, or '-'
+        contract value:  115 if nobody played
+        playing party:   0, 1 or '-' if nobody played
+        points:          the points of party 0 and party 1 so far (including announced pairs)
+        tricks:          the number of tricks party 0 and party 1 got so far
+        player at turn:  the place at the table
+    The player who started the current trick follows from the player at turn and the number of cards on the table.
+ */
+
+use marjapussi::game::cards::{Card, Suit};
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::player::PlaceAtTable;
+use marjapussi::game::points::Points;
+use marjapussi::game::Game;
+
+use super::{format_card, suit_char, suit_from_char};
+use super::{NotationError, NotationErrorKind, Parser};
+use crate::cheater::score_so_far;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub hands: [Vec<Card>; 4],
+    pub trick: Vec<Card>,
+    pub trump: Option<Suit>,
+    pub announced_pairs: Vec<Suit>,
+    pub value: i32,
+    pub playing_party: Option<u8>,
+    pub points: [i32; 2],
+    pub tricks: [u8; 2],
+    pub player_at_turn: u8,
+    pub question_answered: bool
+}
+
+impl Position {
+    pub fn from_game(game: &Game) -> Option<Self> {
+        /*
+            The position of a game in the cardplay (including raising).
+            None in all other phases, especially while a question is answered.
+         */
+
+        if !matches!(game.state.phase, GamePhase::Raising | GamePhase::StartTrick | GamePhase::Trick) {
+            return None;
+        }
+
+        // a trick with four cards is already finished and only kept until the next card is played
+        let trick = if game.state.current_trick.len() == 4 {
+            vec![]
+        } else {
+            game.state.current_trick.clone()
+        };
+        let (points, tricks, playing_party) = score_so_far(game);
+        // after an answer the phase is Trick, even though no card was played yet (in the first trick, the phase is always Trick)
+        let question_answered = game.state.phase == GamePhase::Trick && trick.is_empty() && game.state.player_at_turn().cards.len() < 9;
+        Some(Position {
+            hands: game.state.players.clone().map(|player| player.cards),
+            trick,
+            trump: game.state.trump,
+            announced_pairs: game.state.trump_called.clone(),
+            value: game.state.value.0,
+            playing_party,
+            points,
+            tricks: tricks.map(|tricks| tricks as u8),
+            player_at_turn: game.state.player_at_turn.0,
+            question_answered
+        })
+    }
+
+    pub fn to_game(&self) -> Game {
+        /*
+            Builds a game that is in this position. The game has no history,
+            so the points, tricks and the playing party of the position are not part of it.
+         */
+
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut game = Game::new(String::from("Position"), player_names, Some(self.hands.clone()));
+
+        let state = &mut game.state;
+        state.started = true;
+        state.players_started = (0..4).map(PlaceAtTable).collect();
+        state.bidding_players = if self.playing_party.is_some() { 1 } else { 0 };
+        for player in state.players.iter_mut() {
+            player.bidding = false;
+        }
+        state.trump = self.trump;
+        state.trump_called = self.announced_pairs.clone();
+        state.player_at_turn = PlaceAtTable(self.player_at_turn);
+        state.value = Points(self.value);
+        state.current_trick = self.trick.clone();
+
+        // before the first card, the player who plays the game may still raise
+        let first_card = self.trick.is_empty() && self.tricks == [0, 0];
+        state.phase = if !self.trick.is_empty() || self.question_answered {
+            GamePhase::Trick
+        } else if first_card && self.playing_party.is_some() {
+            GamePhase::Raising
+        } else if first_card {
+            GamePhase::Trick
+        } else {
+            GamePhase::StartTrick
+        };
+
+        game.legal_actions = game.legal_actions();
+        game
+    }
+}
+
+
+pub fn format_position(position: &Position) -> String {
+    let cards = |cards: &[Card]| {
+        if cards.is_empty() {
+            String::from("-")
+        } else {
+            cards.iter().map(format_card).collect::<Vec<String>>().join(",")
+        }
+    };
+    let suits = |suits: &[Suit]| {
+        if suits.is_empty() {
+            String::from("-")
+        } else {
+            suits.iter().map(|suit| suit_char(*suit)).collect()
+        }
+    };
+
+    format!(
+        "{} {} {} {} {} {} {}:{} {}:{} {}",
+        position.hands.iter().map(|hand| cards(hand)).collect::<Vec<String>>().join("/"),
+        if position.question_answered { String::from("?") } else { cards(&position.trick) },
+        position.trump.map_or('-', suit_char),
+        suits(&position.announced_pairs),
+        position.value,
+        position.playing_party.map_or(String::from("-"), |party| party.to_string()),
+        position.points[0],
+        position.points[1],
+        position.tricks[0],
+        position.tricks[1],
+        position.player_at_turn
+    )
+}
+
+pub fn parse_position(input: &str) -> Result<Position, NotationError> {
+    /*
+        Parses a position and checks that it can occur in a game.
+     */
+
+    let mut parser = Parser::new(input);
+    let mut dealt_cards: Vec<Card> = vec![];
+
+    // hands and current trick, no card may appear twice
+    parser.skip_whitespace();
+    let hands_position = parser.position();
+    let mut hands: [Vec<Card>; 4] = [vec![], vec![], vec![], vec![]];
+    for (place, hand) in hands.iter_mut().enumerate() {
+        if place > 0 {
+            parser.expect('/')?;
+        }
+        *hand = parser.card_list(&mut dealt_cards)?;
+    }
+    parser.field_separator()?;
+    let trick_position = parser.position();
+    let question_answered = parser.peek() == Some('?');
+    let trick = if question_answered {
+        parser.expect('?')?;
+        vec![]
+    } else {
+        parser.card_list(&mut dealt_cards)?
+    };
+    parser.field_separator()?;
+
+    // trump and announced pairs
+    let trump_position = parser.position();
+    let trump = parser.suits()?;
+    if trump.len() > 1 {
+        return Err(trump_position.error(NotationErrorKind::InvalidPosition(String::from("there can only be one trump"))));
+    }
+    let trump = trump.first().copied();
+    parser.field_separator()?;
+    let pairs_position = parser.position();
+    let announced_pairs = parser.suits()?;
+    let invalid_pairs = announced_pairs
+        .iter()
+        .enumerate()
+        .any(|(index, suit)| announced_pairs[..index].contains(suit));
+    if invalid_pairs || trump.is_some_and(|trump| !announced_pairs.contains(&trump)) {
+        return Err(pairs_position.error(NotationErrorKind::InvalidPosition(
+            String::from("every pair can only be announced once and the trump must be an announced pair")
+        )));
+    }
+    parser.field_separator()?;
+
+    // contract
+    let value_position = parser.position();
+    let value = parser.number()?;
+    parser.field_separator()?;
+    let party_position = parser.position();
+    let playing_party = if parser.peek() == Some('-') {
+        parser.expect('-')?;
+        None
+    } else {
+        Some(parser.number()?)
+    };
+    if playing_party.is_some_and(|party| party > 1) {
+        return Err(party_position.error(NotationErrorKind::InvalidPosition(String::from("the playing party must be 0 or 1"))));
+    }
+    let valid_value = (115..=420).contains(&value) && value % 5 == 0 && (value == 115) == playing_party.is_none();
+    if !valid_value {
+        return Err(value_position.error(NotationErrorKind::InvalidPosition(
+            String::from("the contract value must be a multiple of 5 up to 420, and 115 exactly if nobody played")
+        )));
+    }
+    parser.field_separator()?;
+
+    // points and tricks so far
+    let points = parser.pair()?;
+    parser.field_separator()?;
+    let tricks_position = parser.position();
+    let tricks = parser.pair()?;
+    parser.field_separator()?;
+    let turn_position = parser.position();
+    let player_at_turn = parser.number()?;
+    if player_at_turn > 3 {
+        return Err(turn_position.error(NotationErrorKind::InvalidPlace(player_at_turn.to_string())));
+    }
+    parser.skip_whitespace();
+    parser.end()?;
+
+    // the players who already played a card in this trick have one card less than the others
+    let cards_left = hands[player_at_turn as usize].len();
+    let trick_start = (player_at_turn as usize + 4 - trick.len() % 4) % 4;
+    for (place, hand) in hands.iter().enumerate() {
+        let played = (place + 4 - trick_start) % 4 < trick.len();
+        let expected = if played { cards_left.wrapping_sub(1) } else { cards_left };
+        if cards_left == 0 || trick.len() > 3 || hand.len() != expected {
+            return Err(hands_position.error(NotationErrorKind::InvalidPosition(
+                format!("place {} has {} cards, but the player at turn has {} and {} cards are on the table", place, hand.len(), cards_left, trick.len())
+            )));
+        }
+    }
+    if tricks[0] + tricks[1] != 9 - cards_left as i32 {
+        return Err(tricks_position.error(NotationErrorKind::InvalidPosition(
+            format!("{} tricks were played, but the players have {} cards left", tricks[0] + tricks[1], cards_left)
+        )));
+    }
+
+    // questions can only be asked before the first card of a trick, but not in the first trick
+    if question_answered && tricks[0] + tricks[1] == 0 {
+        return Err(trick_position.error(NotationErrorKind::InvalidPosition(String::from("there are no questions in the first trick"))));
+    }
+
+    Ok(Position {
+        hands,
+        trick,
+        trump,
+        announced_pairs,
+        value,
+        playing_party: playing_party.map(|party| party as u8),
+        points,
+        tricks: tricks.map(|tricks| tricks as u8),
+        player_at_turn: player_at_turn as u8,
+        question_answered
+    })
+}
+
+
+impl Parser<'_> {
+    fn field_separator(&mut self) -> Result<(), NotationError> {
+        match self.peek() {
+            Some(' ' | '\t') => {
+                self.skip_whitespace();
+                Ok(())
+            },
+            Some(char) => Err(self.position().error(NotationErrorKind::UnexpectedChar(char))),
+            None => Err(self.position().error(NotationErrorKind::UnexpectedEnd))
+        }
+    }
+
+    fn card_list(&mut self, dealt_cards: &mut Vec<Card>) -> Result<Vec<Card>, NotationError> {
+        /*
+            Parses cards separated by ',' or '-' for no cards. Fails if a card is in dealt_cards, then adds the cards to it.
+         */
+
+        let mut cards = vec![];
+        if self.peek() == Some('-') {
+            self.expect('-')?;
+            return Ok(cards);
+        }
+        loop {
+            let card_position = self.position();
+            let card = self.card()?;
+            if dealt_cards.contains(&card) {
+                return Err(card_position.error(NotationErrorKind::DuplicateCard(card)));
+            }
+            dealt_cards.push(card.clone());
+            cards.push(card);
+            if self.peek() != Some(',') {
+                return Ok(cards);
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn suits(&mut self) -> Result<Vec<Suit>, NotationError> {
+        // suit letters or '-' for no suit
+        if self.peek() == Some('-') {
+            self.expect('-')?;
+            return Ok(vec![]);
+        }
+        let mut suits = vec![];
+        loop {
+            let position = self.position();
+            let char = self.next()?;
+            suits.push(suit_from_char(char).ok_or(position.error(NotationErrorKind::UnknownSuit(char)))?);
+            if !self.peek().is_some_and(|char| char.is_ascii_alphabetic()) {
+                return Ok(suits);
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<i32, NotationError> {
+        let position = self.position();
+        let digits = self.take_while(|char| char.is_ascii_digit());
+        digits.parse().map_err(|_| match self.peek() {
+            Some(char) => position.error(NotationErrorKind::UnexpectedChar(char)),
+            None => position.error(NotationErrorKind::UnexpectedEnd)
+        })
+    }
+
+    fn pair(&mut self) -> Result<[i32; 2], NotationError> {
+        // two numbers separated by ':'
+        let first = self.number()?;
+        self.expect(':')?;
+        Ok([first, self.number()?])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_hand;
+
+    // party 0 plays 140 with red as trump, player 0 started the trick with g-A
+    const POSITION: &str = "r-U,g-9/s-6,e-O,e-8/e-K,g-6,s-A/s-K,e-Z,r-6 g-A r gr 140 0 85:40 5:1 1";
+
+    #[test]
+    fn test_round_trip() {
+        let position = parse_position(POSITION).unwrap();
+        assert_eq!(position.hands[2], parse_hand("[e-K, g-6, s-A]").unwrap());
+        assert_eq!(position.trick, parse_hand("[g-A]").unwrap());
+        assert_eq!(position.trump, Some(Suit::Red));
+        assert_eq!(position.announced_pairs, vec![Suit::Green, Suit::Red]);
+        assert_eq!(position.playing_party, Some(0));
+        assert_eq!((position.points, position.tricks, position.player_at_turn), ([85, 40], [5, 1], 1));
+        assert_eq!(format_position(&position), POSITION);
+        assert!(!position.question_answered);
+
+        // the game built from the position is in the same position
+        let game = position.to_game();
+        assert_eq!(game.state.phase, GamePhase::Trick);
+        let position_of_game = Position::from_game(&game).unwrap();
+        assert_eq!(position_of_game.hands, position.hands);
+        assert_eq!(position_of_game.trick, position.trick);
+        assert_eq!(position_of_game.trump, position.trump);
+        assert_eq!(position_of_game.player_at_turn, position.player_at_turn);
+        assert!(game.legal_actions.iter().all(|action| action.player == PlaceAtTable(1)));
+
+        // after a question, the player at turn can only play a card
+        let answered = "r-U,g-9/s-6,e-8/e-K,g-6/s-K,r-6 ? r gr 140 0 85:65 6:1 0";
+        let position = parse_position(answered).unwrap();
+        assert!(position.question_answered);
+        assert_eq!(format_position(&position), answered);
+        let game = position.to_game();
+        assert_eq!(game.state.phase, GamePhase::Trick);
+        assert!(Position::from_game(&game).unwrap().question_answered);
+    }
+
+    #[test]
+    fn test_errors() {
+        let kind = |input: &str| parse_position(input).unwrap_err().kind;
+        let invalid = |input: &str| matches!(kind(input), NotationErrorKind::InvalidPosition(_));
+
+        // the place that played the first card of the trick has one card less
+        assert!(invalid(&POSITION.replace("s-K,e-Z,r-6 ", "s-K,e-Z,r-6,r-7 ")));
+        // the tricks don't match the number of cards
+        assert!(invalid(&POSITION.replace("5:1", "5:2")));
+        // the trump was never announced
+        assert!(invalid(&POSITION.replace(" r gr ", " e gr ")));
+        // a contract without a playing party
+        assert!(invalid(&POSITION.replace(" 140 0 ", " 140 - ")));
+        // a question in the first trick
+        let first_trick = Position {
+            hands: crate::deal::deal_from_seed(1),
+            trick: vec![],
+            trump: None,
+            announced_pairs: vec![],
+            value: 115,
+            playing_party: None,
+            points: [0, 0],
+            tricks: [0, 0],
+            player_at_turn: 0,
+            question_answered: true
+        };
+        assert!(invalid(&format_position(&first_trick)));
+        assert_eq!(kind(&POSITION.replace("g-9", "g-A")), NotationErrorKind::DuplicateCard(parse_hand("[g-A]").unwrap()[0].clone()));
+        assert_eq!(kind(&POSITION[..POSITION.len() - 2]), NotationErrorKind::UnexpectedEnd);
+        let error = parse_position(&POSITION.replace("gr", "gx")).unwrap_err();
+        assert_eq!((error.kind, error.column), (NotationErrorKind::UnknownSuit('x'), 52));
+    }
+}
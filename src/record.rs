@@ -0,0 +1,362 @@
+/*
+    Game records: everything needed to reproduce a finished game, stored as JSON.
+
+    A record contains the deal, the names and configurations of the players, every action in the order it was applied,
+    the seed of the deal (if it was dealt from one) and the GameFinishedInfo of the finished game.
+    Games played by agents that explain their bids also contain the reasoning behind every bid.
+    Replaying a record applies all actions to a new game with the same deal and checks that the result matches the record.
+ */
+
+use marjapussi::game::cards::{Card, Suit};
+use marjapussi::game::gameevent::{ActionType, AnswerType, GameAction, QuestionType};
+use marjapussi::game::gameinfo::GameFinishedInfo;
+use marjapussi::game::player::PlaceAtTable;
+use marjapussi::game::Game;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::ai::{AgentConfig, AgentError};
+use crate::cheater::BiddingReasoning;
+use crate::notation::NotationError;
+
+
+// increase this whenever the format changes in an incompatible way
+const FORMAT_VERSION: u32 = 1;
+
+// these fields of GameFinishedInfo depend on the time the game was played, so they are ignored when comparing
+const TIME_FIELDS: [&str; 4] = ["create_time", "start_time", "end_time", "time"];
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub format_version: u32,
+    pub game_name: String,
+    pub player_names: [String; 4],
+    pub seed: Option<u64>,
+    pub deal: [Vec<Card>; 4],
+    pub agents: Vec<AgentConfig>,
+    #[serde(with = "actions")]
+    pub actions: Vec<GameAction>,
+    // the serialized GameFinishedInfo (which cannot be deserialized)
+    pub final_info: Value,
+    // the reasoning of the agents behind their bids, if they reported it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bidding_reasoning: Vec<BiddingReasoning>
+}
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Notation(NotationError),
+    UnsupportedVersion(u32),
+    IllegalAction { index: usize, action: GameAction },
+    Unfinished,
+    Mismatch(String),
+    // an agent failed while the record was analyzed
+    Agent(AgentError),
+    // an agent of the record can not be created again
+    UnknownAgent(String)
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::Io(error) => write!(f, "could not access the record: {}", error),
+            RecordError::Json(error) => write!(f, "invalid record: {}", error),
+            RecordError::Notation(error) => write!(f, "invalid game notation: {}", error),
+            RecordError::UnsupportedVersion(version) => write!(f, "unsupported record version {} (expected {})", version, FORMAT_VERSION),
+            RecordError::IllegalAction { index, action } => write!(f, "action {} is illegal: {:?}", index, action),
+            RecordError::Unfinished => write!(f, "the game is not finished after all actions"),
+            RecordError::Mismatch(path) => write!(f, "the replayed game differs from the record at {}", path),
+            RecordError::Agent(error) => write!(f, "the agent failed: {}", error),
+            RecordError::UnknownAgent(agent) => write!(f, "the agent {} can not be created from the record", agent)
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<std::io::Error> for RecordError {
+    fn from(error: std::io::Error) -> Self {
+        RecordError::Io(error)
+    }
+}
+
+impl From<NotationError> for RecordError {
+    fn from(error: NotationError) -> Self {
+        RecordError::Notation(error)
+    }
+}
+
+impl From<AgentError> for RecordError {
+    fn from(error: AgentError) -> Self {
+        RecordError::Agent(error)
+    }
+}
+
+impl From<serde_json::Error> for RecordError {
+    fn from(error: serde_json::Error) -> Self {
+        RecordError::Json(error)
+    }
+}
+
+
+impl GameRecord {
+    pub fn new(game: &Game, seed: Option<u64>, agents: Vec<AgentConfig>) -> Self {
+        /*
+            Records a finished game. The actions are taken from the events of the game.
+         */
+
+        let final_info = GameFinishedInfo::from(game.clone());
+        GameRecord {
+            format_version: FORMAT_VERSION,
+            game_name: game.info.name.clone(),
+            player_names: game.info.player_names.clone(),
+            seed,
+            deal: game.info.player_start_cards.clone(),
+            agents,
+            actions: game.all_events.iter().map(|event| event.last_action.clone()).collect(),
+            final_info: serde_json::to_value(final_info).expect("GameFinishedInfo can always be serialized"),
+            bidding_reasoning: vec![]
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RecordError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RecordError> {
+        let record: GameRecord = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if record.format_version != FORMAT_VERSION {
+            return Err(RecordError::UnsupportedVersion(record.format_version));
+        }
+        Ok(record)
+    }
+
+    pub fn replay(&self) -> Result<Game, RecordError> {
+        /*
+            Rebuilds the game by applying all recorded actions to the recorded deal.
+            Fails if an action is illegal or if the final state differs from the record.
+         */
+
+        let mut game = Game::new(self.game_name.clone(), self.player_names.clone(), Some(self.deal.clone()));
+        for (index, action) in self.actions.iter().enumerate() {
+            game = game
+                .apply_action(action.clone())
+                .map_err(|_| RecordError::IllegalAction { index, action: action.clone() })?;
+        }
+        if !game.ended() {
+            return Err(RecordError::Unfinished);
+        }
+
+        let final_info = serde_json::to_value(GameFinishedInfo::from(game.clone()))?;
+        if let Some(path) = first_difference(&final_info, &self.final_info, String::from("final_info")) {
+            return Err(RecordError::Mismatch(path));
+        }
+        Ok(game)
+    }
+}
+
+
+fn first_difference(replayed: &Value, recorded: &Value, path: String) -> Option<String> {
+    /*
+        Returns the path of the first difference between the two values, ignoring the time fields.
+     */
+
+    match (replayed, recorded) {
+        (Value::Object(replayed), Value::Object(recorded)) => {
+            let keys = replayed.keys().chain(recorded.keys().filter(|key| !replayed.contains_key(*key)));
+            for key in keys {
+                if TIME_FIELDS.contains(&key.as_str()) {
+                    continue;
+                }
+                let child_path = format!("{}.{}", path, key);
+                match (replayed.get(key), recorded.get(key)) {
+                    (Some(replayed), Some(recorded)) => {
+                        if let Some(difference) = first_difference(replayed, recorded, child_path) {
+                            return Some(difference);
+                        }
+                    },
+                    _ => return Some(child_path)
+                }
+            }
+            None
+        },
+        (Value::Array(replayed), Value::Array(recorded)) => {
+            if replayed.len() != recorded.len() {
+                return Some(format!("{} (length {} instead of {})", path, replayed.len(), recorded.len()));
+            }
+            replayed
+                .iter()
+                .zip(recorded)
+                .enumerate()
+                .find_map(|(index, (replayed, recorded))| first_difference(replayed, recorded, format!("{}[{}]", path, index)))
+        },
+        _ if replayed == recorded => None,
+        _ => Some(format!("{} ({} instead of {})", path, replayed, recorded))
+    }
+}
+
+
+/*
+    The framework types can only be serialized, so these remote definitions add deserialization.
+    They produce the same JSON as the Serialize implementations of the framework.
+ */
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Suit")]
+enum SuitDef {
+    Green,
+    Acorns,
+    Bells,
+    Red
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "PlaceAtTable")]
+struct PlaceAtTableDef(u8);
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "QuestionType")]
+enum QuestionTypeDef {
+    Yours,
+    YourHalf(#[serde(with = "SuitDef")] Suit)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "AnswerType")]
+enum AnswerTypeDef {
+    YesPair(#[serde(with = "SuitDef")] Suit),
+    NoPair,
+    YesHalf(#[serde(with = "SuitDef")] Suit),
+    NoHalf(#[serde(with = "SuitDef")] Suit)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "ActionType")]
+enum ActionTypeDef {
+    Start,
+    NewBid(i32),
+    StopBidding,
+    Pass(Vec<Card>),
+    CardPlayed(Card),
+    Question(#[serde(with = "QuestionTypeDef")] QuestionType),
+    Answer(#[serde(with = "AnswerTypeDef")] AnswerType),
+    AnnounceTrump(#[serde(with = "SuitDef")] Suit),
+    UndoRequest,
+    UndoDecline,
+    UndoAccept
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "GameAction")]
+struct GameActionDef {
+    #[serde(with = "ActionTypeDef")]
+    action_type: ActionType,
+    #[serde(with = "PlaceAtTableDef")]
+    player: PlaceAtTable
+}
+
+// for lists of actions in other serializable types, e.g. #[serde(with = "crate::record::actions")]
+pub mod actions {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Action(#[serde(with = "GameActionDef")] GameAction);
+
+    pub fn serialize<S: Serializer>(actions: &[GameAction], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(actions.iter().map(|action| Action(action.clone())))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<GameAction>, D::Error> {
+        let actions: Vec<Action> = Vec::deserialize(deserializer)?;
+        Ok(actions.into_iter().map(|Action(action)| action).collect())
+    }
+}
+
+// for lists of suits in other serializable types, e.g. #[serde(with = "crate::record::suits")]
+pub mod suits {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct SuitValue(#[serde(with = "SuitDef")] Suit);
+
+    pub fn serialize<S: Serializer>(suits: &[Suit], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(suits.iter().map(|suit| SuitValue(*suit)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Suit>, D::Error> {
+        let suits: Vec<SuitValue> = Vec::deserialize(deserializer)?;
+        Ok(suits.into_iter().map(|SuitValue(suit)| suit).collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::MarjapussiCheater;
+    use crate::cheater::CheaterV1;
+    use crate::deal::deal_from_seed;
+    use crate::log::LogFilter;
+    use crate::simulate_game::play_game;
+
+    fn recorded_game(seed: u64) -> GameRecord {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4)
+            .map(|place| Box::new(CheaterV1::new(&player_names[place], place as u8, 2)) as Box<dyn MarjapussiCheater>)
+            .collect();
+        play_game(&mut players, player_names, Some(deal_from_seed(seed)), LogFilter::off(), &mut []).unwrap().record(Some(seed))
+    }
+
+    #[test]
+    fn test_save_load_replay() {
+        let record = recorded_game(3);
+        assert_eq!(record.agents[0], AgentConfig { agent: String::from("CheaterV1"), search_depth: Some(2) });
+
+        // the framework serializes actions in the same way as the record
+        let framework_json = serde_json::to_value(&record.actions).unwrap();
+        assert_eq!(serde_json::to_value(&record).unwrap()["actions"], framework_json);
+
+        let path = std::env::temp_dir().join(format!("marjapussi_record_test_{}.json", std::process::id()));
+        record.save(&path).unwrap();
+        let loaded = GameRecord::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.actions, record.actions);
+        assert_eq!(loaded.deal, deal_from_seed(3));
+        // every bid of the cheaters is explained
+        let bids = record.actions.iter().filter(|action| matches!(action.action_type, ActionType::NewBid(_) | ActionType::StopBidding)).count();
+        assert_eq!(loaded.bidding_reasoning.len(), bids);
+        assert_eq!(loaded.bidding_reasoning, record.bidding_reasoning);
+        let game = loaded.replay().unwrap();
+        assert_eq!(game.info.player_start_cards, record.deal);
+    }
+
+    #[test]
+    fn test_replay_errors() {
+        let record = recorded_game(4);
+
+        let mut unfinished = record.clone();
+        unfinished.actions.pop();
+        assert!(matches!(unfinished.replay(), Err(RecordError::Unfinished)));
+
+        let mut illegal = record.clone();
+        illegal.actions.swap(10, 11);
+        assert!(matches!(illegal.replay(), Err(RecordError::IllegalAction { .. })));
+
+        let mut tampered = record.clone();
+        tampered.final_info["game_value"] = Value::from(1000);
+        match tampered.replay() {
+            Err(RecordError::Mismatch(path)) => assert!(path.starts_with("final_info.game_value")),
+            other => panic!("expected a mismatch, got {:?}", other.map(|_| ()))
+        }
+    }
+}
@@ -0,0 +1,243 @@
+/*
+    Repro files: everything needed to reproduce a failed decision of an agent, stored as JSON.
+
+    When a player fails in a simulated game (with an error, a panic or an illegal action), the simulator saves the seed
+    and the deal, the names and configurations of the players, all actions before the failing decision, the place of the
+    failing player and its error. Reproducing the file applies the actions to a new game with the same deal.
+    A new agent with the configuration of the failing player takes all of its earlier decisions on the way,
+    so it knows the same as in the simulation, and then chooses at the failing decision again.
+
+    A minimized case (see minimize) may start from a position in the cardplay instead of the deal.
+    The actions are applied to this position then, and the agent's first decision in it knows the score of the position.
+ */
+
+pub mod minimize;
+
+use marjapussi::game::cards::Card;
+use marjapussi::game::gameevent::GameAction;
+use marjapussi::game::Game;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+use crate::ai::{AgentConfig, AgentError, MarjapussiCheater};
+use crate::log::LogFilter;
+use crate::notation::position::{parse_position, Position};
+use crate::record::RecordError;
+use crate::simulate_game::{choose_action, choose_action_in_position, GameFailure};
+use crate::tournament::AgentFactory;
+
+
+// increase this whenever the format changes in an incompatible way
+const FORMAT_VERSION: u32 = 1;
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReproCase {
+    pub format_version: u32,
+    pub game_name: String,
+    pub player_names: [String; 4],
+    pub seed: Option<u64>,
+    pub deal: [Vec<Card>; 4],
+    pub agents: Vec<AgentConfig>,
+    // the position in the position notation that the game starts from instead of the deal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    // all actions before the failing decision
+    #[serde(with = "crate::record::actions")]
+    pub actions: Vec<GameAction>,
+    pub place: u8,
+    pub error: String
+}
+
+// what happened when the failing decision was taken again
+#[derive(Debug)]
+pub enum Reproduction {
+    // the agent failed after the given number of actions, at the failing decision or at one of its earlier decisions
+    Failed { decision: usize, error: AgentError },
+    // the agent chose this action at the failing decision without failing
+    Chose(GameAction)
+}
+
+impl ReproCase {
+    pub fn new(failure: &GameFailure, seed: Option<u64>) -> Self {
+        ReproCase {
+            format_version: FORMAT_VERSION,
+            game_name: failure.game.info.name.clone(),
+            player_names: failure.game.info.player_names.clone(),
+            seed,
+            deal: failure.game.info.player_start_cards.clone(),
+            agents: failure.agents.clone(),
+            start: None,
+            actions: failure.game.all_events.iter().map(|event| event.last_action.clone()).collect(),
+            place: failure.place,
+            error: failure.error.to_string()
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RecordError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RecordError> {
+        let case: ReproCase = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if case.format_version != FORMAT_VERSION {
+            return Err(RecordError::UnsupportedVersion(case.format_version));
+        }
+        Ok(case)
+    }
+
+    pub fn start_position(&self) -> Result<Option<Position>, RecordError> {
+        Ok(self.start.as_deref().map(parse_position).transpose()?)
+    }
+
+    fn start_game(&self, start: Option<&Position>) -> Game {
+        match start {
+            Some(position) => position.to_game(),
+            None => Game::new(self.game_name.clone(), self.player_names.clone(), Some(self.deal.clone()))
+        }
+    }
+
+    pub fn game(&self) -> Result<Game, RecordError> {
+        /*
+            The game up to the failing decision.
+         */
+
+        let mut game = self.start_game(self.start_position()?.as_ref());
+        for (index, action) in self.actions.iter().enumerate() {
+            game = game
+                .apply_action(action.clone())
+                .map_err(|_| RecordError::IllegalAction { index, action: action.clone() })?;
+        }
+        Ok(game)
+    }
+
+    pub fn reproduce(&self, log_filter: LogFilter) -> Result<Reproduction, RecordError> {
+        /*
+            Replays the actions up to the failing decision and lets a new agent of the failing player choose again.
+            Only the failing decision is logged with the filter, the earlier decisions of the agent are silent.
+         */
+
+        let config = self.agents
+            .get(self.place as usize)
+            .ok_or_else(|| RecordError::UnknownAgent(format!("at place {}", self.place)))?;
+        let factory = AgentFactory::from_config(config).ok_or_else(|| RecordError::UnknownAgent(config.agent.clone()))?;
+        let mut agent = factory.build(&self.player_names[self.place as usize], self.place);
+        agent.set_log_filter(LogFilter::off());
+
+        // only the first decision is taken in the start position, the game built from it does not know the score
+        // (the minimizer only starts cases after the last earlier decision of the agent, so its first decision is the failing one)
+        let start = self.start_position()?;
+        let choose = |agent: &mut dyn MarjapussiCheater, index: usize, game: &Game| match (&start, index) {
+            (Some(position), 0) => choose_action_in_position(agent, position),
+            _ => choose_action(agent, game)
+        };
+
+        let mut game = self.start_game(start.as_ref());
+        for (index, action) in self.actions.iter().enumerate() {
+            // the choice is not applied, but it builds up the knowledge of the agent
+            if game.state.player_at_turn.0 == self.place {
+                if let Err(error) = choose(agent.as_mut(), index, &game) {
+                    return Ok(Reproduction::Failed { decision: index, error });
+                }
+            }
+            game = game
+                .apply_action(action.clone())
+                .map_err(|_| RecordError::IllegalAction { index, action: action.clone() })?;
+        }
+
+        agent.set_log_filter(log_filter);
+        Ok(match choose(agent.as_mut(), self.actions.len(), &game) {
+            Ok(action) => Reproduction::Chose(action),
+            Err(error) => Reproduction::Failed { decision: self.actions.len(), error }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheater::CheaterV1;
+    use crate::deal::deal_from_seed;
+    use crate::simulate_game::play_game;
+    use marjapussi::game::gamestate::GamePhase;
+
+    // a cheater that panics at its first decision in the cardplay
+    struct PanickingCheater(CheaterV1);
+
+    impl MarjapussiCheater for PanickingCheater {
+        fn select_action(&mut self, game: Game) -> Result<GameAction, AgentError> {
+            if game.state.phase == GamePhase::Trick {
+                panic!("no idea what to play");
+            }
+            self.0.select_action(game)
+        }
+    }
+
+    fn players(panicking: Option<u8>) -> Vec<Box<dyn MarjapussiCheater>> {
+        (0..4)
+            .map(|place| {
+                let cheater = CheaterV1::new(&format!("Player {}", place + 1), place, 1);
+                if panicking == Some(place) {
+                    Box::new(PanickingCheater(cheater)) as Box<dyn MarjapussiCheater>
+                } else {
+                    Box::new(cheater)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_panic_is_caught() {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let failure = play_game(&mut players(Some(1)), player_names, Some(deal_from_seed(3)), LogFilter::off(), &mut [])
+            .err()
+            .unwrap();
+        assert_eq!(failure.place, 1);
+        assert!(matches!(&failure.error, AgentError::Panic(message) if message == "no idea what to play"));
+        assert_eq!(failure.decision, failure.game.all_events.len());
+
+        // the panicking agent has no configuration it could be created from
+        let case = ReproCase::new(&failure, Some(3));
+        assert_eq!(case.game().unwrap().state.player_at_turn.0, 1);
+        assert!(matches!(case.reproduce(LogFilter::off()), Err(RecordError::UnknownAgent(_))));
+    }
+
+    #[test]
+    fn test_reproduce() {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let played_game = play_game(&mut players(None), player_names, Some(deal_from_seed(5)), LogFilter::off(), &mut []).unwrap();
+        let record = played_game.record(Some(5));
+
+        // pretend that the decision in the middle of the cardplay failed
+        let decision = record.actions.len() - 14;
+        let mut game = Game::new(record.game_name.clone(), record.player_names.clone(), Some(record.deal.clone()));
+        for action in &record.actions[..decision] {
+            game.apply_action_mut(action.clone());
+        }
+        let failure = GameFailure {
+            place: game.state.player_at_turn.0,
+            decision,
+            error: AgentError::NoLegalActions,
+            game: Box::new(game),
+            agents: record.agents.clone()
+        };
+
+        let path = std::env::temp_dir().join(format!("marjapussi_repro_test_{}.json", std::process::id()));
+        ReproCase::new(&failure, Some(5)).save(&path).unwrap();
+        let case = ReproCase::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(case.actions, record.actions[..decision]);
+        assert_eq!(case.error, "there are no legal actions");
+
+        // the cheaters are deterministic, so the new agent chooses the recorded action
+        match case.reproduce(LogFilter::off()).unwrap() {
+            Reproduction::Chose(action) => assert_eq!(action, record.actions[decision]),
+            Reproduction::Failed { error, .. } => panic!("the decision failed: {}", error)
+        }
+    }
+}
@@ -0,0 +1,308 @@
+/*
+    Delta debugging of repro files: shrinks a failing case as long as it still fails.
+
+    Three kinds of reductions are tried, and each one is only kept if the case still fails:
+        later start:   the case starts from a position in the cardplay closer to the failing decision, so fewer actions are replayed
+                       (but only after the last earlier decision of the failing player)
+        search depth:  the failing agent searches less deep
+        card swaps:    two cards of different players are exchanged in the whole case, which moves the hands towards the sorted deal
+                       (the lowest cards for place 0, the next ones for place 1, ...)
+    The reductions are repeated until none of them works any more. Every kept reduction replays fewer actions,
+    lowers the search depth or puts more cards where the sorted deal has them, so the minimizer always ends.
+
+    Whether a case fails is decided by a predicate. same_failure builds the usual one, which reproduces the case
+    and expects the same kind of error at the failing decision. Other checks, e.g. for a scoring mismatch, can be plugged in.
+ */
+
+use marjapussi::game::cards::Card;
+use marjapussi::game::gameevent::{ActionType, GameAction};
+use marjapussi::game::Game;
+
+use std::mem::{self, Discriminant};
+
+use super::{ReproCase, Reproduction};
+use crate::ai::AgentError;
+use crate::log::LogFilter;
+use crate::notation::position::{format_position, Position};
+use crate::record::RecordError;
+
+
+pub struct Minimized {
+    pub case: ReproCase,
+    // the descriptions of the kept reductions, in the order they were found
+    pub reductions: Vec<String>,
+    // the number of candidates that were checked
+    pub attempts: u32
+}
+
+pub fn same_failure(case: &ReproCase) -> Result<Option<impl FnMut(&ReproCase) -> bool>, RecordError> {
+    /*
+        The predicate for cases that fail with the same kind of error as this case, at the failing decision.
+        None if this case does not fail (any more).
+     */
+
+    let kind = |case: &ReproCase| -> Result<Option<Discriminant<AgentError>>, RecordError> {
+        Ok(match case.reproduce(LogFilter::off())? {
+            Reproduction::Failed { decision, error } if decision == case.actions.len() => Some(mem::discriminant(&error)),
+            _ => None
+        })
+    };
+    let Some(original) = kind(case)? else {
+        return Ok(None);
+    };
+    Ok(Some(move |candidate: &ReproCase| matches!(kind(candidate), Ok(Some(other)) if other == original)))
+}
+
+pub fn minimize(case: &ReproCase, fails: &mut dyn FnMut(&ReproCase) -> bool) -> Minimized {
+    /*
+        Shrinks the case with the reductions above. The case itself has to fail.
+     */
+
+    let mut attempts = 0;
+    let mut check = |candidate: &ReproCase| {
+        attempts += 1;
+        // candidates with illegal actions are no valid cases
+        candidate.game().is_ok() && fails(candidate)
+    };
+
+    let mut current = case.clone();
+    let mut reductions = vec![];
+    loop {
+        let reduced = later_start(&current, &mut check)
+            .or_else(|| lower_depth(&current, &mut check))
+            .or_else(|| swap_cards(&current, &mut check));
+        match reduced {
+            Some((next, description)) => {
+                current = next;
+                reductions.push(description);
+            },
+            None => break
+        }
+    }
+
+    Minimized { case: current, reductions, attempts }
+}
+
+
+fn later_start(case: &ReproCase, check: &mut dyn FnMut(&ReproCase) -> bool) -> Option<(ReproCase, String)> {
+    /*
+        Starts the case from the latest position that still fails, trying the positions closest to the failing decision first.
+        Only the positions after the last earlier decision of the failing player are tried: the agent knows the score of the
+        start position at its first decision only, so it must not take any decision before the failing one.
+     */
+
+    let start = case.start_position().ok()?;
+    let mut game = case.start_game(start.as_ref());
+    let mut positions = vec![];
+    for (index, action) in case.actions.iter().enumerate() {
+        if game.state.player_at_turn.0 == case.place {
+            positions.clear();
+        }
+        game = game.apply_action(action.clone()).ok()?;
+        if let Some(position) = position_after(start.as_ref(), &game) {
+            positions.push((index + 1, position));
+        }
+    }
+
+    positions.into_iter().rev().find_map(|(applied, position)| {
+        let candidate = ReproCase {
+            start: Some(format_position(&position)),
+            actions: case.actions[applied..].to_vec(),
+            ..case.clone()
+        };
+        check(&candidate).then(|| (candidate, format!("start {} actions later", applied)))
+    })
+}
+
+fn position_after(start: Option<&Position>, game: &Game) -> Option<Position> {
+    /*
+        The position of a game that was built from the start position (if there is one).
+        Such a game has no history, so the score of the start position has to be added.
+     */
+
+    let mut position = Position::from_game(game)?;
+    if let Some(start) = start {
+        for party in 0..2 {
+            position.points[party] += start.points[party];
+            position.tricks[party] += start.tricks[party];
+        }
+        position.playing_party = start.playing_party;
+    }
+    Some(position)
+}
+
+fn lower_depth(case: &ReproCase, check: &mut dyn FnMut(&ReproCase) -> bool) -> Option<(ReproCase, String)> {
+    /*
+        Lowers the search depth of the failing agent to the lowest depth that still fails.
+     */
+
+    let depth = case.agents.get(case.place as usize)?.search_depth?;
+    (1..depth).find_map(|lower| {
+        let mut candidate = case.clone();
+        candidate.agents[case.place as usize].search_depth = Some(lower);
+        check(&candidate).then(|| (candidate, format!("search depth {} instead of {}", lower, depth)))
+    })
+}
+
+fn swap_cards(case: &ReproCase, check: &mut dyn FnMut(&ReproCase) -> bool) -> Option<(ReproCase, String)> {
+    /*
+        Exchanges a card that belongs to another player in the sorted deal with a card of this player that is misplaced as well.
+     */
+
+    let hands = match case.start_position().ok()? {
+        Some(position) => position.hands,
+        None => case.deal.clone()
+    };
+    let owners = sorted_owners(&hands);
+    let owner = |card: &Card| owners.iter().find(|(owned, _)| owned == card).map(|(_, place)| *place);
+
+    for (place, hand) in hands.iter().enumerate() {
+        for card in hand.iter().filter(|card| owner(card) != Some(place)) {
+            let Some(other) = owner(card) else {
+                continue;
+            };
+            for exchanged in hands[other].iter().filter(|exchanged| owner(exchanged) != Some(other)) {
+                let candidate = with_swapped_cards(case, card, exchanged)?;
+                if check(&candidate) {
+                    return Some((candidate, format!("swap {} of place {} with {} of place {}", card, place, exchanged, other)));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn sorted_owners(hands: &[Vec<Card>; 4]) -> Vec<(Card, usize)> {
+    /*
+        The place of every card in the sorted deal: all cards sorted and dealt in order, with the same hand sizes.
+     */
+
+    let mut cards: Vec<Card> = hands.iter().flatten().cloned().collect();
+    cards.sort();
+    let mut cards = cards.into_iter();
+    hands
+        .iter()
+        .enumerate()
+        .flat_map(|(place, hand)| cards.by_ref().take(hand.len()).map(move |card| (card, place)).collect::<Vec<_>>())
+        .collect()
+}
+
+fn with_swapped_cards(case: &ReproCase, first: &Card, second: &Card) -> Option<ReproCase> {
+    /*
+        The case with the two cards exchanged in the deal, the start position and all actions.
+     */
+
+    let swap = |card: &Card| {
+        if card == first {
+            second.clone()
+        } else if card == second {
+            first.clone()
+        } else {
+            card.clone()
+        }
+    };
+    let swap_all = |cards: &[Card]| cards.iter().map(swap).collect::<Vec<Card>>();
+
+    let start = match case.start_position().ok()? {
+        Some(mut position) => {
+            position.hands = position.hands.map(|hand| swap_all(&hand));
+            Some(format_position(&position))
+        },
+        None => None
+    };
+    let actions = case.actions
+        .iter()
+        .map(|action| GameAction {
+            action_type: match &action.action_type {
+                ActionType::CardPlayed(card) => ActionType::CardPlayed(swap(card)),
+                ActionType::Pass(cards) => ActionType::Pass(swap_all(cards)),
+                other => other.clone()
+            },
+            player: action.player.clone()
+        })
+        .collect();
+    Some(ReproCase {
+        deal: case.deal.clone().map(|hand| swap_all(&hand)),
+        start,
+        actions,
+        ..case.clone()
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheater::CheaterV1;
+    use crate::ai::MarjapussiCheater;
+    use crate::deal::deal_from_seed;
+    use crate::simulate_game::{play_game, GameFailure};
+
+    fn case(seed: u64, decision_from_end: usize) -> ReproCase {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4)
+            .map(|place| Box::new(CheaterV1::new(&player_names[place], place as u8, 3)) as Box<dyn MarjapussiCheater>)
+            .collect();
+        let record = play_game(&mut players, player_names, Some(deal_from_seed(seed)), LogFilter::off(), &mut [])
+            .unwrap()
+            .record(Some(seed));
+        let decision = record.actions.len() - decision_from_end;
+        let mut game = Game::new(record.game_name.clone(), record.player_names.clone(), Some(record.deal.clone()));
+        for action in &record.actions[..decision] {
+            game.apply_action_mut(action.clone());
+        }
+        ReproCase::new(&GameFailure {
+            place: game.state.player_at_turn.0,
+            decision,
+            error: AgentError::NoLegalActions,
+            game: Box::new(game),
+            agents: record.agents.clone()
+        }, Some(seed))
+    }
+
+    #[test]
+    fn test_minimize() {
+        // a failure that only needs a search depth of at least 2
+        let original = case(6, 10);
+        let mut fails = |case: &ReproCase| case.agents[case.place as usize].search_depth >= Some(2);
+        let minimized = minimize(&original, &mut fails);
+        let case = &minimized.case;
+
+        // the case starts right at the failing decision, with the lowest failing depth and the sorted deal
+        assert!(case.actions.is_empty());
+        assert_eq!(case.agents[case.place as usize].search_depth, Some(2));
+        let hands = case.start_position().unwrap().unwrap().hands;
+        assert!(sorted_owners(&hands).iter().all(|(card, place)| hands[*place].contains(card)));
+        assert_eq!(case.game().unwrap().state.player_at_turn.0, original.place);
+        assert!(minimized.attempts as usize >= minimized.reductions.len());
+
+        // the minimized case is still a valid repro file
+        let json = serde_json::to_string(case).unwrap();
+        let loaded: ReproCase = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.start, case.start);
+    }
+
+    #[test]
+    fn test_minimize_failure() {
+        // the agent is asked to choose after the end of the game, so it really fails without legal actions
+        let original = case(6, 0);
+        let mut fails = same_failure(&original).unwrap().expect("the case should fail");
+        let minimized = minimize(&original, &mut fails);
+        let case = &minimized.case;
+
+        // the case starts in the last trick, after the last decision of the failing player
+        assert!(case.start.is_some());
+        assert!(!case.actions.is_empty() && case.actions.len() <= 4, "{:?}", case.actions);
+        assert!(case.actions.iter().all(|action| action.player.0 != case.place));
+        assert_eq!(case.agents[case.place as usize].search_depth, Some(1));
+        let reproduction = case.reproduce(LogFilter::off()).unwrap();
+        assert!(matches!(reproduction, Reproduction::Failed { decision, error: AgentError::NoLegalActions } if decision == case.actions.len()));
+    }
+
+    #[test]
+    fn test_same_failure() {
+        // the cheaters do not fail in this game, so there is nothing to minimize
+        let original = case(6, 10);
+        assert!(same_failure(&original).unwrap().is_none());
+    }
+}
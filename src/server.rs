@@ -0,0 +1,344 @@
+/*
+    A local table server: owns the game and lets up to four TCP clients play it.
+
+    The clients speak the engine protocol (see engine.rs), the server is their host. Every client that connects
+    before the join timeout gets the next free seat, the remaining seats are played by built-in agents.
+    The server only applies legal actions. A client that sends an illegal action, does not answer within the
+    action timeout or disconnects loses its seat to a built-in agent for the rest of the game.
+
+    Like the engine protocol, the server is made for cheaters: every client gets the whole deal with "new_game",
+    including the cards of the other players. It must not be used for clients that should only know their own hand.
+ */
+
+use marjapussi::game::cards::Card;
+use marjapussi::game::gameevent::GameAction;
+use marjapussi::game::gamestate::GamePhase;
+use marjapussi::game::Game;
+
+use std::io::{self, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ai::{AgentConfig, MarjapussiCheater};
+use crate::engine::host::{EngineConnection, HostError};
+use crate::engine::{parse_protocol_action, EngineMessage, HostMessage};
+use crate::log::LogFilter;
+use crate::notation::game::format_action;
+use crate::record::GameRecord;
+use crate::simulate_game::{choose_action, GameFailure};
+use crate::tournament::AgentFactory;
+
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    // how long the server waits for clients before the game starts
+    pub join_timeout: Duration,
+    // how long a client may think about an action
+    pub action_timeout: Duration,
+    // the depth sent to the clients with "go" (None lets them use their own)
+    pub depth: Option<u32>,
+    pub verbose: bool
+}
+
+
+enum Seat {
+    Client(EngineConnection),
+    Agent(Box<dyn MarjapussiCheater>)
+}
+
+// a game played at the table, with the agent of every seat at the end of the game
+pub struct ServedGame {
+    pub game: Game,
+    pub agents: Vec<AgentConfig>,
+    // the places of clients that lost their seat, with the reason
+    pub replaced: Vec<(u8, String)>
+}
+
+impl ServedGame {
+    pub fn record(&self, seed: Option<u64>) -> GameRecord {
+        GameRecord::new(&self.game, seed, self.agents.clone())
+    }
+}
+
+
+pub struct TableServer {
+    listener: TcpListener,
+    config: ServerConfig,
+    // creates the agents for empty seats and for clients that lost their seat
+    agent: AgentFactory
+}
+
+impl TableServer {
+    pub fn bind(address: impl ToSocketAddrs, config: ServerConfig, agent: AgentFactory) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(TableServer { listener, config, agent })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn play_game(&self, cards: [Vec<Card>; 4]) -> Result<ServedGame, GameFailure> {
+        /*
+            Waits for the clients, seats them and plays one game.
+            The game is aborted if one of the agents of the server fails, since nobody could take its seat.
+         */
+
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut clients = self.accept_clients();
+        let mut replaced = vec![];
+        let mut seats: Vec<Seat> = (0..4u8)
+            .map(|place| match clients.pop() {
+                Some(client) => Seat::Client(client),
+                None => Seat::Agent(self.build_agent(&player_names[place as usize], place))
+            })
+            .collect();
+        for (place, seat) in seats.iter_mut().enumerate() {
+            if let Seat::Client(client) = seat {
+                let new_game = HostMessage::NewGame { place: place as u8, player_names: player_names.clone(), deal: cards.clone() };
+                if let Err(error) = client.send(&new_game) {
+                    *seat = self.replace(seat_name(&player_names, place), place as u8, error.to_string(), &mut replaced);
+                }
+            }
+        }
+
+        let mut game = Game::new(String::from("Table Game"), player_names.clone(), Some(cards));
+        while game.state.phase != GamePhase::Ended {
+            let place = game.state.player_at_turn.0 as usize;
+            let action = match &mut seats[place] {
+                Seat::Agent(agent) => match choose_action(agent.as_mut(), &game) {
+                    Ok(action) => action,
+                    Err(error) => {
+                        let agents = seats.iter().map(seat_config).collect();
+                        close_clients(seats);
+                        return Err(GameFailure { place: place as u8, decision: game.all_events.len(), error, game: Box::new(game), agents });
+                    }
+                },
+                Seat::Client(client) => match self.request_action(client, &game) {
+                    Ok(action) => action,
+                    Err(reason) => {
+                        seats[place] = self.replace(seat_name(&player_names, place), place as u8, reason, &mut replaced);
+                        continue;
+                    }
+                }
+            };
+            if self.config.verbose {
+                println!("{}: {}", player_names[action.player.0 as usize], format_action(&action.action_type));
+            }
+
+            // every client sees every action, a client that can not be reached loses its seat
+            let message = HostMessage::Action { place: action.player.0, action: format_action(&action.action_type) };
+            for (place, seat) in seats.iter_mut().enumerate() {
+                if let Seat::Client(client) = seat {
+                    if let Err(error) = client.send(&message) {
+                        *seat = self.replace(seat_name(&player_names, place), place as u8, error.to_string(), &mut replaced);
+                    }
+                }
+            }
+            game.apply_action_mut(action);
+        }
+
+        // a client that lost its seat is recorded as the agent that took it over
+        let agents: Vec<AgentConfig> = seats.iter().map(seat_config).collect();
+        close_clients(seats);
+        Ok(ServedGame { game, agents, replaced })
+    }
+
+    fn accept_clients(&self) -> Vec<EngineConnection> {
+        /*
+            Accepts clients until four joined or the join timeout passed.
+            Clients that do not answer the greeting with "ready" are dropped.
+            The first client is the last element, so the clients can be popped in the order they joined.
+         */
+
+        let deadline = Instant::now() + self.config.join_timeout;
+        let mut clients = vec![];
+        while clients.len() < 4 && Instant::now() < deadline {
+            match self.listener.accept() {
+                Ok((stream, address)) => match self.greet(stream) {
+                    Ok(client) => {
+                        if self.config.verbose {
+                            println!("{} joined from {}", client.name, address);
+                        }
+                        clients.insert(0, client);
+                    },
+                    Err(error) if self.config.verbose => println!("client from {} was dropped: {}", address, error),
+                    Err(_) => ()
+                },
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(10)),
+                Err(error) if self.config.verbose => println!("could not accept a client: {}", error),
+                Err(_) => ()
+            }
+        }
+        clients
+    }
+
+    fn greet(&self, stream: TcpStream) -> Result<EngineConnection, HostError> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.config.action_timeout))?;
+        let mut client = EngineConnection::new(Box::new(BufReader::new(stream.try_clone()?)), Box::new(stream));
+        client.send(&HostMessage::Hello)?;
+        match client.receive()? {
+            EngineMessage::Ready { name, .. } => {
+                client.name = name;
+                Ok(client)
+            },
+            message => Err(HostError::Protocol { engine: client.name, message: format!("expected ready, got {:?}", message) })
+        }
+    }
+
+    fn request_action(&self, client: &mut EngineConnection, game: &Game) -> Result<GameAction, String> {
+        /*
+            Asks a client for its action. The reason is returned if the client fails to send a legal action in time.
+         */
+
+        let deadline = Instant::now() + self.config.action_timeout;
+        client.send(&HostMessage::Go { depth: self.config.depth, time_ms: Some(self.config.action_timeout.as_millis() as u64) })
+            .map_err(|error| error.to_string())?;
+        loop {
+            if Instant::now() > deadline {
+                return Err(String::from("the action timeout passed"));
+            }
+            match client.receive() {
+                Ok(EngineMessage::Info { .. }) => (),
+                Ok(EngineMessage::BestAction { place, action }) => {
+                    return match parse_protocol_action(place, &action).filter(|action| game.legal_actions().contains(action)) {
+                        Some(action) => Ok(action),
+                        None => {
+                            let message = format!("the action '{}' of place {} is illegal", action, place);
+                            // the client is replaced anyway, so it does not matter if it gets the error
+                            let _ = client.send(&HostMessage::Error { message: message.clone() });
+                            Err(message)
+                        }
+                    };
+                },
+                Ok(message) => return Err(format!("expected best_action, got {:?}", message)),
+                Err(HostError::Io(error)) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Err(String::from("the action timeout passed"));
+                },
+                Err(error) => return Err(error.to_string())
+            }
+        }
+    }
+
+    fn build_agent(&self, name: &str, place: u8) -> Box<dyn MarjapussiCheater> {
+        let mut agent = self.agent.build(name, place);
+        agent.set_log_filter(LogFilter::off());
+        agent
+    }
+
+    fn replace(&self, name: String, place: u8, reason: String, replaced: &mut Vec<(u8, String)>) -> Seat {
+        if self.config.verbose {
+            println!("{} loses the seat to an agent: {}", name, reason);
+        }
+        replaced.push((place, reason));
+        Seat::Agent(self.build_agent(&name, place))
+    }
+}
+
+fn seat_name(player_names: &[String; 4], place: usize) -> String {
+    player_names[place].clone()
+}
+
+fn seat_config(seat: &Seat) -> AgentConfig {
+    match seat {
+        Seat::Client(client) => AgentConfig { agent: format!("client {}", client.name), search_depth: None },
+        Seat::Agent(agent) => agent.config()
+    }
+}
+
+fn close_clients(seats: Vec<Seat>) {
+    for seat in seats {
+        if let Seat::Client(client) = seat {
+            // the game is over, a client that already disconnected does not matter any more
+            let _ = client.close();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deal::deal_from_seed;
+    use crate::engine::{run_engine, Engine};
+    use std::io::{BufRead, Write};
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            join_timeout: Duration::from_millis(500),
+            action_timeout: Duration::from_secs(5),
+            depth: Some(1),
+            verbose: false
+        }
+    }
+
+    fn bot_client(address: SocketAddr) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            run_engine(BufReader::new(stream.try_clone().unwrap()), stream, Engine::new(AgentFactory::cheater(1), 1)).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_bots_as_clients() {
+        let server = TableServer::bind("127.0.0.1:0", config(), AgentFactory::cheater(1)).unwrap();
+        let clients: Vec<thread::JoinHandle<()>> = (0..2).map(|_| bot_client(server.local_addr().unwrap())).collect();
+        let served = server.play_game(deal_from_seed(2)).unwrap();
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        assert_eq!(served.game.state.phase, GamePhase::Ended);
+        assert!(served.replaced.is_empty());
+        assert_eq!(served.agents.iter().filter(|agent| agent.agent.starts_with("client")).count(), 2);
+        served.record(Some(2)).replay().unwrap();
+    }
+
+    #[test]
+    fn test_misbehaving_clients() {
+        let config = ServerConfig { action_timeout: Duration::from_millis(300), ..config() };
+        let server = TableServer::bind("127.0.0.1:0", config, AgentFactory::cheater(1)).unwrap();
+        let address = server.local_addr().unwrap();
+
+        // the first client greets, but answers every request with an illegal action, the second one stops answering
+        let answers = [Some(r#"{"type": "best_action", "place": 0, "action": "r-A"}"#), None];
+        let clients: Vec<thread::JoinHandle<()>> = answers.iter().map(|answer| {
+            let answer = answer.map(String::from);
+            let client = thread::spawn(move || {
+                let mut stream = TcpStream::connect(address).unwrap();
+                let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+                while let Some(Ok(line)) = lines.next() {
+                    let reply = match serde_json::from_str::<HostMessage>(&line).unwrap() {
+                        HostMessage::Hello => r#"{"type": "ready", "name": "bad", "protocol": 2}"#,
+                        HostMessage::Go { .. } => match &answer {
+                            Some(answer) => answer.as_str(),
+                            None => continue
+                        },
+                        _ => continue
+                    };
+                    if writeln!(stream, "{}", reply).is_err() {
+                        break;
+                    }
+                }
+            });
+            // the clients join one after the other, so they get the places 0 and 1
+            thread::sleep(Duration::from_millis(50));
+            client
+        }).collect();
+
+        let served = server.play_game(deal_from_seed(3)).unwrap();
+        for client in clients {
+            client.join().unwrap();
+        }
+        assert_eq!(served.game.state.phase, GamePhase::Ended);
+        let replaced: Vec<u8> = served.replaced.iter().map(|(place, _)| *place).collect();
+        assert_eq!(replaced, vec![0, 1], "{:?}", served.replaced);
+        assert!(served.replaced[0].1.contains("illegal"));
+        assert!(served.replaced[1].1.contains("timeout"));
+        // the record names the agents that took over the seats
+        assert!(served.agents.iter().all(|agent| agent.agent == "CheaterV1"), "{:?}", served.agents);
+    }
+}
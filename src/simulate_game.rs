@@ -1,219 +1,305 @@
-use std::usize;
+pub mod observer;
 
-use marjapussi::game::cards::{Card, Suit, Value};
-use marjapussi::game::gameevent::{ActionType, GameCallback};
+use marjapussi::game::cards::Card;
+use marjapussi::game::gameevent::GameAction;
 use marjapussi::game::gameinfo::GameFinishedInfo;
 use marjapussi::game::player;
-use marjapussi::game::points::{points_pair, Points};
 use marjapussi::game::Game;
-use marjapussi::game::gamestate::{FinishedTrick, GamePhase};
+use marjapussi::game::gamestate::GamePhase;
 
-use crate::ai::MarjapussiCheater;
-use crate::cheater::CheaterV1;
+use serde::Serialize;
 
-pub fn bug() {
-    /*
-        reproducing a bug (probably in the framework)
-    */
-
-    // reproducing the framework bug in the bidding phase
-//     let bidding_bug_input = "0: [s-7, e-9, e-K, r-6, g-7, g-U, e-8, r-K, s-9]
-// 1: [s-8, g-6, e-U, r-7, s-6, e-Z, g-K, g-Z, s-K]
-// 2: [s-Z, r-O, e-6, r-8, s-U, r-9, g-9, r-Z, g-O]
-// 3: [g-A, s-O, e-O, r-U, e-7, g-8, s-A, r-A, e-A]";
-
-    // reproducing a probably wrong point calculation
-    let input = "0: [r-U, g-9, g-A, r-Z, r-K, g-7, g-Z, s-7, r-7]
-1: [s-6, e-O, g-K, e-A, e-6, e-8, g-O, s-O, r-8]
-2: [e-K, g-6, s-A, e-9, s-9, e-U, r-9, r-O, g-8]
-3: [s-K, e-Z, r-6, s-8, e-7, g-U, r-A, s-Z, s-U]";
-
-    fn card_from_string(card: &str) -> Card {
-        let mut suit_char = None;
-        let mut value_char = None;
-        for (num, char) in card.split("-").enumerate() {
-            if num == 0 {
-                suit_char = Some(char);
-            } else if num == 1 {
-                value_char = Some(char);
-                break
-            }
-        }
-
-        let suit = match suit_char.unwrap() {
-            "g" => Suit::Green,
-            "e" => Suit::Acorns,
-            "s" => Suit::Bells,
-            "r" => Suit::Red,
-            _ => panic!()
-        };
+use std::fmt;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-        let value = match value_char.unwrap() {
-            "6" => Value::Six,
-            "7" => Value::Seven,
-            "8" => Value::Eight,
-            "9" => Value::Nine,
-            "U" => Value::Unter,
-            "O" => Value::Ober,
-            "K" => Value::King,
-            "Z" => Value::Ten,
-            "A" => Value::Ace,
-            _ => panic!()
-        };
-
-        Card { suit, value }
-    }
+use crate::ai::{AgentConfig, AgentError, MarjapussiCheater};
+use crate::cheater::{score, score_so_far, BiddingReasoning, CheaterV1, SearchTraceConfig};
+use crate::log::LogFilter;
+use crate::notation::game::{format_action, format_game, parse_game};
+use crate::notation::position::Position;
+use crate::record::{GameRecord, RecordError};
 
-    let mut cards = [vec![], vec![], vec![], vec![]];
-
-    for (player, line) in input.split("\n").enumerate() {
-        let cards_char = line
-            .split("[")
-            .collect::<Vec<&str>>()[1]
-            .split("]")
-            .collect::<Vec<&str>>()[0]
-            .split(", ")
-            .collect::<Vec<&str>>();
-        for card in cards_char {
-            cards[player].push(card_from_string(card));
-        };
-    };
-    println!("{:?}", cards);
+use observer::{GameObserver, GameStreamEvent};
 
-    four_cheaters(12, Some(cards));
-}
-
-pub fn four_cheaters(search_depth: u32, cards: Option<[Vec<Card>; 4]>) {
+pub fn four_cheaters(search_depth: u32, cards: [Vec<Card>; 4], seed: Option<u64>, trace: Option<SearchTraceConfig>,
+                     log_filter: LogFilter, observers: &mut [&mut dyn GameObserver]) -> Result<GameRecord, GameFailure> {
+    /*
+        Plays a game with four cheaters, reports it to the observers and returns the record of the game.
+        The cheaters print the log entries about their decisions that pass the filter.
+     */
 
     // create players and game object
-    let game_name = String::from("Cheater Game");
     let player_names = [
         String::from("Player 1"), 
         String::from("Player 2"), 
         String::from("Player 3"), 
         String::from("Player 4")
     ];
-    let mut players: Vec<CheaterV1> = player_names
+    let mut players: Vec<Box<dyn MarjapussiCheater>> = player_names
                                         .iter()
                                         .enumerate()
                                         .map(|(place, name)| {
-                                            CheaterV1::new(name, place.try_into().unwrap(), search_depth)
+                                            let mut player = CheaterV1::new(name, place.try_into().unwrap(), search_depth);
+                                            if let Some(config) = &trace {
+                                                player.trace_searches(config.clone());
+                                            }
+                                            Box::new(player) as Box<dyn MarjapussiCheater>
                                         })
                                         .collect();
-    let mut game = Game::new(game_name, player_names.clone(), cards);
+    Ok(play_game(&mut players, player_names, Some(cards), log_filter, observers)?.record(seed))
+}
+
+
+pub fn save_game(record: &GameRecord, path: &Path) -> Result<(), RecordError> {
+    /*
+        Saves a game as JSON record if the path ends with .json, otherwise in the text notation.
+     */
 
-    // print the start cards of each player
-    println!("\nStart cards:");
-    for player in &game.state.players {
-        println!("{}: {:?}", player.name, player.cards);
+    if path.extension().is_some_and(|extension| extension == "json") {
+        record.save(path)
+    } else {
+        fs::write(path, format_game(record)?)?;
+        Ok(())
+    }
+}
+
+pub fn load_game(path: &Path) -> Result<GameRecord, RecordError> {
+    /*
+        Loads a game saved by save_game and checks that it can be replayed.
+     */
+
+    let record = if path.extension().is_some_and(|extension| extension == "json") {
+        GameRecord::load(path)?
+    } else {
+        parse_game(&fs::read_to_string(path)?)?
+    };
+    record.replay()?;
+    Ok(record)
+}
+
+
+// a finished game together with the players' configurations and the time each seat needed for its decisions
+pub struct PlayedGame {
+    pub game: Game,
+    pub agents: Vec<AgentConfig>,
+    pub decisions: [u32; 4],
+    pub decision_times: [Duration; 4],
+    pub bidding_reasoning: Vec<BiddingReasoning>
+}
+
+impl PlayedGame {
+    pub fn record(&self, seed: Option<u64>) -> GameRecord {
+        GameRecord {
+            bidding_reasoning: self.bidding_reasoning.clone(),
+            ..GameRecord::new(&self.game, seed, self.agents.clone())
+        }
     }
+}
+
+// a game that was aborted because a player could not choose an action
+#[derive(Debug)]
+pub struct GameFailure {
+    pub place: u8,
+    // the number of actions that were applied before the failing decision
+    pub decision: usize,
+    pub error: AgentError,
+    // the game up to the failing decision and the players' configurations, to reproduce the failure
+    pub game: Box<Game>,
+    pub agents: Vec<AgentConfig>
+}
+
+impl fmt::Display for GameFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "player {} failed after {} actions: {}", self.place + 1, self.decision, self.error)
+    }
+}
+
+impl std::error::Error for GameFailure {}
+
+pub fn play_game(players: &mut [Box<dyn MarjapussiCheater>], player_names: [String; 4], cards: Option<[Vec<Card>; 4]>,
+                 log_filter: LogFilter, observers: &mut [&mut dyn GameObserver]) -> Result<PlayedGame, GameFailure> {
+    /*
+        Plays a game with the given players (indexed by their place at the table) and reports everything that happens to the observers.
+        The players only log what passes the filter.
+        The game is aborted when a player fails to choose an action (see choose_action), the failure is reported to the observers as well.
+     */
+
+    for player in players.iter_mut() {
+        player.set_log_filter(log_filter.clone());
+    }
+    let mut report = |event: GameStreamEvent| {
+        for observer in observers.iter_mut() {
+            observer.observe(&event);
+        }
+    };
+
+    let game_name = String::from("Cheater Game");
+    let mut game = Game::new(game_name, player_names.clone(), cards);
+    let mut decisions = [0; 4];
+    let mut decision_times = [Duration::ZERO; 4];
+    let mut bidding_reasoning = vec![];
+    report(GameStreamEvent::Start {
+        player_names,
+        deal: [0, 1, 2, 3].map(|place| game.state.player_at_place(player::PlaceAtTable(place)).cards.clone())
+    });
 
     // iterate through the game step for step
-    let mut player_at_turn;
-    let mut player_name;
     while game.state.phase != GamePhase::Ended {
 
         // find out which player is at turn
-        player_at_turn = game.state.player_at_turn().place_at_table.0;
-        player_name = &player_names[player_at_turn as usize];
-
-        // print the current state of the game
-        match game.state.phase {
-            GamePhase::StartTrick => println!("\n{} starting new trick", player_name),
-            GamePhase::Bidding => println!("\n{}: thinking about the next bidding step", player_name),
-            GamePhase::Raising => println!("\nRaising or starting first trick"),
-            _ => ()
-        }
-        // print the player's cards
-        match game.state.phase {
-            GamePhase::WaitingForStart => (),
-            GamePhase::Bidding => println!("  cards: {:?}", game.state.player_at_place(player::PlaceAtTable(player_at_turn)).cards),
-            _ => println!("{}: cards: {:?}", player_name, game.state.player_at_place(player::PlaceAtTable(player_at_turn)).cards)
-        }
+        let player_at_turn = game.state.player_at_turn().place_at_table.0;
+        report(GameStreamEvent::Turn {
+            place: player_at_turn,
+            phase: format!("{:?}", game.state.phase),
+            cards: game.state.player_at_place(player::PlaceAtTable(player_at_turn)).cards.clone()
+        });
 
         // let the player choose an action
-        let chosen_action = players[usize::from(player_at_turn)].select_action(game.clone());
+        let decision_start = Instant::now();
+        let chosen_action = match choose_action(players[usize::from(player_at_turn)].as_mut(), &game) {
+            Ok(action) => action,
+            Err(error) => {
+                report(GameStreamEvent::Failure { place: player_at_turn, error: error.to_string() });
+                return Err(GameFailure {
+                    place: player_at_turn,
+                    decision: game.all_events.len(),
+                    error,
+                    game: Box::new(game),
+                    agents: players.iter().map(|player| player.config()).collect()
+                });
+            }
+        };
+        let decision_time = decision_start.elapsed();
+        decision_times[usize::from(player_at_turn)] += decision_time;
+        decisions[usize::from(player_at_turn)] += 1;
+        bidding_reasoning.extend(players[usize::from(player_at_turn)].take_bidding_reasoning());
 
-        // print the chosen action
-        match game.state.phase {
-            GamePhase::WaitingForStart => (),
-            GamePhase::Bidding => println!("  {:?}", chosen_action.action_type),
-            _ => println!("  {:?}", chosen_action.action_type)
-        }
-        
         // apply the chosen action to the game
+        let finished_tricks = game.state.all_tricks.len();
         game.apply_action_mut(chosen_action.clone());
+        report(GameStreamEvent::Action {
+            place: chosen_action.player.0,
+            action: format_action(&chosen_action.action_type),
+            time_ms: decision_time.as_millis() as u64
+        });
+        if let Some(callback) = game.all_events.last().and_then(|event| event.callback.clone()) {
+            report(GameStreamEvent::Callback { place: chosen_action.player.0, callback });
+        }
 
-        // if a trick was just finished: print the winner
-        if let Some(last_trick) = game.state.all_tricks.last() {
-            if chosen_action.action_type == ActionType::CardPlayed(last_trick.cards.last().unwrap().clone()) {
-                println!("Trick goes to {}", player_names[last_trick.winner.0 as usize]);
-            }
+        // if a trick was just finished: report it with the winner
+        if game.state.all_tricks.len() > finished_tricks {
+            let trick = game.state.all_tricks.last().unwrap();
+            report(GameStreamEvent::Trick {
+                number: game.state.all_tricks.len(),
+                cards: trick.cards.to_vec(),
+                winner: trick.winner.0,
+                points: trick.points.0
+            });
         }
     }
+    report(GameStreamEvent::Result {
+        result: GameResult::from_game(&game),
+        won: GameFinishedInfo::from(game.clone()).won
+    });
 
-    // print some info about the finished game
-    println!("\nFinal info:");
-    let final_info = GameFinishedInfo::from(game.clone());
-    print_evaluation(&game, &final_info);
+    Ok(PlayedGame {
+        game,
+        agents: players.iter().map(|player| player.config()).collect(),
+        decisions,
+        decision_times,
+        bidding_reasoning
+    })
 }
 
-fn print_evaluation(game: &Game, game_finished_info: &GameFinishedInfo) {
+pub fn choose_action(player: &mut dyn MarjapussiCheater, game: &Game) -> Result<GameAction, AgentError> {
     /*
-        Mainly copied from super::cheater::search_state::AlphaBetaGameState::evaluate
-    */
+        Lets the player choose an action and makes sure that it is legal.
+        A panic of the player is caught and returned as error, so a long simulation is not ended by a single game.
+     */
 
-    // this will hold the points each player made
-    let mut players_points = [Points(0); 4];
+    checked_choice(game, || player.select_action(game.clone()))
+}
 
-    // calculate the points from winning tricks
-    let mut players_tricks: [Vec<FinishedTrick>; 4] = [vec![], vec![], vec![], vec![]];
-    for trick in &game.state.all_tricks {
-        players_tricks[trick.winner.0 as usize].push(trick.clone());
-        players_points[trick.winner.0 as usize] += trick.points;
+pub fn choose_action_in_position(player: &mut dyn MarjapussiCheater, position: &Position) -> Result<GameAction, AgentError> {
+    /*
+        Like choose_action, but in a position, so the player knows the score so far.
+     */
+
+    checked_choice(&position.to_game(), || player.select_action_in_position(position))
+}
+
+fn checked_choice(game: &Game, select: impl FnOnce() -> Result<GameAction, AgentError>) -> Result<GameAction, AgentError> {
+    let action = panic::catch_unwind(AssertUnwindSafe(select))
+        .map_err(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("unknown panic"));
+            AgentError::Panic(message)
+        })??;
+    if !game.legal_actions().contains(&action) {
+        return Err(AgentError::IllegalChoice(format!("{:?} of player {}", action.action_type, action.player.0)));
     }
+    Ok(action)
+}
 
-    // points from winning the last trick (+20)
-    let last_trick = game.state.all_tricks.last().unwrap();
-    players_points[last_trick.winner.0 as usize] += Points(20);
+// the outcome of a finished game
+#[derive(Debug, Clone, Serialize)]
+pub struct GameResult {
+    pub party_points: [i32; 2],
+    pub party_tricks: [usize; 2],
+    pub playing_party: Option<u8>,
+    pub game_value: i32
+}
 
-    // calculate the points from announcing pairs
-    // also get the playing party
-    let mut playing_party = None;
-    for event in &game.all_events {
-        if ActionType::NewBid(game.state.value.0) == event.last_action.action_type {
-            playing_party = Some(event.last_action.player.0 % 2);
-        }
-        if let Some(GameCallback::NewTrump(suit)) = event.callback {
-            players_points[event.last_action.player.clone().0 as usize] +=
-                points_pair(suit);
+impl GameResult {
+    pub fn from_game(game: &Game) -> Self {
+        /*
+            The result of a finished game, scored like the search does it (see cheater::score_so_far).
+        */
+
+        let (party_points, party_tricks, playing_party) = score_so_far(game);
+        GameResult {
+            party_points,
+            party_tricks: party_tricks.map(|tricks| tricks as usize),
+            playing_party,
+            game_value: game.state.value.0
         }
     }
 
-    // calculate the points each party reached
-    let party_points: [i32; 2] = [
-        players_points[0].0 + players_points[2].0,
-        players_points[1].0 + players_points[3].0
-    ];
-    let game_value = game.state.value.0;
+    pub fn schwarz_game(&self) -> bool {
+        self.party_tricks[0] == 0 || self.party_tricks[1] == 0
+    }
 
-    // find out if this was a schwarz game
-    let tricks_party_zero = players_tricks[0].len() + players_tricks[2].len();
-    let schwarz_game = tricks_party_zero == 0 || tricks_party_zero == 9;
+    pub fn won(&self) -> Option<bool> {
+        // None if no one played
+        self.playing_party.map(|playing| self.party_points[playing as usize] >= self.game_value)
+    }
 
+    pub fn score(&self, party: u8) -> i32 {
+        score(self.party_points, self.party_tricks.map(|tricks| tricks as i8), self.playing_party, self.game_value, party)
+    }
+}
+
+
+pub fn print_evaluation(result: &GameResult, won: Option<bool>) {
     // print info
-    if schwarz_game {
+    if result.schwarz_game() {
         println!("- schwarz game");
     }
-    if let Some(playing) = playing_party {
+    if let Some(playing) = result.playing_party {
         let non_playing = (playing + 1) % 2;
-        println!("- playing party ({}): {}/{} points", playing, party_points[playing as usize], game_value);
-        println!("- non-playing party ({}): {} points", non_playing, party_points[non_playing as usize]);
-        println!("- playing party {}", if game_finished_info.won.unwrap() {"won"} else {"lost"})
+        println!("- playing party ({}): {}/{} points", playing, result.party_points[playing as usize], result.game_value);
+        println!("- non-playing party ({}): {} points", non_playing, result.party_points[non_playing as usize]);
+        println!("- playing party {}", if won.unwrap() {"won"} else {"lost"})
     } else {
         println!("- no playing party");
         for i in 0..2 {
-            println!("- party ({}): {} points", i, party_points[i as usize])
+            println!("- party ({}): {} points", i, result.party_points[i as usize])
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,192 @@
+/*
+    Observers follow a game while it is simulated.
+
+    play_game reports everything that happens as GameStreamEvent to its observers:
+    the start of the game, the player at turn, every applied action with its callback, finished tricks and the final result,
+    or the failure of a player that aborted the game.
+    The JsonLinesObserver writes every event as one JSON object per line (NDJSON), e.g.
+        {"type":"action","place":1,"action":"r-A","time_ms":12}
+        {"type":"trick","number":3,"cards":["r-A","r-6","r-K","r-9"],"winner":1,"points":15}
+    The TextObserver prints the game for humans.
+ */
+
+use marjapussi::game::cards::Card;
+use marjapussi::game::gameevent::GameCallback;
+
+use serde::Serialize;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::{print_evaluation, GameResult};
+
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameStreamEvent {
+    Start { player_names: [String; 4], deal: [Vec<Card>; 4] },
+    // the player at turn is about to choose an action, with the phase and the player's cards
+    Turn { place: u8, phase: String, cards: Vec<Card> },
+    // the action in the game notation and the time the player needed to choose it
+    Action { place: u8, action: String, time_ms: u64 },
+    // implicit information that followed the last action, e.g. a new trump
+    Callback { place: u8, callback: GameCallback },
+    Trick { number: usize, cards: Vec<Card>, winner: u8, points: i32 },
+    Result {
+        #[serde(flatten)]
+        result: GameResult,
+        // None if no one played
+        won: Option<bool>
+    },
+    // the player at turn could not choose an action, the game is aborted
+    Failure { place: u8, error: String }
+}
+
+pub trait GameObserver {
+    fn observe(&mut self, event: &GameStreamEvent);
+}
+
+
+pub struct JsonLinesObserver {
+    output: Box<dyn Write>,
+    // the first error while writing, nothing is written after it
+    error: Option<io::Error>
+}
+
+impl JsonLinesObserver {
+    pub fn new(output: Box<dyn Write>) -> Self {
+        JsonLinesObserver { output, error: None }
+    }
+
+    pub fn stdout() -> Self {
+        JsonLinesObserver::new(Box::new(io::stdout()))
+    }
+
+    pub fn file(path: &Path) -> io::Result<Self> {
+        Ok(JsonLinesObserver::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        /*
+            Flushes the output and returns the first error that occurred while writing the events.
+         */
+
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.output.flush()
+        }
+    }
+}
+
+impl GameObserver for JsonLinesObserver {
+    fn observe(&mut self, event: &GameStreamEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let line = serde_json::to_string(event).expect("Game stream events can be serialized");
+        // every line is flushed, so the events can be followed live
+        if let Err(error) = writeln!(self.output, "{}", line).and_then(|_| self.output.flush()) {
+            self.error = Some(error);
+        }
+    }
+}
+
+
+#[derive(Default)]
+pub struct TextObserver {
+    player_names: [String; 4],
+    // the start of the game is not printed
+    waiting_for_start: bool
+}
+
+impl GameObserver for TextObserver {
+    fn observe(&mut self, event: &GameStreamEvent) {
+        match event {
+            GameStreamEvent::Start { player_names, deal } => {
+                self.player_names = player_names.clone();
+                println!("\nStart cards:");
+                for (name, cards) in player_names.iter().zip(deal) {
+                    println!("{}: {:?}", name, cards);
+                }
+            },
+            GameStreamEvent::Turn { place, phase, cards } => {
+                let player_name = &self.player_names[*place as usize];
+                self.waiting_for_start = phase == "WaitingForStart";
+                match phase.as_str() {
+                    "StartTrick" => println!("\n{} starting new trick", player_name),
+                    "Bidding" => println!("\n{}: thinking about the next bidding step", player_name),
+                    "Raising" => println!("\nRaising or starting first trick"),
+                    _ => ()
+                }
+                match phase.as_str() {
+                    "WaitingForStart" => (),
+                    "Bidding" => println!("  cards: {:?}", cards),
+                    _ => println!("{}: cards: {:?}", player_name, cards)
+                }
+            },
+            GameStreamEvent::Action { action, .. } if !self.waiting_for_start => println!("  {}", action),
+            GameStreamEvent::Trick { winner, .. } => println!("Trick goes to {}", self.player_names[*winner as usize]),
+            GameStreamEvent::Result { result, won } => {
+                println!("\nFinal info:");
+                print_evaluation(result, *won);
+            },
+            GameStreamEvent::Failure { place, error } => {
+                println!("\n{} failed: {}", self.player_names[*place as usize], error);
+            },
+            _ => ()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheater::CheaterV1;
+    use crate::ai::MarjapussiCheater;
+    use crate::deal::deal_from_seed;
+    use crate::log::LogFilter;
+    use crate::simulate_game::play_game;
+    use std::sync::{Arc, Mutex};
+
+    // collects everything written to it, so the test can read it after the observer is finished
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let player_names = [0, 1, 2, 3].map(|place| format!("Player {}", place + 1));
+        let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4u8)
+            .map(|place| Box::new(CheaterV1::new(&player_names[place as usize], place, 1)) as Box<dyn MarjapussiCheater>)
+            .collect();
+        let buffer = SharedBuffer::default();
+        let mut observer = JsonLinesObserver::new(Box::new(buffer.clone()));
+        let played_game = play_game(&mut players, player_names, Some(deal_from_seed(4)), LogFilter::off(), &mut [&mut observer]).unwrap();
+        observer.finish().unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let count = |kind: &str| events.iter().filter(|event| event["type"] == kind).count();
+        assert_eq!(events.first().unwrap()["type"], "start");
+        assert_eq!(count("action"), played_game.game.all_events.len());
+        assert_eq!(count("turn"), count("action"));
+        assert_eq!(count("trick"), 9);
+        let result = events.last().unwrap();
+        assert_eq!(result["type"], "result");
+        let party_points = result["party_points"].as_array().unwrap();
+        assert_eq!(party_points.len(), 2);
+        let callbacks = played_game.game.all_events.iter().filter(|event| event.callback.is_some()).count();
+        assert_eq!(count("callback"), callbacks);
+    }
+}
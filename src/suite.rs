@@ -0,0 +1,199 @@
+/*
+    A test suite of positions with known best actions, in a format similar to EPD in chess:
+
+    <position>; bm <action> [<action> ...]; id "<name>"; c0 "<comment>"
+
+    The position is written in the position notation, followed by operations separated by ';':
+        bm: the best actions, the agent has to choose one of them
+        am: actions the agent must avoid (instead of or in addition to bm)
+        id: the unique name of the position
+        c0: a comment, e.g. why the action is the best one
+    Actions are written in the game notation without the place, since the player at turn acts.
+    Empty lines and lines starting with '#' are ignored.
+ */
+
+use marjapussi::game::gameevent::{ActionType, GameAction};
+
+use crate::log::LogFilter;
+use crate::notation::game::{format_action, parse_action};
+use crate::notation::position::{parse_position, Position};
+use crate::notation::{NotationError, NotationErrorKind};
+use crate::tournament::AgentFactory;
+
+
+#[derive(Debug, Clone)]
+pub struct SuiteEntry {
+    pub id: String,
+    pub position: Position,
+    pub best_actions: Vec<ActionType>,
+    pub avoid_actions: Vec<ActionType>,
+    pub comment: Option<String>
+}
+
+impl SuiteEntry {
+    pub fn passed(&self, action: &ActionType) -> bool {
+        (self.best_actions.is_empty() || self.best_actions.contains(action)) && !self.avoid_actions.contains(action)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SuiteOutcome {
+    pub id: String,
+    // the error, if the agent could not choose an action
+    pub chosen: Result<GameAction, String>,
+    pub passed: bool
+}
+
+
+pub fn parse_suite(input: &str) -> Result<Vec<SuiteEntry>, NotationError> {
+    /*
+        Parses all positions of a suite and checks that their actions are legal.
+     */
+
+    let mut entries: Vec<SuiteEntry> = vec![];
+    for (index, line) in input.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let entry = parse_entry(line).map_err(|error| NotationError { line: index + 1, ..error })?;
+        if entries.iter().any(|other| other.id == entry.id) {
+            return Err(NotationError {
+                kind: NotationErrorKind::InvalidTag { tag: String::from("id"), value: entry.id },
+                line: index + 1,
+                column: 1
+            });
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn parse_entry(line: &str) -> Result<SuiteEntry, NotationError> {
+    let error = |column: usize, kind: NotationErrorKind| NotationError { kind, line: 1, column };
+    let mut parts = line.split(';');
+    let position = parse_position(parts.next().unwrap_or_default())?;
+    let legal_actions: Vec<ActionType> = position.to_game()
+        .legal_actions()
+        .into_iter()
+        .map(|action| action.action_type)
+        .collect();
+
+    let mut id = None;
+    let mut comment = None;
+    let mut best_actions = vec![];
+    let mut avoid_actions = vec![];
+    let mut column = line.find(';').unwrap_or(line.len()) + 2;
+    for part in parts {
+        let (opcode, operand) = part.trim().split_once(' ').unwrap_or((part.trim(), ""));
+        let operand = operand.trim();
+        match opcode {
+            "bm" | "am" => {
+                for word in operand.split_whitespace() {
+                    let action = parse_action(word).ok_or(error(column, NotationErrorKind::UnknownAction(String::from(word))))?;
+                    if !legal_actions.contains(&action) {
+                        return Err(error(column, NotationErrorKind::IllegalAction(String::from(word))));
+                    }
+                    if opcode == "bm" { best_actions.push(action) } else { avoid_actions.push(action) }
+                }
+            },
+            "id" | "c0" => {
+                let text = operand
+                    .strip_prefix('"')
+                    .and_then(|operand| operand.strip_suffix('"'))
+                    .ok_or(error(column, NotationErrorKind::InvalidTag { tag: String::from(opcode), value: String::from(operand) }))?;
+                if opcode == "id" { id = Some(String::from(text)) } else { comment = Some(String::from(text)) }
+            },
+            "" => (),
+            _ => return Err(error(column, NotationErrorKind::UnknownTag(String::from(opcode))))
+        }
+        column += part.len() + 1;
+    }
+
+    let end = line.len() + 1;
+    let id = id.ok_or(error(end, NotationErrorKind::InvalidTag { tag: String::from("id"), value: String::new() }))?;
+    if best_actions.is_empty() && avoid_actions.is_empty() {
+        return Err(error(end, NotationErrorKind::InvalidTag { tag: String::from("bm"), value: String::new() }));
+    }
+    Ok(SuiteEntry { id, position, best_actions, avoid_actions, comment })
+}
+
+
+pub fn run_suite(entries: &[SuiteEntry], agent: &AgentFactory) -> Vec<SuiteOutcome> {
+    /*
+        Lets a new agent of the factory choose an action in every position.
+     */
+
+    entries
+        .iter()
+        .map(|entry| {
+            let place = entry.position.player_at_turn;
+            let mut player = agent.build(&format!("Player {}", place + 1), place);
+            player.set_log_filter(LogFilter::off());
+            let chosen = player.select_action_in_position(&entry.position).map_err(|error| error.to_string());
+            SuiteOutcome {
+                id: entry.id.clone(),
+                passed: chosen.as_ref().is_ok_and(|action| entry.passed(&action.action_type)),
+                chosen
+            }
+        })
+        .collect()
+}
+
+pub fn print_outcomes(entries: &[SuiteEntry], outcomes: &[SuiteOutcome]) {
+    for (entry, outcome) in entries.iter().zip(outcomes) {
+        let expected = entry.best_actions
+            .iter()
+            .map(format_action)
+            .chain(entry.avoid_actions.iter().map(|action| format!("not {}", format_action(action))))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let chosen = match &outcome.chosen {
+            Ok(action) => format!("chose {:<10}", format_action(&action.action_type)),
+            Err(error) => format!("failed ({})", error)
+        };
+        println!(
+            "{:<4} {:<28} {} expected {}",
+            if outcome.passed { "ok" } else { "FAIL" },
+            outcome.id,
+            chosen,
+            expected
+        );
+        if let (false, Some(comment)) = (outcome.passed, &entry.comment) {
+            println!("     {}", comment);
+        }
+    }
+    let passed = outcomes.iter().filter(|outcome| outcome.passed).count();
+    println!("\n{} of {} positions passed", passed, outcomes.len());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: &str = include_str!("../suite/best_actions.txt");
+
+    #[test]
+    fn test_best_actions() {
+        // the positions are late enough that the search is complete
+        let entries = parse_suite(SUITE).unwrap();
+        let outcomes = run_suite(&entries, &AgentFactory::cheater(20));
+        let failed: Vec<String> = outcomes
+            .iter()
+            .filter(|outcome| !outcome.passed)
+            .map(|outcome| format!("{} (chose {:?})", outcome.id, outcome.chosen.as_ref().map(|action| format_action(&action.action_type))))
+            .collect();
+        assert!(failed.is_empty(), "wrong actions in the best-action suite: {}", failed.join(", "));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let line = SUITE.lines().find(|line| !line.starts_with('#') && line.contains("bm ")).unwrap();
+        let kind = |input: &str| parse_suite(input).unwrap_err().kind;
+        assert!(matches!(kind(&line.replace("bm ", "xy ")), NotationErrorKind::UnknownTag(_)));
+        assert!(matches!(kind(&line.replace("bm ", "bm pass ")), NotationErrorKind::UnknownAction(_)));
+        assert!(matches!(kind(&line.replace("bm ", "bm undo-accept ")), NotationErrorKind::IllegalAction(_)));
+        assert!(matches!(kind(&format!("{}\n{}", line, line)), NotationErrorKind::InvalidTag { .. }));
+        assert_eq!(parse_suite(&format!("# comment\n\n{}", line.replace("bm ", "xy "))).unwrap_err().line, 3);
+    }
+}
@@ -0,0 +1,469 @@
+use std::time::Duration;
+
+use marjapussi::game::cards::Card;
+
+use crate::ai::{AgentConfig, MarjapussiCheater};
+use crate::cheater::CheaterV1;
+use crate::deal::deal_from_seed;
+use crate::human::HumanPlayer;
+use crate::log::LogFilter;
+use crate::simulate_game::{play_game, GameFailure, GameResult, PlayedGame};
+
+
+type BuildAgent = dyn Fn(&str, u8) -> Box<dyn MarjapussiCheater>;
+
+// creates agents of one kind for a given player name and place at the table
+pub struct AgentFactory {
+    pub name: String,
+    build: Box<BuildAgent>
+}
+
+impl AgentFactory {
+    pub fn new<F>(name: &str, build: F) -> Self
+    where F: Fn(&str, u8) -> Box<dyn MarjapussiCheater> + 'static {
+        AgentFactory {
+            name: String::from(name),
+            build: Box::new(build)
+        }
+    }
+
+    pub fn cheater(search_depth: u32) -> Self {
+        AgentFactory::new(&format!("CheaterV1 (depth {})", search_depth), move |name, place| {
+            Box::new(CheaterV1::new(name, place, search_depth))
+        })
+    }
+
+    pub fn human(hint_depth: u32) -> Self {
+        AgentFactory::new("Human", move |_, place| Box::new(HumanPlayer::terminal(place, hint_depth)))
+    }
+
+    pub fn from_config(config: &AgentConfig) -> Option<Self> {
+        /*
+            The factory of the agents with the configuration, as it is saved in records.
+            Only agents that play on their own can be created again, so not humans or clients of a server.
+         */
+
+        match (config.agent.as_str(), config.search_depth) {
+            ("CheaterV1", Some(search_depth)) => Some(AgentFactory::cheater(search_depth)),
+            _ => None
+        }
+    }
+
+    pub fn build(&self, name: &str, place: u8) -> Box<dyn MarjapussiCheater> {
+        (self.build)(name, place)
+    }
+}
+
+
+// everything we measure for one team during a tournament
+#[derive(Debug, Clone, Default)]
+pub struct TeamStats {
+    pub name: String,
+    // the score of every deal, as calculated by GameResult::score
+    pub scores: Vec<i32>,
+    pub contracts_bid: u32,
+    pub contracts_made: u32,
+    pub schwarz_won: u32,
+    pub schwarz_lost: u32,
+    pub decisions: u32,
+    pub decision_time: Duration
+}
+
+impl TeamStats {
+    pub fn mean_score(&self) -> f64 {
+        mean(&self.scores)
+    }
+
+    pub fn confidence_interval(&self) -> f64 {
+        /*
+            Half width of the 95% confidence interval of the mean score (normal approximation).
+         */
+
+        confidence_interval(&self.scores)
+    }
+
+    pub fn avg_decision_time(&self) -> Duration {
+        if self.decisions == 0 {
+            Duration::ZERO
+        } else {
+            self.decision_time / self.decisions
+        }
+    }
+}
+
+
+// a game of a tournament that was skipped because an agent failed
+#[derive(Debug)]
+pub struct FailedGame {
+    pub seed: u64,
+    pub failure: GameFailure
+}
+
+pub struct TournamentResult {
+    // deal i was dealt from the seed first_seed + i
+    pub first_seed: u64,
+    // the number of finished games, without the failed ones
+    pub games: u32,
+    pub teams: [TeamStats; 2],
+    pub failed: Vec<FailedGame>
+}
+
+impl TournamentResult {
+    pub fn print_summary(&self) {
+        println!("\n ---------- Tournament results ----------");
+        println!("number of games: {}", self.games);
+        println!("first deal seed: {}", self.first_seed);
+        println!(
+            "{:<32} {:>20} {:>6} {:>6} {:>10} {:>10} {:>14}",
+            "team", "avg score (95% CI)", "bid", "made", "schwarz +", "schwarz -", "avg decision"
+        );
+        for team in &self.teams {
+            println!(
+                "{:<32} {:>20} {:>6} {:>6} {:>10} {:>10} {:>14}",
+                team.name,
+                format!("{:.1} ± {:.1}", team.mean_score(), team.confidence_interval()),
+                team.contracts_bid,
+                team.contracts_made,
+                percentage(team.schwarz_won, self.games),
+                percentage(team.schwarz_lost, self.games),
+                format!("{:.2?}", team.avg_decision_time())
+            );
+        }
+        if !self.failed.is_empty() {
+            println!("skipped {} failed game(s):", self.failed.len());
+            for failed in &self.failed {
+                println!("  deal seed {}: {}", failed.seed, failed.failure);
+            }
+        }
+    }
+}
+
+
+// the result of a tournament in which every deal was played by both teams on both sides
+pub struct DuplicateResult {
+    pub tournament: TournamentResult,
+    // per deal: the score of the first team minus the score of the second team, summed over both games
+    pub differences: Vec<i32>,
+    // the seed of the deal of every difference, deals with a failed game have no difference
+    pub seeds: Vec<u64>
+}
+
+impl DuplicateResult {
+    pub fn mean_difference(&self) -> f64 {
+        mean(&self.differences)
+    }
+
+    pub fn significance(&self) -> Option<(f64, f64)> {
+        /*
+            Paired test of the hypothesis that both teams are equally strong.
+            Returns the t statistic of the mean difference and its two-sided p-value.
+            The p-value uses the normal approximation, so it is only meaningful for a reasonable number of deals.
+            None if there are less than two deals or all differences are equal.
+         */
+
+        let standard_error = confidence_interval(&self.differences) / 1.96;
+        if !standard_error.is_finite() || standard_error == 0.0 {
+            return None;
+        }
+        let t = self.mean_difference() / standard_error;
+        Some((t, 2.0 * (1.0 - normal_cdf(t.abs()))))
+    }
+
+    pub fn print_summary(&self) {
+        self.tournament.print_summary();
+
+        println!("\n ---------- Duplicate comparison ----------");
+        println!("score difference per deal ({} - {}):", self.tournament.teams[0].name, self.tournament.teams[1].name);
+        for (deal, (difference, seed)) in self.differences.iter().zip(&self.seeds).enumerate() {
+            println!("  deal {} (seed {}): {:+}", deal + 1, seed, difference);
+        }
+        println!(
+            "avg difference per deal: {:.1} ± {:.1} (95% CI)",
+            self.mean_difference(),
+            confidence_interval(&self.differences)
+        );
+        match self.significance() {
+            Some((t, p)) => println!("paired test: t = {:.2}, p = {:.4}", t, p),
+            None => println!("paired test: not enough variation")
+        }
+    }
+}
+
+
+pub fn run_tournament(agents: &[AgentFactory], deals: u32, first_seed: u64) -> TournamentResult {
+    /*
+        Plays the given number of deals without any output.
+        Deal i is dealt from the seed first_seed + i, so every game can be replayed.
+        The agents sit at the table in the given order, so agents with even and odd indices are partners and form the teams.
+        After every deal, all agents move one seat further, so every agent plays every seat equally often.
+        Games in which an agent fails are skipped and kept in the result.
+     */
+
+    let mut teams = new_teams(agents);
+    let mut failed = vec![];
+    for deal in 0..deals as usize {
+        let seed = first_seed + deal as u64;
+        match play_deal(agents, deal, deal_from_seed(seed)) {
+            Ok(played_game) => {
+                add_game(agents, deal, &played_game, &mut teams);
+            },
+            Err(failure) => failed.push(FailedGame { seed, failure })
+        }
+    }
+
+    TournamentResult {
+        first_seed,
+        games: teams[0].scores.len() as u32,
+        teams,
+        failed
+    }
+}
+
+pub fn run_duplicate(agents: &[AgentFactory], deals: u32, first_seed: u64) -> DuplicateResult {
+    /*
+        Like run_tournament, but every random deal is played a second time with the same cards,
+        with all agents moved one seat further. So each team gets the cards the other team had in the first game,
+        and comparing the scores per deal removes most of the card luck.
+        If one of the two games fails, the whole deal is skipped, since the other game can not be compared.
+     */
+
+    let mut teams = new_teams(agents);
+    let mut differences = vec![];
+    let mut seeds = vec![];
+    let mut failed = vec![];
+    for deal in 0..deals as usize {
+        let seed = first_seed + deal as u64;
+        let cards = deal_from_seed(seed);
+        let played_games = play_deal(agents, deal, cards.clone())
+            .and_then(|first_game| Ok((first_game, play_deal(agents, deal + 1, cards)?)));
+        match played_games {
+            Ok((first_game, second_game)) => {
+                let first_scores = add_game(agents, deal, &first_game, &mut teams);
+                let second_scores = add_game(agents, deal + 1, &second_game, &mut teams);
+                differences.push(first_scores[0] + second_scores[0] - first_scores[1] - second_scores[1]);
+                seeds.push(seed);
+            },
+            Err(failure) => failed.push(FailedGame { seed, failure })
+        }
+    }
+
+    DuplicateResult {
+        tournament: TournamentResult {
+            first_seed,
+            games: teams[0].scores.len() as u32,
+            teams,
+            failed
+        },
+        differences,
+        seeds
+    }
+}
+
+
+fn new_teams(agents: &[AgentFactory]) -> [TeamStats; 2] {
+    assert!(agents.len() == 2 || agents.len() == 4,
+            "A tournament needs two agents (one per team) or four agents (one per seat)");
+
+    [0, 1].map(|team| {
+        let names: Vec<&str> = agents
+            .iter()
+            .skip(team)
+            .step_by(2)
+            .map(|agent| agent.name.as_str())
+            .collect();
+        TeamStats {
+            name: names.join(" + "),
+            ..TeamStats::default()
+        }
+    })
+}
+
+fn play_deal(agents: &[AgentFactory], rotation: usize, cards: [Vec<Card>; 4]) -> Result<PlayedGame, GameFailure> {
+    /*
+        Plays one game with the agents moved by rotation seats.
+     */
+
+    // seat rotation: the agent at a place changes with every rotation
+    let agent_at_place = |place: usize| (place + rotation) % agents.len();
+    let player_names: [String; 4] = [0, 1, 2, 3].map(|place| {
+        format!("{} (seat {})", agents[agent_at_place(place)].name, place)
+    });
+    let mut players: Vec<Box<dyn MarjapussiCheater>> = (0..4)
+        .map(|place| agents[agent_at_place(place)].build(&player_names[place], place as u8))
+        .collect();
+
+    play_game(&mut players, player_names, Some(cards), LogFilter::off(), &mut [])
+}
+
+fn add_game(agents: &[AgentFactory], rotation: usize, played_game: &PlayedGame, teams: &mut [TeamStats; 2]) -> [i32; 2] {
+    /*
+        Adds a game played by play_deal with the same rotation to the team statistics.
+        Returns the score of each team.
+     */
+
+    let team_at_place = |place: usize| ((place + rotation) % agents.len()) % 2;
+    let result = GameResult::from_game(&played_game.game);
+
+    // collect the statistics per party and per seat
+    let mut scores = [0; 2];
+    for party in 0..2 {
+        let team_index = team_at_place(party);
+        let team = &mut teams[team_index];
+        let score = result.score(party as u8);
+        scores[team_index] = score;
+        team.scores.push(score);
+        if result.playing_party == Some(party as u8) {
+            team.contracts_bid += 1;
+            if result.won() == Some(true) {
+                team.contracts_made += 1;
+            }
+        }
+        if result.party_tricks[party] == 9 {
+            team.schwarz_won += 1;
+        } else if result.party_tricks[party] == 0 {
+            team.schwarz_lost += 1;
+        }
+    }
+    for place in 0..4 {
+        let team = &mut teams[team_at_place(place)];
+        team.decisions += played_game.decisions[place];
+        team.decision_time += played_game.decision_times[place];
+    }
+
+    scores
+}
+
+
+fn mean(values: &[i32]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().map(|value| f64::from(*value)).sum::<f64>() / values.len() as f64
+}
+
+fn confidence_interval(values: &[i32]) -> f64 {
+    if values.len() < 2 {
+        return f64::INFINITY;
+    }
+    let mean = mean(values);
+    let variance = values
+        .iter()
+        .map(|value| (f64::from(*value) - mean).powi(2))
+        .sum::<f64>() / (values.len() - 1) as f64;
+    1.96 * (variance / values.len() as f64).sqrt()
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    /*
+        Standard normal distribution function, using the erf approximation 7.1.26 of Abramowitz and Stegun
+        (absolute error below 1.5e-7).
+     */
+
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.327_591_1 * z);
+    let polynomial = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let erf = 1.0 - polynomial * (-z * z).exp();
+    if x >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
+
+fn percentage(count: u32, total: u32) -> String {
+    if total == 0 {
+        return String::from("-");
+    }
+    format!("{:.1}%", 100.0 * f64::from(count) / f64::from(total))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::AgentError;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_tournament() {
+        let agents = [AgentFactory::cheater(1), AgentFactory::cheater(2)];
+        let result = run_tournament(&agents, 4, 0);
+
+        assert_eq!(result.teams[0].name, "CheaterV1 (depth 1)");
+        assert_eq!(result.teams[1].name, "CheaterV1 (depth 2)");
+        for team in &result.teams {
+            assert_eq!(team.scores.len(), 4);
+            assert!(team.contracts_made <= team.contracts_bid);
+            assert!(team.decisions > 0);
+        }
+        // every deal has at most one playing party
+        assert!(result.teams[0].contracts_bid + result.teams[1].contracts_bid <= 4);
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let agents = [AgentFactory::cheater(1), AgentFactory::cheater(1)];
+        let result = run_duplicate(&agents, 2, 0);
+
+        assert_eq!(result.tournament.games, 4);
+        assert_eq!(result.differences.len(), 2);
+        for team in &result.tournament.teams {
+            assert_eq!(team.scores.len(), 4);
+        }
+        // the cheaters are deterministic, so two equal agents play both sides of a deal in the same way
+        assert_eq!(result.differences, vec![0, 0]);
+        assert!(result.significance().is_none());
+    }
+
+    #[test]
+    fn test_failing_games() {
+        struct FailingAgent;
+        impl MarjapussiCheater for FailingAgent {
+            fn select_action(&mut self, _game: marjapussi::game::Game) -> Result<marjapussi::game::gameevent::GameAction, AgentError> {
+                Err(AgentError::NoLegalActions)
+            }
+        }
+        // the two agents of the first game fail, all later agents are cheaters
+        let flaky = || {
+            let built = Rc::new(Cell::new(0));
+            AgentFactory::new("Flaky", move |name, place| {
+                built.set(built.get() + 1);
+                if built.get() <= 2 {
+                    Box::new(FailingAgent) as Box<dyn MarjapussiCheater>
+                } else {
+                    Box::new(CheaterV1::new(name, place, 1))
+                }
+            })
+        };
+
+        let result = run_tournament(&[AgentFactory::cheater(1), flaky()], 3, 5);
+        assert_eq!(result.games, 2);
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].seed, 5);
+        assert!(result.teams.iter().all(|team| team.scores.len() == 2));
+
+        // a failed game skips the whole deal of a duplicate tournament
+        let result = run_duplicate(&[AgentFactory::cheater(1), flaky()], 2, 5);
+        assert_eq!(result.tournament.games, 2);
+        assert_eq!(result.tournament.failed.len(), 1);
+        assert_eq!(result.seeds, vec![6]);
+        assert_eq!(result.differences.len(), 1);
+    }
+
+    #[test]
+    fn test_normal_cdf() {
+        assert!((normal_cdf(0.0) - 0.5).abs() < 1e-7);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-4);
+        assert!((normal_cdf(-1.0) - 0.158_655).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_confidence_interval() {
+        assert_eq!(mean(&[1, 2, 3]), 2.0);
+        assert_eq!(confidence_interval(&[5]), f64::INFINITY);
+        assert_eq!(confidence_interval(&[4, 4, 4]), 0.0);
+        // sample variance 4/3, four values
+        assert!((confidence_interval(&[1, 3, 1, 3]) - 1.96 * (4.0_f64 / 3.0).sqrt() / 2.0).abs() < 1e-9);
+    }
+}